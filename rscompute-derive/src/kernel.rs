@@ -0,0 +1,1104 @@
+//! `#[compute_kernel]`: a Rust function signature and body translated to a GLSL compute
+//! shader, with a struct that implements `rscompute::ComputeShader` for it.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::{BinOp, Block, Error, Expr, FnArg, Ident, ItemFn, Lit, Pat, RangeLimits, ReturnType, Stmt, Type, UnOp};
+
+/// Words GLSL reserves or uses for types and functions, and the names the generated source
+/// declares itself. Parameters and locals with these names get a `_` appended.
+const GLSL_RESERVED: &[&str] = &[
+    "active", "asm", "atomic_uint", "attribute", "bool", "break", "buffer", "bvec2", "bvec3", "bvec4", "case",
+    "cast", "centroid", "class", "coherent", "common", "const", "continue", "default", "discard", "do", "double",
+    "dvec2", "dvec3", "dvec4", "else", "enum", "extern", "external", "false", "filter", "fixed", "flat", "float",
+    "for", "fvec2", "fvec3", "fvec4", "goto", "half", "highp", "hvec2", "hvec3", "hvec4", "if", "in", "inline",
+    "inout", "input", "int", "interface", "invariant", "isampler2D", "ivec2", "ivec3", "ivec4", "layout",
+    "long", "lowp", "main", "mat2", "mat3", "mat4", "mediump", "namespace", "noinline", "noperspective", "out",
+    "output", "partition", "patch", "pc", "precise", "precision", "public", "readonly", "resource", "restrict",
+    "return", "sample", "sampler2D", "shared", "short", "sizeof", "smooth", "static", "struct", "subroutine",
+    "superp", "switch", "template", "this", "true", "typedef", "uint", "uniform", "union", "unsigned", "using",
+    "uvec2", "uvec3", "uvec4", "varying", "vec2", "vec3", "vec4", "void", "volatile", "while", "writeonly",
+    // Functions a local could hide
+    "abs", "ceil", "clamp", "cos", "exp", "exp2", "floor", "fma", "length", "log", "log2", "max", "min", "mod",
+    "pow", "round", "sin", "sqrt", "tan", "tanh",
+];
+
+/// The built-in variables a kernel body can use without declaring them.
+const BUILTINS: &[(&str, &str, Ty)] = &[
+    ("global_id", "gl_GlobalInvocationID", Ty::UVec3),
+    ("local_id", "gl_LocalInvocationID", Ty::UVec3),
+    ("workgroup_id", "gl_WorkGroupID", Ty::UVec3),
+    ("num_workgroups", "gl_NumWorkGroups", Ty::UVec3),
+    ("local_index", "gl_LocalInvocationIndex", Ty::Uint),
+];
+
+/// Rust functions and `f32` methods a body can call, the GLSL function they become, how
+/// many arguments they take including the receiver, and whether they only take floats.
+const FUNCTIONS: &[(&str, &str, usize, bool)] = &[
+    ("abs", "abs", 1, false),
+    ("min", "min", 2, false),
+    ("max", "max", 2, false),
+    ("clamp", "clamp", 3, false),
+    ("sqrt", "sqrt", 1, true),
+    ("floor", "floor", 1, true),
+    ("ceil", "ceil", 1, true),
+    ("round", "round", 1, true),
+    ("exp", "exp", 1, true),
+    ("exp2", "exp2", 1, true),
+    ("ln", "log", 1, true),
+    ("log2", "log2", 1, true),
+    ("sin", "sin", 1, true),
+    ("cos", "cos", 1, true),
+    ("tan", "tan", 1, true),
+    ("tanh", "tanh", 1, true),
+    ("powf", "pow", 2, true),
+    ("mul_add", "fma", 3, true),
+];
+
+/// The field of the generated struct holding the dispatch size.
+const INVOCATIONS: &str = "invocations";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Ty {
+    Float,
+    Int,
+    Uint,
+    Bool,
+    UVec3,
+    /// An integer literal without a suffix, which takes the type of the integer it meets
+    IntLiteral,
+    /// The type of a local initialized with an integer literal, inferred from how it is used.
+    /// The index is into [`Translator::vars`].
+    Var(usize),
+}
+
+impl Ty {
+    fn glsl(self) -> &'static str {
+        match self {
+            Ty::Float => "float",
+            Ty::Int => "int",
+            Ty::Uint => "uint",
+            Ty::Bool => "bool",
+            Ty::UVec3 => "uvec3",
+            // As in Rust, integers nothing gives a type to are `i32`
+            Ty::IntLiteral | Ty::Var(_) => "int",
+        }
+    }
+
+    fn rust(self) -> &'static str {
+        match self {
+            Ty::Float => "f32",
+            Ty::Int => "i32",
+            Ty::Uint => "u32",
+            Ty::Bool => "bool",
+            Ty::UVec3 => "a vector",
+            Ty::IntLiteral | Ty::Var(_) => "{integer}",
+        }
+    }
+
+    fn is_numeric(self) -> bool {
+        self == Ty::Float || self.is_integer()
+    }
+
+    fn is_integer(self) -> bool {
+        matches!(self, Ty::Int | Ty::Uint | Ty::IntLiteral | Ty::Var(_))
+    }
+}
+
+/// `f32`, `i32` or `u32`, and `bool` when `allow_bool`.
+fn scalar_type(ty: &Type, allow_bool: bool) -> Option<Ty> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    match path.path.get_ident()?.to_string().as_str() {
+        "f32" => Some(Ty::Float),
+        "i32" => Some(Ty::Int),
+        "u32" => Some(Ty::Uint),
+        "bool" if allow_bool => Some(Ty::Bool),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ParamKind {
+    /// `&[T]`, written before the dispatch
+    Input,
+    /// `&mut [T]`, read back after it. Its elements can only be assigned to, since the host's
+    /// values aren't uploaded.
+    Output,
+    /// A scalar, a member of the push constant block
+    PushConstant,
+}
+
+struct Param {
+    ident: Ident,
+    glsl: String,
+    kind: ParamKind,
+    ty: Ty,
+    rust_ty: Type,
+}
+
+fn glsl_name(ident: &Ident) -> syn::Result<String> {
+    let name = ident.to_string();
+    if name.contains("__") || name.starts_with("gl_") {
+        return Err(Error::new(ident.span(), format!("`{}` is reserved in GLSL, rename it", name)));
+    }
+    Ok(if GLSL_RESERVED.contains(&name.as_str()) { format!("{}_", name) } else { name })
+}
+
+fn parse_param(arg: &FnArg) -> syn::Result<Param> {
+    let FnArg::Typed(arg) = arg else {
+        return Err(Error::new(arg.span(), "a kernel cannot take `self`"));
+    };
+    let Pat::Ident(pat) = &*arg.pat else {
+        return Err(Error::new(arg.pat.span(), "kernel parameters must be plain names"));
+    };
+    if pat.ident == INVOCATIONS {
+        return Err(Error::new(pat.ident.span(), "`invocations` is the field the dispatch size is set with, rename the parameter"));
+    }
+    let element = |ty: &Type| scalar_type(ty, false)
+        .ok_or_else(|| Error::new(ty.span(), "buffer elements must be `f32`, `i32` or `u32`"));
+    let (kind, ty, rust_ty) = match &*arg.ty {
+        Type::Reference(reference) => {
+            let Type::Slice(slice) = &*reference.elem else {
+                return Err(Error::new(reference.elem.span(), "buffers are slices, `&[T]` for inputs and `&mut [T]` for outputs"));
+            };
+            let kind = if reference.mutability.is_some() { ParamKind::Output } else { ParamKind::Input };
+            (kind, element(&slice.elem)?, (*slice.elem).clone())
+        }
+        ty => match scalar_type(ty, false) {
+            Some(scalar) => (ParamKind::PushConstant, scalar, ty.clone()),
+            None => return Err(Error::new(ty.span(),
+                "kernel parameters are buffers, `&[T]` or `&mut [T]`, or push constants of type `f32`, `i32` or `u32`")),
+        },
+    };
+    Ok(Param { glsl: glsl_name(&pat.ident)?, ident: pat.ident.clone(), kind, ty, rust_ty })
+}
+
+/// Parses `workgroup_size = [x, y, z]`, where `y` and `z` default to 1.
+fn parse_workgroup_size(attr: TokenStream2) -> syn::Result<[u32; 3]> {
+    let mut workgroup_size = None;
+    let parser = syn::meta::parser(|meta| {
+        if !meta.path.is_ident("workgroup_size") {
+            return Err(meta.error("expected `workgroup_size`"));
+        }
+        let array: syn::ExprArray = meta.value()?.parse()?;
+        if array.elems.is_empty() || array.elems.len() > 3 {
+            return Err(Error::new(array.span(), "the workgroup size has one to three dimensions"));
+        }
+        let mut size = [1; 3];
+        for (dimension, elem) in size.iter_mut().zip(&array.elems) {
+            let Expr::Lit(syn::ExprLit { lit: Lit::Int(lit), .. }) = elem else {
+                return Err(Error::new(elem.span(), "workgroup sizes must be integer literals"));
+            };
+            *dimension = lit.base10_parse::<u32>()?;
+            if *dimension == 0 {
+                return Err(Error::new(lit.span(), "workgroup sizes must be at least 1"));
+            }
+        }
+        workgroup_size = Some(size);
+        Ok(())
+    });
+    syn::parse::Parser::parse2(parser, attr)?;
+    workgroup_size.ok_or_else(|| Error::new(Span::call_site(),
+        "compute_kernel needs a workgroup size, such as #[compute_kernel(workgroup_size = [64])]"))
+}
+
+pub(crate) fn expand(attr: TokenStream2, function: &ItemFn) -> syn::Result<TokenStream2> {
+    let workgroup_size = parse_workgroup_size(attr)?;
+    let signature = &function.sig;
+    if !signature.generics.params.is_empty() {
+        return Err(Error::new(signature.generics.span(), "kernels cannot be generic"));
+    }
+    if let Some(asyncness) = &signature.asyncness {
+        return Err(Error::new(asyncness.span(), "kernels cannot be async"));
+    }
+    if let ReturnType::Type(_, ty) = &signature.output {
+        return Err(Error::new(ty.span(), "kernels return nothing, they write their results to `&mut [T]` buffers"));
+    }
+    let params = signature.inputs.iter().map(parse_param).collect::<syn::Result<Vec<Param>>>()?;
+    let glsl = glsl_source(&params, workgroup_size, &function.block)?;
+
+    let vis = &function.vis;
+    let name = format_ident!("{}", upper_camel_case(&signature.ident.to_string()), span = signature.ident.span());
+    let push_constants_name = format_ident!("{}PushConstants", name);
+    let docs = function.attrs.iter().filter(|attr| attr.path().is_ident("doc"));
+    let file_name = format!("{}.comp", signature.ident);
+    let [x, y, z] = workgroup_size;
+
+    let buffers = params.iter().filter(|param| param.kind != ParamKind::PushConstant).collect::<Vec<&Param>>();
+    let push_constants = params.iter().filter(|param| param.kind == ParamKind::PushConstant).collect::<Vec<&Param>>();
+    let fields = params.iter().map(|param| {
+        let ident = &param.ident;
+        let ty = &param.rust_ty;
+        match param.kind {
+            ParamKind::PushConstant => quote!(pub #ident: #ty),
+            _ => quote!(pub #ident: ::std::vec::Vec<#ty>),
+        }
+    });
+
+    // Bindings are numbered in parameter order, and inputs and outputs each in theirs
+    let layout_descriptions = buffers.iter().enumerate().map(|(binding, param)| {
+        let binding = binding as u32;
+        let ident = &param.ident;
+        let index = buffers[..binding as usize].iter().filter(|other| other.kind == param.kind).count();
+        let index = if param.kind == ParamKind::Input {
+            quote!(::rscompute::LayoutDescriptorIndex::WriteIndex(#index))
+        } else {
+            quote!(::rscompute::LayoutDescriptorIndex::ReadIndex(#index))
+        };
+        quote! {
+            ::rscompute::LayoutDescription {
+                set: 0,
+                binding: #binding,
+                buffer_size: ::rscompute::GpuMappedMemory::buffer_size(&self.#ident),
+                index: #index,
+                placement: ::rscompute::MemoryPlacement::Auto,
+                resource: ::rscompute::BindingResource::Buffer,
+            }
+        }
+    }).collect::<Vec<TokenStream2>>();
+    let writables = buffers.iter().filter(|param| param.kind == ParamKind::Input).map(|param| &param.ident);
+    let readables = buffers.iter().filter(|param| param.kind == ParamKind::Output).map(|param| &param.ident);
+
+    let (push_constants_type, push_constants_struct, get_push_constants) = if push_constants.is_empty() {
+        (quote!(()), None, None)
+    } else {
+        let idents = push_constants.iter().map(|param| &param.ident).collect::<Vec<&Ident>>();
+        let types = push_constants.iter().map(|param| &param.rust_ty);
+        let doc = format!("The push constants of [`{}`].", name);
+        let definition: syn::DeriveInput = syn::parse_quote! {
+            #[doc = #doc]
+            #[derive(Clone, Copy, Debug)]
+            #vis struct #push_constants_name {
+                #(pub #idents: #types),*
+            }
+        };
+        let shader_layout = crate::expand_shader_layout(&definition)?;
+        (
+            quote!(#push_constants_name),
+            Some(quote!(#definition #shader_layout)),
+            Some(quote! {
+                fn get_push_constants(&self) -> ::std::option::Option<#push_constants_name> {
+                    ::std::option::Option::Some(#push_constants_name { #(#idents: self.#idents),* })
+                }
+            }),
+        )
+    };
+
+    Ok(quote! {
+        #(#docs)*
+        #vis struct #name {
+            #(#fields,)*
+            /// Invocations to dispatch in each dimension, rounded up to whole workgroups
+            pub invocations: [u32; 3],
+        }
+
+        #push_constants_struct
+
+        impl #name {
+            /// The GLSL the kernel was translated to.
+            pub const SOURCE: &'static str = #glsl;
+            pub const WORKGROUP_SIZE: [u32; 3] = [#x, #y, #z];
+        }
+
+        impl ::rscompute::ComputeShader<#push_constants_type> for #name {
+            fn get_source(&self) -> ::rscompute::Result<::rscompute::ShaderSource> {
+                ::std::result::Result::Ok(::rscompute::ShaderSource::glsl(Self::SOURCE, #file_name))
+            }
+
+            fn get_layout_descriptors(&self) -> ::std::vec::Vec<::rscompute::LayoutDescription> {
+                ::std::vec![#(#layout_descriptions),*]
+            }
+
+            fn get_writables(&self) -> ::std::vec::Vec<&dyn ::rscompute::GpuMappedMemory> {
+                ::std::vec![#(&self.#writables as &dyn ::rscompute::GpuMappedMemory),*]
+            }
+
+            fn get_readables(&mut self) -> ::std::vec::Vec<&mut dyn ::rscompute::GpuMappedMemory> {
+                ::std::vec![#(&mut self.#readables as &mut dyn ::rscompute::GpuMappedMemory),*]
+            }
+
+            #get_push_constants
+
+            fn get_group_vec(&self) -> (u32, u32, u32) {
+                (self.invocations[0].div_ceil(#x), self.invocations[1].div_ceil(#y), self.invocations[2].div_ceil(#z))
+            }
+        }
+    })
+}
+
+fn upper_camel_case(name: &str) -> String {
+    name.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map_or_else(String::new, |first| first.to_uppercase().chain(chars).collect())
+        })
+        .collect()
+}
+
+/// The whole GLSL shader: its declarations, then the body as `main`.
+fn glsl_source(params: &[Param], [x, y, z]: [u32; 3], body: &Block) -> syn::Result<String> {
+    let mut glsl = String::from("#version 450\n");
+    let _ = writeln!(glsl, "layout(local_size_x = {}, local_size_y = {}, local_size_z = {}) in;", x, y, z);
+    for (binding, param) in params.iter().filter(|param| param.kind != ParamKind::PushConstant).enumerate() {
+        let qualifier = if param.kind == ParamKind::Input { "readonly " } else { "writeonly " };
+        let _ = writeln!(glsl, "layout(set = 0, binding = {}) {}buffer Binding{} {{ {} {}[]; }};",
+            binding, qualifier, binding, param.ty.glsl(), param.glsl);
+    }
+    let push_constants = params.iter().filter(|param| param.kind == ParamKind::PushConstant).collect::<Vec<&Param>>();
+    if !push_constants.is_empty() {
+        glsl.push_str("layout(push_constant) uniform PushConstants {\n");
+        for param in &push_constants {
+            let _ = writeln!(glsl, "    {} {};", param.ty.glsl(), param.glsl);
+        }
+        glsl.push_str("} pc;\n");
+    }
+    glsl.push_str("\nvoid main() {\n");
+    let names = params.iter().map(|param| param.glsl.clone())
+        .chain((0..params.len()).map(|binding| format!("Binding{}", binding)))
+        .chain(["PushConstants".to_string()])
+        .collect();
+    let mut translator = Translator { params, scopes: Vec::new(), names, vars: RefCell::new(Vec::new()), glsl, depth: 1 };
+    translator.block(body)?;
+    translator.glsl.push_str("}\n");
+    // Locals are declared before their uses give them a type, so it is filled in last
+    let mut glsl = translator.glsl.clone();
+    for var in 0..translator.vars.borrow().len() {
+        glsl = glsl.replace(&var_placeholder(var), translator.resolve(Ty::Var(var)).glsl());
+    }
+    Ok(glsl)
+}
+
+/// Stands in for the GLSL type of a local until its type is inferred.
+fn var_placeholder(var: usize) -> String {
+    format!("\u{1}{}\u{1}", var)
+}
+
+struct Local {
+    glsl: String,
+    ty: Ty,
+    mutable: bool,
+}
+
+/// Translates the restricted subset of Rust a kernel body is written in to GLSL statements,
+/// checking types as it goes so mistakes are reported on the Rust code.
+struct Translator<'a> {
+    params: &'a [Param],
+    scopes: Vec<HashMap<String, Local>>,
+    /// Every name the GLSL declares. GLSL can't redeclare a name in a scope the way `let`
+    /// can shadow it, so a local whose name is taken is renamed to one that isn't.
+    names: HashSet<String>,
+    /// The types inferred for [`Ty::Var`]s so far, `None` until one is used with a typed integer
+    vars: RefCell<Vec<Option<Ty>>>,
+    glsl: String,
+    depth: usize,
+}
+
+impl Translator<'_> {
+    fn line(&mut self, line: &str) {
+        self.glsl.push_str(&"    ".repeat(self.depth));
+        self.glsl.push_str(line);
+        self.glsl.push('\n');
+    }
+
+    fn block(&mut self, block: &Block) -> syn::Result<()> {
+        self.scopes.push(HashMap::new());
+        for stmt in &block.stmts {
+            self.stmt(stmt)?;
+        }
+        self.scopes.pop();
+        Ok(())
+    }
+
+    /// A nested block, with its own scope, between `{` and `}` lines.
+    fn nested(&mut self, block: &Block) -> syn::Result<()> {
+        self.depth += 1;
+        self.block(block)?;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    fn declare(&mut self, ident: &Ident, ty: Ty, mutable: bool) -> syn::Result<String> {
+        let glsl = self.unused_name(&glsl_name(ident)?);
+        self.scopes.last_mut().expect("declared outside a block")
+            .insert(ident.to_string(), Local { glsl: glsl.clone(), ty, mutable });
+        Ok(glsl)
+    }
+
+    /// `name`, or `name_2`, `name_3` and so on if it is taken, which is then taken too.
+    fn unused_name(&mut self, name: &str) -> String {
+        // A `_` appended to a reserved word is dropped so the result can't contain `__`
+        let base = name.trim_end_matches('_');
+        let name = std::iter::once(name.to_string())
+            .chain((2..).map(|count| format!("{}_{}", base, count)))
+            .find(|candidate| !self.names.contains(candidate))
+            .expect("an unused name");
+        self.names.insert(name.clone());
+        name
+    }
+
+    fn new_var(&self) -> Ty {
+        let mut vars = self.vars.borrow_mut();
+        vars.push(None);
+        Ty::Var(vars.len() - 1)
+    }
+
+    /// What `ty` stands for, following a [`Ty::Var`] to the type inferred for it.
+    fn resolve(&self, ty: Ty) -> Ty {
+        match ty {
+            Ty::Var(var) => self.vars.borrow()[var].map_or(ty, |inferred| self.resolve(inferred)),
+            ty => ty,
+        }
+    }
+
+    /// The type two values have when they must have the same one, as the operands of most
+    /// operators and the two sides of an assignment do in Rust. An untyped integer takes the
+    /// type of the other, and a local's inferred type is set by the first one it meets.
+    fn unify(&self, a: Ty, b: Ty) -> Option<Ty> {
+        let (a, b) = (self.resolve(a), self.resolve(b));
+        match (a, b) {
+            _ if a == b => Some(a),
+            (Ty::IntLiteral, other) | (other, Ty::IntLiteral) if other.is_integer() => Some(other),
+            (Ty::Var(var), other) | (other, Ty::Var(var)) if other.is_integer() => {
+                self.vars.borrow_mut()[var] = Some(other);
+                Some(other)
+            }
+            _ => None,
+        }
+    }
+
+    /// `glsl`, an expression of type `from`, as one of type `to` once the two are unified. An
+    /// untyped integer is written as an `int`, which GLSL doesn't convert to a `uint` when
+    /// choosing a function's overload, so it is made a `uint` itself.
+    fn convert(&self, glsl: String, from: Ty, to: Ty) -> String {
+        if from != Ty::IntLiteral || self.resolve(to) != Ty::Uint {
+            glsl
+        } else if glsl.bytes().all(|byte| byte.is_ascii_digit()) {
+            format!("{}u", glsl)
+        } else {
+            format!("uint({})", glsl)
+        }
+    }
+
+    /// The GLSL type to declare a local of type `ty` with.
+    fn type_name(&self, ty: Ty) -> String {
+        match ty {
+            Ty::Var(var) => var_placeholder(var),
+            ty => ty.glsl().to_string(),
+        }
+    }
+
+    fn local(&self, ident: &Ident) -> Option<&Local> {
+        let name = ident.to_string();
+        self.scopes.iter().rev().find_map(|scope| scope.get(&name))
+    }
+
+    fn param(&self, ident: &Ident) -> Option<&Param> {
+        self.params.iter().find(|param| param.ident == *ident)
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> syn::Result<()> {
+        match stmt {
+            Stmt::Local(local) => {
+                let (pat, annotation) = match &local.pat {
+                    Pat::Type(typed) => (&*typed.pat, Some(&*typed.ty)),
+                    pat => (pat, None),
+                };
+                let Pat::Ident(pat) = pat else {
+                    return Err(Error::new(pat.span(), "`let` in a kernel binds a single name"));
+                };
+                let Some(init) = local.init.as_ref().filter(|init| init.diverge.is_none()) else {
+                    return Err(Error::new(local.span(), "`let` in a kernel needs a value"));
+                };
+                let (value, value_ty) = self.expr(&init.expr)?;
+                let ty = match annotation {
+                    Some(annotation) => {
+                        let ty = scalar_type(annotation, true)
+                            .ok_or_else(|| Error::new(annotation.span(), "locals are `f32`, `i32`, `u32` or `bool`"))?;
+                        if self.unify(ty, value_ty).is_none() {
+                            return Err(Error::new(init.expr.span(), format!("expected {}, found {}", ty.rust(), value_ty.rust())));
+                        }
+                        ty
+                    }
+                    None if value_ty == Ty::IntLiteral => self.new_var(),
+                    None => value_ty,
+                };
+                let name = self.declare(&pat.ident, ty, pat.mutability.is_some())?;
+                self.line(&format!("{} {} = {};", self.type_name(ty), name, self.convert(value, value_ty, ty)));
+            }
+            Stmt::Expr(expr, _) => self.expr_stmt(expr)?,
+            Stmt::Item(item) => return Err(Error::new(item.span(), "items cannot be declared in a kernel")),
+            Stmt::Macro(stmt) => return Err(Error::new(stmt.span(), "macros cannot be used in a kernel")),
+        }
+        Ok(())
+    }
+
+    fn expr_stmt(&mut self, expr: &Expr) -> syn::Result<()> {
+        match expr {
+            Expr::Assign(assign) => {
+                let (target, target_ty) = self.place(&assign.left, false)?;
+                let (value, value_ty) = self.expr(&assign.right)?;
+                if self.unify(target_ty, value_ty).is_none() {
+                    return Err(Error::new(assign.right.span(), format!("expected {}, found {}", target_ty.rust(), value_ty.rust())));
+                }
+                self.line(&format!("{} = {};", target, self.convert(value, value_ty, target_ty)));
+            }
+            Expr::Binary(binary) if compound_assignment(&binary.op).is_some() => {
+                let (target, target_ty) = self.place(&binary.left, true)?;
+                let (value, value_ty) = self.expr(&binary.right)?;
+                let op = compound_assignment(&binary.op).unwrap();
+                let ty = self.arithmetic_type(&binary.op, op, target_ty, value_ty, expr)?;
+                self.line(&format!("{} {}= {};", target, op, self.convert(value, value_ty, ty)));
+            }
+            Expr::If(if_expr) => {
+                let mut keyword = "if";
+                let mut if_expr = if_expr;
+                loop {
+                    let condition = self.condition(&if_expr.cond)?;
+                    self.line(&format!("{} ({}) {{", keyword, condition));
+                    self.nested(&if_expr.then_branch)?;
+                    match if_expr.else_branch.as_ref().map(|(_, branch)| &**branch) {
+                        Some(Expr::If(else_if)) => {
+                            keyword = "} else if";
+                            if_expr = else_if;
+                        }
+                        Some(Expr::Block(block)) => {
+                            self.line("} else {");
+                            self.nested(&block.block)?;
+                            break;
+                        }
+                        Some(other) => return Err(Error::new(other.span(), "expected a block")),
+                        None => break,
+                    }
+                }
+                self.line("}");
+            }
+            Expr::ForLoop(for_loop) => {
+                let Pat::Ident(pat) = &*for_loop.pat else {
+                    return Err(Error::new(for_loop.pat.span(), "`for` in a kernel binds a single name"));
+                };
+                let Expr::Range(range) = &*for_loop.expr else {
+                    return Err(Error::new(for_loop.expr.span(), "kernels can only loop over ranges such as `0..n`"));
+                };
+                let (Some(start), Some(end)) = (&range.start, &range.end) else {
+                    return Err(Error::new(range.span(), "loop ranges need a start and an end"));
+                };
+                let (start, start_ty) = self.expr(start)?;
+                let (end, end_ty) = self.expr(end)?;
+                if !start_ty.is_integer() || !end_ty.is_integer() {
+                    return Err(Error::new(range.span(), "loop ranges are over integers"));
+                }
+                let ty = match self.unify(start_ty, end_ty) {
+                    Some(Ty::IntLiteral) => self.new_var(),
+                    Some(ty) => ty,
+                    None => return Err(Error::new(range.span(),
+                        format!("the range starts at {} and ends at {}, convert one with `as`", start_ty.rust(), end_ty.rust()))),
+                };
+                let comparison = if matches!(range.limits, RangeLimits::Closed(_)) { "<=" } else { "<" };
+                self.scopes.push(HashMap::new());
+                let name = self.declare(&pat.ident, ty, false)?;
+                // Rust evaluates the end once, before the first iteration
+                let end_name = self.unused_name(&format!("{}_end", name.trim_end_matches('_')));
+                let (start, end) = (self.convert(start, start_ty, ty), self.convert(end, end_ty, ty));
+                self.line(&format!("for ({} {} = {}, {} = {}; {} {} {}; {}++) {{",
+                    self.type_name(ty), name, start, end_name, end, name, comparison, end_name, name));
+                self.nested(&for_loop.body)?;
+                self.line("}");
+                self.scopes.pop();
+            }
+            Expr::While(while_loop) => {
+                let condition = self.condition(&while_loop.cond)?;
+                self.line(&format!("while ({}) {{", condition));
+                self.nested(&while_loop.body)?;
+                self.line("}");
+            }
+            Expr::Block(block) if block.label.is_none() => {
+                self.line("{");
+                self.nested(&block.block)?;
+                self.line("}");
+            }
+            Expr::Return(ret) => match &ret.expr {
+                None => self.line("return;"),
+                Some(value) => return Err(Error::new(value.span(), "kernels return nothing")),
+            },
+            Expr::Break(brk) if brk.label.is_none() && brk.expr.is_none() => self.line("break;"),
+            Expr::Continue(cont) if cont.label.is_none() => self.line("continue;"),
+            other => return Err(Error::new(other.span(),
+                "kernel statements are `let`, assignments, `if`, `for` over a range, `while`, `break`, `continue` and `return`")),
+        }
+        Ok(())
+    }
+
+    fn condition(&self, expr: &Expr) -> syn::Result<String> {
+        let (condition, ty) = self.expr(expr)?;
+        if ty != Ty::Bool {
+            return Err(Error::new(expr.span(), format!("expected bool, found {}", ty.rust())));
+        }
+        Ok(condition)
+    }
+
+    /// Something that can be assigned to: a mutable local or an element of an output buffer.
+    /// `reads` is set for compound assignments, which read the old value first.
+    fn place(&self, expr: &Expr, reads: bool) -> syn::Result<(String, Ty)> {
+        match expr {
+            Expr::Path(path) => {
+                let ident = path.path.get_ident().ok_or_else(|| Error::new(path.span(), "expected a local"))?;
+                match self.local(ident) {
+                    Some(local) if local.mutable => Ok((local.glsl.clone(), local.ty)),
+                    Some(_) => Err(Error::new(ident.span(), format!("`{}` is not `mut`", ident))),
+                    None if self.param(ident).is_some() => Err(Error::new(ident.span(), "parameters cannot be assigned to")),
+                    None => Err(Error::new(ident.span(), format!("cannot find `{}`", ident))),
+                }
+            }
+            Expr::Index(index) => {
+                let (param, element) = self.element(index)?;
+                match param.kind {
+                    ParamKind::Input => Err(Error::new(index.expr.span(),
+                        format!("`{}` is an input, `&[{}]`, make it `&mut [{}]` to write to it", param.ident, param.ty.rust(), param.ty.rust()))),
+                    _ if reads => Err(output_read(param, index)),
+                    _ => Ok((element, param.ty)),
+                }
+            }
+            Expr::Paren(paren) => self.place(&paren.expr, reads),
+            other => Err(Error::new(other.span(), "only `mut` locals and elements of `&mut [T]` buffers can be assigned to")),
+        }
+    }
+
+    /// The buffer parameter `index` indexes and the GLSL of the element.
+    fn element(&self, index: &syn::ExprIndex) -> syn::Result<(&Param, String)> {
+        let param = match &*index.expr {
+            Expr::Path(path) => path.path.get_ident().and_then(|ident| self.param(ident)),
+            _ => None,
+        }.filter(|param| param.kind != ParamKind::PushConstant)
+            .ok_or_else(|| Error::new(index.expr.span(), "only buffer parameters can be indexed"))?;
+        let (position, position_ty) = self.expr(&index.index)?;
+        if !position_ty.is_integer() {
+            return Err(Error::new(index.index.span(), format!("buffers are indexed with integers, found {}", position_ty.rust())));
+        }
+        // Rust indexes with `usize`, which is `u32` in a kernel
+        if matches!(position_ty, Ty::Var(_)) {
+            self.unify(position_ty, Ty::Uint);
+        }
+        Ok((param, format!("{}[{}]", param.glsl, position)))
+    }
+
+    /// An operand of a binary expression, in parentheses when it is itself one so GLSL's
+    /// precedence, which differs from Rust's for bit operators, doesn't matter.
+    fn operand(&self, expr: &Expr) -> syn::Result<(String, Ty)> {
+        let expr = strip_parens(expr);
+        let (glsl, ty) = self.expr(expr)?;
+        Ok(if matches!(expr, Expr::Binary(_)) { (format!("({})", glsl), ty) } else { (glsl, ty) })
+    }
+
+    /// The type of an arithmetic or bit operation, or of the value a compound assignment stores.
+    fn arithmetic_type(&self, op: &BinOp, glsl_op: &str, left: Ty, right: Ty, expr: &Expr) -> syn::Result<Ty> {
+        let integer_only = matches!(op,
+            BinOp::Rem(_) | BinOp::RemAssign(_) | BinOp::BitAnd(_) | BinOp::BitAndAssign(_) | BinOp::BitOr(_)
+            | BinOp::BitOrAssign(_) | BinOp::BitXor(_) | BinOp::BitXorAssign(_) | BinOp::Shl(_) | BinOp::ShlAssign(_)
+            | BinOp::Shr(_) | BinOp::ShrAssign(_));
+        let valid = if integer_only { left.is_integer() && right.is_integer() } else { left.is_numeric() && right.is_numeric() };
+        if !valid {
+            return Err(Error::new(expr.span(), format!("`{}` cannot be applied to {} and {}{}", glsl_op, left.rust(), right.rust(),
+                if integer_only { ", only to integers" } else { "" })));
+        }
+        // A shift amount can be any integer, everything else is the same type on both sides
+        if matches!(op, BinOp::Shl(_) | BinOp::ShlAssign(_) | BinOp::Shr(_) | BinOp::ShrAssign(_)) {
+            return Ok(left);
+        }
+        self.unify(left, right).ok_or_else(|| Error::new(expr.span(),
+            format!("`{}` cannot be applied to {} and {}, convert one with `as`", glsl_op, left.rust(), right.rust())))
+    }
+
+    fn expr(&self, expr: &Expr) -> syn::Result<(String, Ty)> {
+        match expr {
+            Expr::Lit(lit) => literal(&lit.lit),
+            Expr::Paren(paren) => self.expr(&paren.expr),
+            Expr::Path(path) => {
+                let ident = path.path.get_ident().ok_or_else(|| Error::new(path.span(), "paths cannot be used in a kernel"))?;
+                if let Some(local) = self.local(ident) {
+                    return Ok((local.glsl.clone(), self.resolve(local.ty)));
+                }
+                if let Some(param) = self.param(ident) {
+                    return match param.kind {
+                        ParamKind::PushConstant => Ok((format!("pc.{}", param.glsl), param.ty)),
+                        _ => Err(Error::new(ident.span(), format!("`{}` is a buffer, index it or call `{}.len()`", ident, ident))),
+                    };
+                }
+                BUILTINS.iter()
+                    .find(|(name, _, _)| ident == name)
+                    .map(|&(_, glsl, ty)| (glsl.to_string(), ty))
+                    .ok_or_else(|| Error::new(ident.span(), format!("cannot find `{}`, kernels can use their parameters, locals and {}",
+                        ident, BUILTINS.iter().map(|(name, _, _)| *name).collect::<Vec<&str>>().join(", "))))
+            }
+            Expr::Field(field) => {
+                let (base, ty) = self.expr(&field.base)?;
+                let component = match &field.member {
+                    syn::Member::Named(ident) if ["x", "y", "z"].contains(&ident.to_string().as_str()) => ident.to_string(),
+                    member => return Err(Error::new(member.span(), "vectors have the fields `x`, `y` and `z`")),
+                };
+                if ty != Ty::UVec3 {
+                    return Err(Error::new(field.base.span(), format!("{} has no fields", ty.rust())));
+                }
+                Ok((format!("{}.{}", base, component), Ty::Uint))
+            }
+            Expr::Index(index) => {
+                let (param, element) = self.element(index)?;
+                if param.kind == ParamKind::Output {
+                    return Err(output_read(param, index));
+                }
+                Ok((element, param.ty))
+            }
+            Expr::Binary(binary) => {
+                let (mut left, left_ty) = self.operand(&binary.left)?;
+                let (mut right, right_ty) = self.operand(&binary.right)?;
+                let (op, ty) = match binary.op {
+                    BinOp::And(_) | BinOp::Or(_) => {
+                        if left_ty != Ty::Bool || right_ty != Ty::Bool {
+                            return Err(Error::new(expr.span(), format!("expected bool operands, found {} and {}", left_ty.rust(), right_ty.rust())));
+                        }
+                        (if matches!(binary.op, BinOp::And(_)) { "&&" } else { "||" }, Ty::Bool)
+                    }
+                    BinOp::Eq(_) | BinOp::Ne(_) | BinOp::Lt(_) | BinOp::Le(_) | BinOp::Gt(_) | BinOp::Ge(_) => {
+                        let equality = matches!(binary.op, BinOp::Eq(_) | BinOp::Ne(_));
+                        let operand_ty = self.unify(left_ty, right_ty).filter(|ty| ty.is_numeric() || equality)
+                            .ok_or_else(|| Error::new(expr.span(), format!("{} and {} cannot be compared", left_ty.rust(), right_ty.rust())))?;
+                        left = self.convert(left, left_ty, operand_ty);
+                        right = self.convert(right, right_ty, operand_ty);
+                        (comparison(&binary.op), Ty::Bool)
+                    }
+                    ref op => {
+                        let glsl_op = arithmetic(op).ok_or_else(|| Error::new(binary.op.span(), "assignments are statements in a kernel"))?;
+                        let ty = self.arithmetic_type(op, glsl_op, left_ty, right_ty, expr)?;
+                        if !matches!(op, BinOp::Shl(_) | BinOp::Shr(_)) {
+                            left = self.convert(left, left_ty, ty);
+                            right = self.convert(right, right_ty, ty);
+                        }
+                        (glsl_op, ty)
+                    }
+                };
+                Ok((format!("{} {} {}", left, op, right), ty))
+            }
+            Expr::Unary(unary) => {
+                let (operand, ty) = self.operand(&unary.expr)?;
+                match unary.op {
+                    UnOp::Neg(_) if ty == Ty::Float => Ok((format!("-{}", operand), ty)),
+                    // Only signed integers can be negated
+                    UnOp::Neg(_) if self.unify(ty, Ty::Int).is_some() => Ok((format!("-{}", operand), Ty::Int)),
+                    UnOp::Not(_) if ty == Ty::Bool => Ok((format!("!{}", operand), ty)),
+                    UnOp::Not(_) if ty.is_integer() => Ok((format!("~{}", operand), ty)),
+                    ref op => Err(Error::new(expr.span(), format!("`{}` cannot be applied to {}", quote!(#op), ty.rust()))),
+                }
+            }
+            Expr::Cast(cast) => {
+                let (value, value_ty) = self.expr(&cast.expr)?;
+                let is_usize = matches!(&*cast.ty, Type::Path(path) if path.path.is_ident("usize"));
+                let ty = if is_usize { Some(Ty::Uint) } else { scalar_type(&cast.ty, false) }
+                    .ok_or_else(|| Error::new(cast.ty.span(), "kernels can cast to `f32`, `i32`, `u32` and `usize`"))?;
+                if !value_ty.is_numeric() {
+                    return Err(Error::new(cast.expr.span(), format!("{} cannot be cast", value_ty.rust())));
+                }
+                Ok((format!("{}({})", ty.glsl(), value), ty))
+            }
+            Expr::Call(call) => {
+                let Expr::Path(path) = &*call.func else {
+                    return Err(Error::new(call.func.span(), "kernels can only call functions by name"));
+                };
+                let ident = path.path.get_ident().ok_or_else(|| Error::new(path.span(), "kernels can only call functions by name"))?;
+                self.function(ident, call.args.iter().collect(), expr)
+            }
+            Expr::MethodCall(call) => {
+                if call.method == "len" && call.args.is_empty() {
+                    if let Expr::Path(path) = &*call.receiver {
+                        if let Some(param) = path.path.get_ident().and_then(|ident| self.param(ident))
+                            .filter(|param| param.kind != ParamKind::PushConstant) {
+                            return Ok((format!("uint({}.length())", param.glsl), Ty::Uint));
+                        }
+                    }
+                }
+                let args = std::iter::once(&*call.receiver).chain(call.args.iter()).collect();
+                self.function(&call.method, args, expr)
+            }
+            other => Err(Error::new(other.span(),
+                "kernel expressions are literals, names, buffer elements, arithmetic, comparisons, casts and math functions")),
+        }
+    }
+
+    fn function(&self, ident: &Ident, args: Vec<&Expr>, expr: &Expr) -> syn::Result<(String, Ty)> {
+        let &(_, glsl, arity, float_only) = FUNCTIONS.iter()
+            .find(|(name, _, _, _)| ident == name)
+            .ok_or_else(|| Error::new(ident.span(), format!("`{}` is not available in kernels, they can call {}",
+                ident, FUNCTIONS.iter().map(|(name, _, _, _)| *name).collect::<Vec<&str>>().join(", "))))?;
+        if args.len() != arity {
+            return Err(Error::new(expr.span(), format!("`{}` takes {} arguments including its receiver", ident, arity)));
+        }
+        let args = args.into_iter().map(|arg| self.expr(arg).map(|(glsl, ty)| (glsl, ty, arg))).collect::<syn::Result<Vec<_>>>()?;
+        let mut ty = args[0].1;
+        for (_, arg_ty, arg) in &args {
+            if !arg_ty.is_numeric() || (float_only && *arg_ty != Ty::Float) {
+                return Err(Error::new(arg.span(), format!("`{}` takes {}, found {}", ident, if float_only { "f32" } else { "numbers" }, arg_ty.rust())));
+            }
+            ty = self.unify(ty, *arg_ty).ok_or_else(|| Error::new(arg.span(),
+                format!("`{}` takes arguments of one type, found {} and {}", ident, ty.rust(), arg_ty.rust())))?;
+        }
+        let args = args.into_iter().map(|(glsl, arg_ty, _)| self.convert(glsl, arg_ty, ty)).collect::<Vec<String>>();
+        Ok((format!("{}({})", glsl, args.join(", ")), ty))
+    }
+}
+
+fn strip_parens(expr: &Expr) -> &Expr {
+    match expr {
+        Expr::Paren(paren) => strip_parens(&paren.expr),
+        expr => expr,
+    }
+}
+
+fn literal(lit: &Lit) -> syn::Result<(String, Ty)> {
+    match lit {
+        Lit::Int(int) => {
+            let value = int.base10_parse::<u64>()?;
+            match int.suffix() {
+                "" => i32::try_from(value).map(|value| (value.to_string(), Ty::IntLiteral))
+                    .map_err(|_| Error::new(int.span(), "integer literals without a suffix must fit an i32, add `u32`")),
+                "i32" => i32::try_from(value).map(|value| (value.to_string(), Ty::Int))
+                    .map_err(|_| Error::new(int.span(), "the literal does not fit an i32")),
+                "u32" | "usize" => u32::try_from(value).map(|value| (format!("{}u", value), Ty::Uint))
+                    .map_err(|_| Error::new(int.span(), "the literal does not fit a u32")),
+                "f32" => Ok((format!("{:?}", value as f32), Ty::Float)),
+                suffix => Err(Error::new(int.span(), format!("`{}` literals are not supported in kernels", suffix))),
+            }
+        }
+        Lit::Float(float) => match float.suffix() {
+            "" | "f32" => Ok((format!("{:?}", float.base10_parse::<f32>()?), Ty::Float)),
+            suffix => Err(Error::new(float.span(), format!("`{}` literals are not supported in kernels", suffix))),
+        },
+        Lit::Bool(bool) => Ok((bool.value.to_string(), Ty::Bool)),
+        other => Err(Error::new(other.span(), "kernels only have number and bool literals")),
+    }
+}
+
+fn comparison(op: &BinOp) -> &'static str {
+    match op {
+        BinOp::Eq(_) => "==",
+        BinOp::Ne(_) => "!=",
+        BinOp::Lt(_) => "<",
+        BinOp::Le(_) => "<=",
+        BinOp::Gt(_) => ">",
+        _ => ">=",
+    }
+}
+
+fn arithmetic(op: &BinOp) -> Option<&'static str> {
+    Some(match op {
+        BinOp::Add(_) => "+",
+        BinOp::Sub(_) => "-",
+        BinOp::Mul(_) => "*",
+        BinOp::Div(_) => "/",
+        BinOp::Rem(_) => "%",
+        BinOp::BitAnd(_) => "&",
+        BinOp::BitOr(_) => "|",
+        BinOp::BitXor(_) => "^",
+        BinOp::Shl(_) => "<<",
+        BinOp::Shr(_) => ">>",
+        _ => return None,
+    })
+}
+
+/// The operator of `+=` and the like, without the `=`.
+/// Outputs are only read back after the dispatch, their values aren't uploaded before it, so
+/// reading one would read whatever the buffer held.
+fn output_read(param: &Param, index: &syn::ExprIndex) -> Error {
+    Error::new(index.span(), format!(
+        "`{}` is an output, `&mut [{}]`, which is only written: its values aren't uploaded, so read them from a `&[{}]` input",
+        param.ident, param.ty.rust(), param.ty.rust()))
+}
+
+fn compound_assignment(op: &BinOp) -> Option<&'static str> {
+    Some(match op {
+        BinOp::AddAssign(_) => "+",
+        BinOp::SubAssign(_) => "-",
+        BinOp::MulAssign(_) => "*",
+        BinOp::DivAssign(_) => "/",
+        BinOp::RemAssign(_) => "%",
+        BinOp::BitAndAssign(_) => "&",
+        BinOp::BitOrAssign(_) => "|",
+        BinOp::BitXorAssign(_) => "^",
+        BinOp::ShlAssign(_) => "<<",
+        BinOp::ShrAssign(_) => ">>",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The GLSL a kernel with a workgroup size of 64 translates to.
+    fn source(function: &str) -> String {
+        let function: ItemFn = syn::parse_str(function).unwrap();
+        let params = function.sig.inputs.iter().map(parse_param).collect::<syn::Result<Vec<Param>>>().unwrap();
+        glsl_source(&params, [64, 1, 1], &function.block).unwrap()
+    }
+
+    /// The lines of `main`'s body, without their indentation.
+    fn main_body(function: &str) -> Vec<String> {
+        let source = source(function);
+        let mut body = source.split_once("void main() {\n").unwrap().1
+            .lines()
+            .map(|line| line.trim().to_string())
+            .collect::<Vec<String>>();
+        // main's own closing brace
+        body.pop();
+        body
+    }
+
+    #[test]
+    fn declarations() {
+        assert_eq!(source("fn saxpy(x: &[f32], y: &[f32], result: &mut [f32], a: f32, n: u32) {}"), "\
+#version 450
+layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+layout(set = 0, binding = 0) readonly buffer Binding0 { float x[]; };
+layout(set = 0, binding = 1) readonly buffer Binding1 { float y[]; };
+layout(set = 0, binding = 2) writeonly buffer Binding2 { float result[]; };
+layout(push_constant) uniform PushConstants {
+    float a;
+    uint n;
+} pc;
+
+void main() {
+}
+");
+    }
+
+    #[test]
+    fn loops() {
+        let source = source("fn f(src: &[f32], dst: &mut [f32], n: u32) {
+            let mut sum = 0.0;
+            for i in 0..n {
+                sum += src[i];
+            }
+            for j in 1..=4 {
+                if j == 3 {
+                    continue;
+                }
+                sum -= j as f32;
+            }
+            let mut k = 0;
+            while k < dst.len() {
+                dst[k] = sum;
+                k += 1;
+            }
+        }");
+        assert!(source.contains("    for (uint i = 0u, i_end = pc.n; i < i_end; i++) {\n        sum += src[i];\n    }\n"), "{}", source);
+        assert!(source.contains("    for (int j = 1, j_end = 4; j <= j_end; j++) {\n"), "{}", source);
+        assert!(source.contains("    uint k = 0;\n    while (k < uint(dst.length())) {\n"), "{}", source);
+        assert!(source.contains("        k += 1u;\n"), "{}", source);
+    }
+
+    #[test]
+    fn range_end_is_evaluated_once() {
+        let body = main_body("fn f(src: &[u32], dst: &mut [u32]) {
+            for i in 0..src[0] {
+                dst[i] = i;
+            }
+        }");
+        assert_eq!(body[0], "for (uint i = 0u, i_end = src[0]; i < i_end; i++) {");
+    }
+
+    #[test]
+    fn shadowing() {
+        let body = main_body("fn f(dst: &mut [u32], x_3: &[u32]) {
+            let x = 1u32;
+            let x = x + 1u32;
+            let x_2 = x;
+            {
+                let x = x_2;
+                dst[0] = x + x_3[0];
+            }
+            dst[1] = x;
+        }");
+        assert_eq!(body, [
+            "uint x = 1u;",
+            "uint x_2 = x + 1u;",
+            "uint x_2_2 = x_2;",
+            "{",
+            "uint x_4 = x_2_2;",
+            "dst[0] = x_4 + x_3[0];",
+            "}",
+            "dst[1] = x_2;",
+        ]);
+    }
+
+    #[test]
+    fn reserved_names() {
+        let source = source("fn f(out: &mut [i32]) {
+            let int = 1;
+            let int = int + 1;
+            out[0] = int;
+        }");
+        assert!(source.contains("buffer Binding0 { int out_[]; };"), "{}", source);
+        assert!(source.contains("    int int_ = 1;\n    int int_2 = int_ + 1;\n    out_[0] = int_2;\n"), "{}", source);
+    }
+
+    #[test]
+    fn casts() {
+        let body = main_body("fn f(dst: &mut [f32], i: i32) {
+            let x = i as f32;
+            let u = x as u32;
+            let index = u as usize;
+            dst[index] = (u as f32).sqrt() + x;
+        }");
+        assert_eq!(body, [
+            "float x = float(pc.i);",
+            "uint u = uint(x);",
+            "uint index = uint(u);",
+            "dst[index] = sqrt(float(u)) + x;",
+        ]);
+    }
+
+    #[test]
+    fn untyped_integers() {
+        let body = main_body("fn f(src: &[u32], dst: &mut [u32]) {
+            let a = 2;
+            dst[a] = src[0].min(7) + 1;
+            let b = -3;
+            dst[1] = b as u32;
+        }");
+        assert_eq!(body, [
+            "uint a = 2;",
+            "dst[a] = min(src[0], 7u) + 1u;",
+            "int b = -3;",
+            "dst[1] = uint(b);",
+        ]);
+    }
+
+    #[test]
+    fn precedence() {
+        let body = main_body("fn f(dst: &mut [u32], n: u32) {
+            dst[0] = n & 1u32 << 2 | n ^ 3u32;
+        }");
+        assert_eq!(body, ["dst[0] = (pc.n & (1u << 2)) | (pc.n ^ 3u);"]);
+    }
+
+    /// The message of the error translating `function` gives.
+    fn error(function: &str) -> String {
+        let function: ItemFn = syn::parse_str(function).unwrap();
+        let params = function.sig.inputs.iter().map(parse_param).collect::<syn::Result<Vec<Param>>>().unwrap();
+        glsl_source(&params, [64, 1, 1], &function.block).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn outputs_are_only_written() {
+        let message = "`dst` is an output, `&mut [f32]`, which is only written: its values aren't uploaded, so read them from a `&[f32]` input";
+        assert_eq!(error("fn f(dst: &mut [f32]) { let x = dst[0]; }"), message);
+        assert_eq!(error("fn f(dst: &mut [f32]) { dst[0] += 1.0; }"), message);
+        assert_eq!(error("fn f(dst: &mut [f32]) { dst[1] = dst[0]; }"), message);
+        // Its length isn't one of its values
+        assert!(source("fn f(dst: &mut [f32]) { dst[0] = dst.len() as f32; }").contains("dst[0] = float(uint(dst.length()));"));
+    }
+}
@@ -0,0 +1,361 @@
+//! Derive macros for `rscompute`, re-exported from there.
+
+mod kernel;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, GenericArgument, Ident, ItemFn, PathArguments, Type};
+
+/// Derives `rscompute::GpuMappedMemory` for a struct whose fields are `bytemuck::Pod`
+/// values, arrays of them, or `Vec`s of them.
+///
+/// Fields are laid out one after another in declaration order, each starting at a multiple
+/// of its Rust alignment. That matches std430 for scalars and arrays of them, but not for
+/// vectors, whose std430 alignment is larger than their elements'. Since a `[f32; 3]` could
+/// be either a `float[3]` or a `vec3`, three-element arrays of scalars are rejected. Blocks
+/// with vectors, matrices or structs need explicit padding fields, such as a `vec3` as a
+/// `[f32; 4]`, or a `ShaderLayout` type written with a `BlockLayout`. A `Vec`
+/// is the block's runtime-sized array, so it must be the last field. Fields the shader
+/// doesn't see are marked `#[gpu_mapped(skip)]`.
+///
+/// The element types are checked at compile time: they must be `Pod`, and be 4-byte
+/// aligned since shaders have no 8 or 16-bit types without extensions. `write` and `read`
+/// fail with `Error::Layout` when the buffer is smaller than `buffer_size`, which counts an
+/// empty `Vec` as one value since Vulkan has no empty buffers.
+#[proc_macro_derive(GpuMappedMemory, attributes(gpu_mapped))]
+pub fn derive_gpu_mapped_memory(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `rscompute::layout::ShaderLayout` for a struct, laying its fields out as the
+/// members of a GLSL struct in declaration order. Every field must implement `ShaderLayout`.
+#[proc_macro_derive(ShaderLayout)]
+pub fn derive_shader_layout(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_shader_layout(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Turns a function into a compute kernel: a struct of the same name in UpperCamelCase that
+/// implements `rscompute::ComputeShader`, with GLSL translated from the function's body.
+///
+/// `&[T]` parameters are input buffers and `&mut [T]` parameters output buffers, bound to set 0
+/// in parameter order, where `T` is `f32`, `i32` or `u32`. Outputs are only read back, not
+/// uploaded, so their elements can be assigned to but not read. Scalar parameters are the members
+/// of the push constant block, a generated `<Name>PushConstants` struct. The struct has a
+/// `Vec<T>` field for each buffer, a field for each push constant, and `invocations`, the
+/// number of invocations to dispatch in each dimension.
+///
+/// The body is a subset of Rust: `let`, assignments, `if`, `for` over a range, `while`,
+/// `break`, `continue` and `return`, over number and bool literals, parameters, locals,
+/// buffer elements, `buffer.len()`, arithmetic, comparisons, `as` casts and common math
+/// functions. `global_id`, `local_id`, `workgroup_id`, `num_workgroups` and `local_index`
+/// are the invocation's built-in IDs. Operands have the same type as in Rust, with no implicit
+/// conversions. Anything outside the subset is a compile error.
+#[proc_macro_attribute]
+pub fn compute_kernel(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let function = parse_macro_input!(item as ItemFn);
+    kernel::expand(attr.into(), &function)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_shader_layout(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new(input.ident.span(), "ShaderLayout can only be derived for structs"));
+    };
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let name = &input.ident;
+
+    let members = data.fields.iter().enumerate().map(|(index, field)| match &field.ident {
+        Some(ident) => (quote!(#ident), ident.to_string()),
+        None => {
+            let index = syn::Index::from(index);
+            (quote!(#index), format!("_{}", index.index))
+        }
+    }).collect::<Vec<_>>();
+    let struct_members = data.fields.iter().zip(&members).map(|(field, (_, member_name))| {
+        let ty = &field.ty;
+        quote! {
+            ::rscompute::layout::StructMember {
+                name: ::std::string::String::from(#member_name),
+                ty: <#ty as ::rscompute::layout::ShaderLayout>::shader_type(),
+            }
+        }
+    });
+    let writes = members.iter().enumerate().map(|(index, (member, _))| quote! {
+        ::rscompute::layout::ShaderLayout::write_to(&self.#member, layout, &mut bytes[offsets[#index]..]);
+    });
+    let reads = members.iter().enumerate().map(|(index, (member, _))| quote! {
+        ::rscompute::layout::ShaderLayout::read_from(&mut self.#member, layout, &bytes[offsets[#index]..]);
+    });
+    let offsets = (!members.is_empty()).then(|| quote! {
+        let offsets = ::rscompute::layout::member_offsets::<Self>(layout);
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::rscompute::layout::ShaderLayout for #name #type_generics #where_clause {
+            fn shader_type() -> ::rscompute::layout::ShaderType {
+                ::rscompute::layout::ShaderType::Struct(::std::vec![#(#struct_members),*])
+            }
+
+            fn write_to(&self, layout: ::rscompute::layout::BlockLayout, bytes: &mut [u8]) {
+                #offsets
+                #(#writes)*
+            }
+
+            fn read_from(&mut self, layout: ::rscompute::layout::BlockLayout, bytes: &[u8]) {
+                #offsets
+                #(#reads)*
+            }
+        }
+    })
+}
+
+/// A field that is copied to and from the buffer.
+struct MappedField {
+    member: TokenStream2,
+    /// The type the std430 alignment comes from, the element type for a `Vec`
+    element_type: Type,
+    is_vec: bool,
+    span: Span,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(input.generics.span(),
+            "GpuMappedMemory cannot be derived for generic structs"));
+    }
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new(input.ident.span(), "GpuMappedMemory can only be derived for structs"));
+    };
+
+    let fields = mapped_fields(&data.fields)?;
+    if fields.is_empty() {
+        return Err(Error::new(input.ident.span(), "GpuMappedMemory needs at least one field that isn't skipped"));
+    }
+    if let Some(vec_field) = fields.iter().rev().skip(1).find(|field| field.is_vec) {
+        return Err(Error::new(vec_field.span,
+            "only the last mapped field can be a Vec, since std430 only allows a runtime-sized array at the end of a block"));
+    }
+
+    let name = &input.ident;
+    let checks = fields.iter().map(layout_check);
+    let sizes = fields.iter().map(|field| {
+        let member = &field.member;
+        let element_type = &field.element_type;
+        // An empty `Vec` still takes one value, as the `Vec` implementation does
+        let bytes = if field.is_vec {
+            quote!(::std::mem::size_of_val(&self.#member[..]).max(::std::mem::size_of::<#element_type>()))
+        } else {
+            quote!(::std::mem::size_of_val(&self.#member))
+        };
+        quote! {
+            size = size.next_multiple_of(::std::mem::align_of::<#element_type>()) + #bytes;
+        }
+    });
+    let writes = fields.iter().map(|field| {
+        let member = &field.member;
+        let element_type = &field.element_type;
+        let bytes = if field.is_vec {
+            quote!(::rscompute::bytemuck::cast_slice::<#element_type, u8>(&self.#member[..]))
+        } else {
+            quote!(::rscompute::bytemuck::bytes_of(&self.#member))
+        };
+        quote! {
+            offset = offset.next_multiple_of(::std::mem::align_of::<#element_type>());
+            let bytes = #bytes;
+            mapped[offset..offset + bytes.len()].copy_from_slice(bytes);
+            offset += bytes.len();
+        }
+    });
+    let reads = fields.iter().map(|field| {
+        let member = &field.member;
+        let element_type = &field.element_type;
+        let bytes = if field.is_vec {
+            quote!(::rscompute::bytemuck::cast_slice_mut::<#element_type, u8>(&mut self.#member[..]))
+        } else {
+            quote!(::rscompute::bytemuck::bytes_of_mut(&mut self.#member))
+        };
+        quote! {
+            offset = offset.next_multiple_of(::std::mem::align_of::<#element_type>());
+            let bytes = #bytes;
+            let len = bytes.len();
+            bytes.copy_from_slice(&mapped[offset..offset + len]);
+            offset += len;
+        }
+    });
+
+    let size_check_message = format!("{} needs {{}} bytes, but binding {{}} holds {{}}", name);
+    let size_check = quote! {
+        let size = ::rscompute::GpuMappedMemory::buffer_size(self);
+        if size > buffer.size {
+            return ::std::result::Result::Err(::rscompute::Error::Layout(
+                ::std::format!(#size_check_message, size, buffer.binding, buffer.size)));
+        }
+    };
+
+    Ok(quote! {
+        const _: () = {
+            #(#checks)*
+        };
+
+        impl ::rscompute::data::MappedFields for #name {
+            fn write_fields(&self, mapped: &mut [u8]) {
+                let mut offset = 0usize;
+                #(#writes)*
+                let _ = offset;
+            }
+
+            fn read_fields(&mut self, mapped: &[u8]) {
+                let mut offset = 0usize;
+                #(#reads)*
+                let _ = offset;
+            }
+        }
+
+        impl ::rscompute::GpuMappedMemory for #name {
+            fn write(&self, _ctx: &::rscompute::VkCtx, buffer: &mut ::rscompute::LinkedMemory) -> ::rscompute::Result<()> {
+                #size_check
+                ::rscompute::data::MappedFields::write_fields(self, &mut buffer.map_mut::<u8>()?);
+                Ok(())
+            }
+
+            fn read(&mut self, _ctx: &::rscompute::VkCtx, buffer: &mut ::rscompute::LinkedMemory) -> ::rscompute::Result<()> {
+                #size_check
+                ::rscompute::data::MappedFields::read_fields(self, &buffer.map::<u8>()?);
+                Ok(())
+            }
+
+            fn buffer_size(&self) -> u64 {
+                let mut size = 0usize;
+                #(#sizes)*
+                size as u64
+            }
+        }
+    })
+}
+
+fn mapped_fields(fields: &Fields) -> syn::Result<Vec<MappedField>> {
+    let mut mapped = Vec::new();
+    for (index, field) in fields.iter().enumerate() {
+        if is_skipped(field)? {
+            continue;
+        }
+        let member = match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let index = syn::Index::from(index);
+                quote!(#index)
+            }
+        };
+        reject_platform_sized(&field.ty)?;
+        reject_vec3(&field.ty)?;
+        let (element_type, is_vec) = match vec_element(&field.ty) {
+            Some(element_type) => (element_type.clone(), true),
+            None => (field.ty.clone(), false),
+        };
+        mapped.push(MappedField {
+            member,
+            element_type,
+            is_vec,
+            span: field.ty.span(),
+        });
+    }
+    Ok(mapped)
+}
+
+fn is_skipped(field: &syn::Field) -> syn::Result<bool> {
+    let mut skip = false;
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("gpu_mapped")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                skip = true;
+                Ok(())
+            } else {
+                Err(meta.error("expected `skip`"))
+            }
+        })?;
+    }
+    Ok(skip)
+}
+
+/// Returns `T` for a field of type `Vec<T>`.
+fn vec_element(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Vec" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(arguments) = &segment.arguments else {
+        return None;
+    };
+    match arguments.args.first()? {
+        GenericArgument::Type(element_type) => Some(element_type),
+        _ => None,
+    }
+}
+
+/// `usize` and `isize` are `Pod` but change size with the host, so no shader type matches them.
+fn reject_platform_sized(ty: &Type) -> syn::Result<()> {
+    if let Type::Array(array) = ty {
+        return reject_platform_sized(&array.elem);
+    }
+    if let Some(element_type) = vec_element(ty) {
+        return reject_platform_sized(element_type);
+    }
+    if let Type::Path(path) = ty {
+        if let Some(ident) = path.path.get_ident().filter(|ident| *ident == "usize" || *ident == "isize") {
+            return Err(Error::new(ident.span(),
+                format!("`{ident}` has no std430 equivalent, use `u32` or `u64` instead")));
+        }
+    }
+    Ok(())
+}
+
+/// Fields are laid out at their Rust alignment, so a `[f32; 3]` lands where a `float[3]` would,
+/// not where a 16-byte aligned `vec3` would. Rather than guess which one the shader declares,
+/// three-element arrays of scalars are rejected.
+fn reject_vec3(ty: &Type) -> syn::Result<()> {
+    if let Some(element_type) = vec_element(ty) {
+        return reject_vec3(element_type);
+    }
+    let Type::Array(array) = ty else {
+        return Ok(());
+    };
+    let is_three = matches!(&array.len, syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(len), .. })
+        if len.base10_parse::<usize>().ok() == Some(3));
+    let scalar = match &*array.elem {
+        Type::Path(path) => path.path.get_ident()
+            .filter(|ident| ["f32", "f64", "i32", "u32", "i64", "u64"].iter().any(|scalar| *ident == scalar)),
+        _ => None,
+    };
+    match scalar {
+        Some(scalar) if is_three => Err(Error::new(array.span(), format!(
+            "`[{scalar}; 3]` is laid out as three scalars, but a three-component vector is aligned like a \
+             four-component one in std430: use `[{scalar}; 4]` with a padding value, or a `ShaderLayout` type"))),
+        _ => reject_vec3(&array.elem),
+    }
+}
+
+fn layout_check(field: &MappedField) -> TokenStream2 {
+    let element_type = &field.element_type;
+    let message = format!("`{}` must be at least 4-byte aligned, shaders have no smaller types without extensions",
+        quote!(#element_type).to_string().replace(' ', ""));
+    let assert_pod = Ident::new("assert_pod", field.span);
+    quote_spanned! {field.span=>
+        {
+            const fn #assert_pod<T: ::rscompute::bytemuck::Pod>() {}
+            #assert_pod::<#element_type>();
+            ::std::assert!(::std::mem::align_of::<#element_type>() >= 4, #message);
+        }
+    }
+}
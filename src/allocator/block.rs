@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex, MutexGuard};
+use ash::vk;
+use crate::context::Device;
+use crate::error::{Error, Result};
+use super::{align_up, find_requested_memory_type, Allocation, AllocationRequest, Allocator, AllocatorStats};
+
+#[derive(Clone, Copy, Debug)]
+pub struct BlockAllocatorConfig {
+    /// Size of each block of device memory that allocations are carved out of
+    pub block_size: u64,
+    /// Allocations at least this large get a `VkDeviceMemory` of their own
+    pub dedicated_threshold: u64,
+}
+
+impl Default for BlockAllocatorConfig {
+    fn default() -> Self {
+        BlockAllocatorConfig {
+            block_size: 64 * 1024 * 1024,
+            dedicated_threshold: 32 * 1024 * 1024,
+        }
+    }
+}
+
+/// The default [`Allocator`]. Each memory type has a pool of large blocks which are
+/// sub-allocated with a first-fit free list, so the number of `vkAllocateMemory` calls
+/// stays far below `maxMemoryAllocationCount`. Host-visible blocks are mapped once
+/// when they are allocated and stay mapped until they are freed, and allocations from
+/// non-coherent memory are padded to `nonCoherentAtomSize`.
+pub struct BlockAllocator {
+    device: Arc<Device>,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    config: BlockAllocatorConfig,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Blocks by memory type index
+    pools: HashMap<u32, Vec<Block>>,
+    dedicated_count: usize,
+    dedicated_bytes: u64,
+    next_block_id: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct FreeRange {
+    offset: u64,
+    size: u64,
+}
+
+impl FreeRange {
+    fn end(&self) -> u64 {
+        self.offset + self.size
+    }
+}
+
+struct Block {
+    id: u64,
+    memory: vk::DeviceMemory,
+    size: u64,
+    mapped_ptr: Option<NonNull<u8>>,
+    /// Sorted by offset, with adjacent ranges always merged
+    free: Vec<FreeRange>,
+    allocation_count: usize,
+}
+
+// The mapped pointer is only handed out inside allocations, the block itself never reads it
+unsafe impl Send for Block {}
+
+impl Block {
+    fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        let (index, offset) = self.free.iter()
+            .enumerate()
+            .map(|(index, range)| (index, align_up(range.offset, alignment)))
+            .find(|(index, offset)| offset + size <= self.free[*index].end())?;
+
+        let range = self.free.remove(index);
+        let before = FreeRange { offset: range.offset, size: offset - range.offset };
+        let after = FreeRange { offset: offset + size, size: range.end() - (offset + size) };
+        let mut insert_at = index;
+        if before.size > 0 {
+            self.free.insert(insert_at, before);
+            insert_at += 1;
+        }
+        if after.size > 0 {
+            self.free.insert(insert_at, after);
+        }
+        self.allocation_count += 1;
+        Some(offset)
+    }
+
+    fn free(&mut self, offset: u64, size: u64) {
+        let mut index = self.free.partition_point(|range| range.offset < offset);
+        self.free.insert(index, FreeRange { offset, size });
+        if index + 1 < self.free.len() && self.free[index].end() == self.free[index + 1].offset {
+            self.free[index].size += self.free.remove(index + 1).size;
+        }
+        if index > 0 && self.free[index - 1].end() == self.free[index].offset {
+            self.free[index - 1].size += self.free.remove(index).size;
+            index -= 1;
+        }
+        debug_assert!(self.free[index].end() <= self.size);
+        self.allocation_count -= 1;
+    }
+
+    fn free_bytes(&self) -> u64 {
+        self.free.iter().map(|range| range.size).sum()
+    }
+
+    fn largest_free_range(&self) -> u64 {
+        self.free.iter().map(|range| range.size).max().unwrap_or(0)
+    }
+}
+
+impl BlockAllocator {
+    pub fn new(device: &Arc<Device>, config: BlockAllocatorConfig) -> BlockAllocator {
+        let memory_properties = unsafe {
+            device.instance.get_physical_device_memory_properties(device.physical_device)
+        };
+        BlockAllocator {
+            device: device.clone(),
+            memory_properties,
+            config,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // The state is consistent between calls, so a panic elsewhere cannot have corrupted it
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn memory_flags(&self, memory_type_index: u32) -> vk::MemoryPropertyFlags {
+        self.memory_properties.memory_types[memory_type_index as usize].property_flags
+    }
+
+    /// Allocates device memory, mapping it when it is host-visible.
+    fn allocate_memory(&self, size: u64, memory_type_index: u32) -> Result<(vk::DeviceMemory, Option<NonNull<u8>>)> {
+        let memory = unsafe {
+            self.device.allocate_memory(&vk::MemoryAllocateInfo {
+                allocation_size: size,
+                memory_type_index,
+                ..Default::default()
+            }, None)
+        }.map_err(|r| Error::from_vk(r, Error::Allocation))?;
+
+        if !self.memory_flags(memory_type_index).contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+            return Ok((memory, None));
+        }
+        match unsafe { self.device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty()) } {
+            Ok(ptr) => Ok((memory, NonNull::new(ptr as *mut u8))),
+            Err(r) => {
+                unsafe { self.device.free_memory(memory, None) };
+                Err(Error::from_vk(r, Error::MemoryMap))
+            }
+        }
+    }
+
+    fn allocate_dedicated(&self, size: u64, memory_type_index: u32) -> Result<Allocation> {
+        let (memory, mapped_ptr) = self.allocate_memory(size, memory_type_index)?;
+        let mut state = self.lock();
+        state.dedicated_count += 1;
+        state.dedicated_bytes += size;
+        tracing::debug!(?memory, size, memory_type_index, "dedicated allocation");
+        Ok(Allocation {
+            memory,
+            offset: 0,
+            size,
+            memory_type_index,
+            memory_flags: self.memory_flags(memory_type_index),
+            mapped_ptr,
+            block_id: None,
+        })
+    }
+}
+
+impl Allocator for BlockAllocator {
+    fn allocate(&self, request: &AllocationRequest) -> Result<Allocation> {
+        let memory_type_index = find_requested_memory_type(&self.memory_properties, request)
+            .ok_or(Error::NoSuitableMemoryType {
+                memory_type_bits: request.requirements.memory_type_bits,
+                flags: request.required_flags,
+            })?;
+
+        let mut requirements = request.requirements;
+        let flags = self.memory_flags(memory_type_index);
+        if flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) && !flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT) {
+            // Flushes and invalidates cover whole atoms, which must not reach into a neighbour
+            let atom = self.device.info.limits.non_coherent_atom_size;
+            requirements.alignment = requirements.alignment.max(atom);
+            requirements.size = align_up(requirements.size, atom);
+        }
+
+        if request.prefers_dedicated || requirements.size >= self.config.dedicated_threshold {
+            return self.allocate_dedicated(requirements.size, memory_type_index);
+        }
+
+        let mut state = self.lock();
+        let existing = state.pools.get_mut(&memory_type_index).and_then(|blocks| {
+            blocks.iter_mut().find_map(|block| {
+                block.allocate(requirements.size, requirements.alignment)
+                    .map(|offset| (block.id, block.memory, block.mapped_ptr, offset))
+            })
+        });
+
+        let (block_id, memory, block_ptr, offset) = match existing {
+            Some(found) => found,
+            None => {
+                let block_size = self.config.block_size
+                    .max(align_up(requirements.size, requirements.alignment));
+                let (memory, mapped_ptr) = self.allocate_memory(block_size, memory_type_index)?;
+                let id = state.next_block_id;
+                state.next_block_id += 1;
+                tracing::debug!(?memory, block_size, memory_type_index, block_id = id, "allocated block");
+
+                let mut block = Block {
+                    id,
+                    memory,
+                    size: block_size,
+                    mapped_ptr,
+                    free: vec![FreeRange { offset: 0, size: block_size }],
+                    allocation_count: 0,
+                };
+                let offset = block.allocate(requirements.size, requirements.alignment)
+                    .expect("a new block is large enough for the allocation it was created for");
+                state.pools.entry(memory_type_index).or_default().push(block);
+                (id, memory, mapped_ptr, offset)
+            }
+        };
+
+        tracing::trace!(?memory, offset, size = requirements.size, block_id, "sub-allocated");
+        Ok(Allocation {
+            memory,
+            offset,
+            size: requirements.size,
+            memory_type_index,
+            memory_flags: self.memory_flags(memory_type_index),
+            mapped_ptr: block_ptr.map(|ptr| unsafe { NonNull::new_unchecked(ptr.as_ptr().add(offset as usize)) }),
+            block_id: Some(block_id),
+        })
+    }
+
+    fn free(&self, allocation: Allocation) {
+        let mut state = self.lock();
+        let Some(block_id) = allocation.block_id else {
+            tracing::debug!(memory = ?allocation.memory, size = allocation.size, "freeing dedicated allocation");
+            unsafe { self.device.free_memory(allocation.memory, None) };
+            state.dedicated_count -= 1;
+            state.dedicated_bytes -= allocation.size;
+            return;
+        };
+
+        let Some(blocks) = state.pools.get_mut(&allocation.memory_type_index) else {
+            tracing::warn!(block_id, "freed an allocation from an unknown memory type");
+            return;
+        };
+        let Some(index) = blocks.iter().position(|block| block.id == block_id) else {
+            tracing::warn!(block_id, "freed an allocation from an unknown block");
+            return;
+        };
+        blocks[index].free(allocation.offset, allocation.size);
+        tracing::trace!(memory = ?allocation.memory, offset = allocation.offset, block_id, "freed sub-allocation");
+
+        // Keep one empty block per memory type around so allocation churn doesn't thrash the driver
+        if blocks[index].allocation_count == 0 && blocks.len() > 1 {
+            let block = blocks.remove(index);
+            tracing::debug!(memory = ?block.memory, block_id, "freeing empty block");
+            unsafe { self.device.free_memory(block.memory, None) };
+        }
+    }
+
+    fn stats(&self) -> AllocatorStats {
+        let state = self.lock();
+        let mut stats = AllocatorStats {
+            dedicated_count: state.dedicated_count,
+            allocation_count: state.dedicated_count,
+            allocated_bytes: state.dedicated_bytes,
+            used_bytes: state.dedicated_bytes,
+            ..Default::default()
+        };
+        for block in state.pools.values().flatten() {
+            let free_bytes = block.free_bytes();
+            stats.block_count += 1;
+            stats.allocation_count += block.allocation_count;
+            stats.allocated_bytes += block.size;
+            stats.used_bytes += block.size - free_bytes;
+            stats.fragmented_bytes += free_bytes - block.largest_free_range();
+        }
+        stats
+    }
+}
+
+impl Drop for BlockAllocator {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for block in state.pools.values().flatten() {
+            if block.allocation_count > 0 {
+                tracing::warn!(block_id = block.id, allocations = block.allocation_count,
+                    "freeing a block that still has live allocations");
+            }
+            unsafe { self.device.free_memory(block.memory, None) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(size: u64) -> Block {
+        Block {
+            id: 0,
+            memory: vk::DeviceMemory::null(),
+            size,
+            mapped_ptr: None,
+            free: vec![FreeRange { offset: 0, size }],
+            allocation_count: 0,
+        }
+    }
+
+    fn free_ranges(block: &Block) -> Vec<(u64, u64)> {
+        block.free.iter().map(|range| (range.offset, range.size)).collect()
+    }
+
+    #[test]
+    fn first_fit_takes_the_lowest_range_that_fits() {
+        let mut block = block(1024);
+        let offsets = [0; 3].map(|_| block.allocate(100, 1).unwrap());
+        assert_eq!(offsets, [0, 100, 200]);
+        block.free(0, 100);
+        assert_eq!(free_ranges(&block), [(0, 100), (300, 724)]);
+
+        assert_eq!(block.allocate(50, 1), Some(0));
+        // The 50 bytes left at the start are too few, so the next range is used
+        assert_eq!(block.allocate(60, 1), Some(300));
+        assert_eq!(free_ranges(&block), [(50, 50), (360, 664)]);
+        assert_eq!(block.allocation_count, 4);
+    }
+
+    #[test]
+    fn alignment_padding_stays_free() {
+        let mut block = block(1024);
+        assert_eq!(block.allocate(10, 1), Some(0));
+        assert_eq!(block.allocate(16, 64), Some(64));
+        assert_eq!(free_ranges(&block), [(10, 54), (80, 944)]);
+        assert_eq!(block.allocate(54, 2), Some(10));
+        assert_eq!(free_ranges(&block), [(80, 944)]);
+    }
+
+    #[test]
+    fn freed_ranges_coalesce_with_their_neighbours() {
+        let mut block = block(1024);
+        for _ in 0..3 {
+            block.allocate(100, 1).unwrap();
+        }
+        block.free(100, 100);
+        assert_eq!(free_ranges(&block), [(100, 100), (300, 724)]);
+        block.free(0, 100);
+        assert_eq!(free_ranges(&block), [(0, 200), (300, 724)]);
+        block.free(200, 100);
+        assert_eq!(free_ranges(&block), [(0, 1024)]);
+        assert_eq!(block.allocation_count, 0);
+    }
+
+    #[test]
+    fn allocation_that_doesnt_fit_fails() {
+        let mut block = block(100);
+        assert_eq!(block.allocate(101, 1), None);
+        assert_eq!(block.allocate(10, 1), Some(0));
+        // Aligning to 16 leaves 84 bytes, too few for 90
+        assert_eq!(block.allocate(90, 16), None);
+        assert_eq!(block.allocate(90, 1), Some(10));
+        assert_eq!(block.allocate(1, 1), None);
+        assert_eq!(free_ranges(&block), []);
+        assert_eq!(block.allocation_count, 2);
+    }
+
+    #[test]
+    fn free_bytes_and_largest_range_count_every_gap() {
+        let mut block = block(1024);
+        let offsets = [0; 4].map(|_| block.allocate(128, 1).unwrap());
+        block.free(offsets[0], 128);
+        block.free(offsets[2], 128);
+        assert_eq!(block.free_bytes(), 128 + 128 + 512);
+        assert_eq!(block.largest_free_range(), 512);
+        block.allocate(512, 1).unwrap();
+        assert_eq!(block.largest_free_range(), 128);
+    }
+}
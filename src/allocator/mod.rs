@@ -0,0 +1,187 @@
+use std::ops::Deref;
+use std::ptr::NonNull;
+use std::sync::Arc;
+use ash::vk;
+use crate::context::Device;
+use crate::error::{Error, Result};
+
+pub mod block;
+
+pub use block::{BlockAllocator, BlockAllocatorConfig};
+
+/// What a resource needs from the memory it is bound to.
+#[derive(Clone, Copy, Debug)]
+pub struct AllocationRequest {
+    pub requirements: vk::MemoryRequirements,
+    /// Every one of these flags must be set on the chosen memory type
+    pub required_flags: vk::MemoryPropertyFlags,
+    /// Flags to look for first, falling back to a type with only `required_flags`
+    pub preferred_flags: vk::MemoryPropertyFlags,
+    /// The driver asked for the resource to get an allocation of its own
+    pub prefers_dedicated: bool,
+}
+
+/// A range of device memory handed out by an [`Allocator`].
+#[derive(Debug)]
+pub struct Allocation {
+    pub memory: vk::DeviceMemory,
+    /// Offset of this allocation inside `memory`, to pass to `vkBind*Memory`
+    pub offset: u64,
+    pub size: u64,
+    pub memory_type_index: u32,
+    pub memory_flags: vk::MemoryPropertyFlags,
+    /// Host pointer to the start of the allocation when its memory is persistently mapped
+    pub mapped_ptr: Option<NonNull<u8>>,
+    /// Identifies the block this was sub-allocated from, `None` for dedicated allocations.
+    /// Only meaningful to the allocator that created it.
+    pub block_id: Option<u64>,
+}
+
+impl Allocation {
+    /// Whether host writes and device writes are visible to each other without flushing
+    /// and invalidating. Memory that isn't host-visible counts as coherent, since the host
+    /// never touches it.
+    pub fn is_coherent(&self) -> bool {
+        !self.memory_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
+            || self.memory_flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+    }
+
+    /// Makes host writes to `size` bytes at `offset` into the allocation visible to the device.
+    /// Does nothing for coherent memory.
+    pub fn flush(&self, device: &Device, offset: u64, size: u64) -> Result<()> {
+        if self.is_coherent() {
+            return Ok(());
+        }
+        let range = self.mapped_range(device, offset, size);
+        tracing::trace!(memory = ?self.memory, offset = range.offset, size = range.size, "flushing mapped memory");
+        unsafe { device.flush_mapped_memory_ranges(&[range]) }
+            .map_err(|r| Error::from_vk(r, Error::MemoryMap))
+    }
+
+    /// Makes device writes to `size` bytes at `offset` into the allocation visible to the host.
+    /// Does nothing for coherent memory.
+    pub fn invalidate(&self, device: &Device, offset: u64, size: u64) -> Result<()> {
+        if self.is_coherent() {
+            return Ok(());
+        }
+        let range = self.mapped_range(device, offset, size);
+        tracing::trace!(memory = ?self.memory, offset = range.offset, size = range.size, "invalidating mapped memory");
+        unsafe { device.invalidate_mapped_memory_ranges(&[range]) }
+            .map_err(|r| Error::from_vk(r, Error::MemoryMap))
+    }
+
+    /// Widens a range of the allocation to `nonCoherentAtomSize` boundaries. Allocators pad
+    /// non-coherent allocations to whole atoms, so the widened range stays inside this one.
+    fn mapped_range(&self, device: &Device, offset: u64, size: u64) -> vk::MappedMemoryRange<'static> {
+        let atom = device.info.limits.non_coherent_atom_size.max(1);
+        let start = (self.offset + offset) / atom * atom;
+        let end = align_up(self.offset + offset.saturating_add(size).min(self.size), atom);
+        vk::MappedMemoryRange::default()
+            .memory(self.memory)
+            .offset(start)
+            .size(end - start)
+    }
+}
+
+// The mapped pointer is only dereferenced through the buffer that owns the allocation
+unsafe impl Send for Allocation {}
+unsafe impl Sync for Allocation {}
+
+/// Memory usage of an allocator.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocatorStats {
+    /// Blocks of device memory that allocations are carved out of
+    pub block_count: usize,
+    /// Allocations that got a `VkDeviceMemory` of their own
+    pub dedicated_count: usize,
+    /// Live allocations, both sub-allocated and dedicated
+    pub allocation_count: usize,
+    /// Bytes of device memory allocated from the driver
+    pub allocated_bytes: u64,
+    /// Bytes of that memory handed out to live allocations
+    pub used_bytes: u64,
+    /// Free bytes inside blocks that are not part of each block's largest free range,
+    /// and so cannot be used for an allocation as big as the free space suggests
+    pub fragmented_bytes: u64,
+}
+
+/// Hands out device memory for buffers. [`LinkedMemory`](crate::data::LinkedMemory) draws
+/// its memory from the context's allocator, so implementing this trait replaces the
+/// allocation strategy for every buffer.
+///
+/// Allocations from host-visible memory that isn't `HOST_COHERENT` must start and end on
+/// `nonCoherentAtomSize` boundaries, so [`Allocation::flush`] and [`Allocation::invalidate`]
+/// never touch a neighbouring allocation.
+pub trait Allocator: Send + Sync {
+    fn allocate(&self, request: &AllocationRequest) -> Result<Allocation>;
+
+    /// Returns an allocation made by this allocator. Nothing bound to it may still be in use.
+    fn free(&self, allocation: Allocation);
+
+    fn stats(&self) -> AllocatorStats;
+}
+
+/// An allocation that is returned to its allocator when dropped.
+pub struct OwnedAllocation {
+    allocation: Option<Allocation>,
+    allocator: Arc<dyn Allocator>,
+}
+
+impl OwnedAllocation {
+    pub fn allocate(allocator: &Arc<dyn Allocator>, request: &AllocationRequest) -> Result<OwnedAllocation> {
+        Ok(OwnedAllocation {
+            allocation: Some(allocator.allocate(request)?),
+            allocator: allocator.clone(),
+        })
+    }
+}
+
+impl Deref for OwnedAllocation {
+    type Target = Allocation;
+
+    fn deref(&self) -> &Allocation {
+        self.allocation.as_ref().expect("allocation is only taken when dropped")
+    }
+}
+
+impl std::fmt::Debug for OwnedAllocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.allocation.fmt(f)
+    }
+}
+
+impl Drop for OwnedAllocation {
+    fn drop(&mut self) {
+        if let Some(allocation) = self.allocation.take() {
+            self.allocator.free(allocation);
+        }
+    }
+}
+
+/// Finds a memory type for `request`, trying its preferred flags before its required ones.
+pub fn find_requested_memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    request: &AllocationRequest) -> Option<u32> {
+    let memory_type_bits = request.requirements.memory_type_bits;
+    find_memory_type(memory_properties, memory_type_bits, request.required_flags | request.preferred_flags)
+        .or_else(|| find_memory_type(memory_properties, memory_type_bits, request.required_flags))
+}
+
+/// Finds the first memory type allowed by `memory_type_bits` that has all of `flags`.
+pub fn find_memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    memory_type_bits: u32,
+    flags: vk::MemoryPropertyFlags) -> Option<u32> {
+    (0..memory_properties.memory_type_count).find(|&i| {
+        (memory_type_bits & (1 << i)) != 0
+            && (memory_properties.memory_types[i as usize].property_flags & flags) == flags
+    })
+}
+
+pub(crate) fn align_up(value: u64, alignment: u64) -> u64 {
+    if alignment <= 1 {
+        value
+    } else {
+        value.div_ceil(alignment) * alignment
+    }
+}
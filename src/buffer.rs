@@ -0,0 +1,301 @@
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use bytemuck::Pod;
+use crate::allocator::OwnedAllocation;
+use crate::context::VkCtx;
+use crate::data::{LinkedMemory, MemoryPlacement};
+use crate::error::{Error, Result};
+use crate::layout::{BlockLayout, ShaderLayout};
+use ash::vk;
+
+/// Read access to the mapped memory of a [`LinkedMemory`] as a slice of `T`.
+///
+/// Borrows the buffer mutably, which holds its device alive, so the slice can outlive neither
+/// and the buffer can't be dispatched while it is mapped.
+pub struct MappedSlice<'a, T: Pod> {
+    slice: &'a [T],
+}
+
+impl<T: Pod> Deref for MappedSlice<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.slice
+    }
+}
+
+/// Write access to the mapped memory of a [`LinkedMemory`] as a slice of `T`.
+/// Writes are flushed when the guard is dropped, for memory that needs it.
+pub struct MappedSliceMut<'a, T: Pod> {
+    slice: &'a mut [T],
+    memory: &'a OwnedAllocation,
+    device: &'a crate::context::Device,
+}
+
+impl<T: Pod> Deref for MappedSliceMut<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.slice
+    }
+}
+
+impl<T: Pod> DerefMut for MappedSliceMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.slice
+    }
+}
+
+impl<T: Pod> Drop for MappedSliceMut<'_, T> {
+    fn drop(&mut self) {
+        let size = std::mem::size_of_val(self.slice) as u64;
+        if let Err(error) = self.memory.flush(self.device, 0, size) {
+            tracing::warn!(%error, memory = ?self.memory.memory, "failed to flush mapped memory");
+        }
+    }
+}
+
+/// Returns the mapped host pointer of an allocation along with how many `T` fit in `size` bytes.
+fn mapped_parts<T: Pod>(memory: &OwnedAllocation, size: u64) -> Result<(*mut T, usize)> {
+    let ptr = memory.mapped_ptr
+        .ok_or(Error::MemoryMap(vk::Result::ERROR_MEMORY_MAP_FAILED))?
+        .as_ptr();
+    aligned_parts(ptr, size).inspect_err(|_| {
+        tracing::warn!(memory = ?memory.memory, offset = memory.offset, ty = std::any::type_name::<T>(),
+            "mapped memory is not aligned for the type");
+    })
+}
+
+/// Views `size` bytes at `ptr` as values of `T`, failing when `ptr` isn't aligned for `T`.
+fn aligned_parts<T: Pod>(ptr: *mut u8, size: u64) -> Result<(*mut T, usize)> {
+    // Mapped pointers are aligned to minMemoryMapAlignment and buffer offsets to the
+    // buffer's alignment, which covers every type a shader can read, but not every `Pod` type
+    if ptr.align_offset(std::mem::align_of::<T>()) != 0 {
+        return Err(Error::MemoryMap(vk::Result::ERROR_MEMORY_MAP_FAILED));
+    }
+    let len = (size as usize).checked_div(std::mem::size_of::<T>()).unwrap_or(0);
+    Ok((ptr as *mut T, len))
+}
+
+/// The size in bytes of `len` values of `T`, failing for no values, since Vulkan has no empty
+/// buffers, and for sizes that overflow.
+fn values_size<T>(len: usize) -> Result<u64> {
+    if len == 0 {
+        return Err(Error::Layout(format!("a buffer of {} needs at least one value", std::any::type_name::<T>())));
+    }
+    len.checked_mul(std::mem::size_of::<T>())
+        .and_then(|size| u64::try_from(size).ok())
+        .ok_or_else(|| Error::Layout(format!("{} values of {} overflow the size of a buffer",
+            len, std::any::type_name::<T>())))
+}
+
+/// Fails when `len` values don't fit in a buffer of `capacity` values of `T`.
+fn check_len<T>(len: usize, capacity: usize) -> Result<()> {
+    if len > capacity {
+        return Err(Error::Layout(format!("{} values don't fit in a buffer of {} {}",
+            len, capacity, std::any::type_name::<T>())));
+    }
+    Ok(())
+}
+
+impl LinkedMemory {
+    /// Maps the buffer's memory, or its staging buffer's, as a slice of `T`, invalidating
+    /// it first so device writes are visible.
+    ///
+    /// Takes `self` mutably, like [`LinkedMemory::map_mut`], so the slice can't be alive while the
+    /// [`crate::shader::ShaderExecutionContext`] holding the buffer is borrowed to run the shader.
+    pub fn map<T: Pod>(&mut self) -> Result<MappedSlice<'_, T>> {
+        let memory = self.mapped_memory();
+        memory.invalidate(self.buffer.device(), 0, self.size)?;
+        let (ptr, len) = mapped_parts::<T>(memory, self.size)?;
+        tracing::trace!(memory = ?memory.memory, offset = memory.offset, binding = self.binding, len, "mapped for reading");
+        // The memory stays mapped while the allocation is alive, and the allocation lives as long as `self`
+        Ok(MappedSlice { slice: unsafe { std::slice::from_raw_parts(ptr, len) } })
+    }
+
+    /// Maps the buffer's memory, or its staging buffer's, as a mutable slice of `T`.
+    pub fn map_mut<T: Pod>(&mut self) -> Result<MappedSliceMut<'_, T>> {
+        let memory = self.mapped_memory();
+        let (ptr, len) = mapped_parts::<T>(memory, self.size)?;
+        tracing::trace!(memory = ?memory.memory, offset = memory.offset, binding = self.binding, len, "mapped for writing");
+        // Taking `self` mutably means no other slice of this memory can exist on the host
+        Ok(MappedSliceMut {
+            slice: unsafe { std::slice::from_raw_parts_mut(ptr, len) },
+            memory,
+            device: self.buffer.device(),
+        })
+    }
+
+    /// Writes `value` to the start of the buffer laid out as `layout`, padding included.
+    pub fn write_value<T: ShaderLayout>(&mut self, value: &T, layout: BlockLayout) -> Result<()> {
+        self.check_fits::<T>(layout)?;
+        value.write_to(layout, &mut self.map_mut::<u8>()?);
+        Ok(())
+    }
+
+    /// Reads `value` from the start of the buffer laid out as `layout`.
+    pub fn read_value<T: ShaderLayout>(&mut self, value: &mut T, layout: BlockLayout) -> Result<()> {
+        self.check_fits::<T>(layout)?;
+        value.read_from(layout, &self.map::<u8>()?);
+        Ok(())
+    }
+
+    fn check_fits<T: ShaderLayout>(&self, layout: BlockLayout) -> Result<()> {
+        let size = T::shader_type().size(layout);
+        if size > self.size {
+            return Err(Error::Layout(format!("{} is {} bytes as {:?}, but the buffer is {}",
+                std::any::type_name::<T>(), size, layout, self.size)));
+        }
+        Ok(())
+    }
+
+    /// Copies the staging buffer to the buffer and waits for the copy, if it is staged.
+    pub fn upload(&self, ctx: &VkCtx) -> Result<()> {
+        if !self.is_staged() {
+            return Ok(());
+        }
+        ctx.execute_commands(|device, command_buffer| self.record_upload(device, command_buffer))
+    }
+
+    /// Copies the buffer to its staging buffer and waits for the copy, if it is staged.
+    pub fn download(&self, ctx: &VkCtx) -> Result<()> {
+        if !self.is_staged() {
+            return Ok(());
+        }
+        ctx.execute_commands(|device, command_buffer| unsafe {
+            self.record_download(device, command_buffer);
+            device.cmd_pipeline_barrier(command_buffer,
+                vk::PipelineStageFlags::TRANSFER, vk::PipelineStageFlags::HOST, vk::DependencyFlags::empty(),
+                &[vk::MemoryBarrier::default()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::HOST_READ)],
+                &[], &[]);
+        })
+    }
+}
+
+/// A storage buffer of `len` values of `T`, persistently mapped when its memory is
+/// host-visible and staged otherwise.
+#[derive(Debug)]
+pub struct GpuBuffer<T: Pod> {
+    pub memory: LinkedMemory,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> GpuBuffer<T> {
+    /// Creates a buffer for `len` values, see [`LinkedMemory::new`] for `readback`.
+    /// Fails with [`Error::Layout`] when `len` is zero or the size in bytes overflows.
+    pub fn new(ctx: &VkCtx, binding: u32, len: usize, placement: MemoryPlacement, readback: bool) -> Result<GpuBuffer<T>> {
+        let size = values_size::<T>(len)?;
+        Ok(GpuBuffer {
+            memory: LinkedMemory::new(ctx, binding, size, placement, readback)?,
+            len,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Views existing buffer memory as values of `T`, as many as fit in it.
+    pub fn from_linked_memory(memory: LinkedMemory) -> GpuBuffer<T> {
+        let len = (memory.size as usize).checked_div(std::mem::size_of::<T>()).unwrap_or(0);
+        GpuBuffer {
+            memory,
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn buffer(&self) -> vk::Buffer {
+        *self.memory.buffer
+    }
+
+    /// The mapped values. Staged buffers show what was last written or downloaded.
+    pub fn map(&mut self) -> Result<MappedSlice<'_, T>> {
+        let mut mapped = self.memory.map::<T>()?;
+        mapped.slice = &mapped.slice[..self.len];
+        Ok(mapped)
+    }
+
+    /// The mapped values, for writing. Staged buffers need an [`LinkedMemory::upload`] afterwards.
+    pub fn map_mut(&mut self) -> Result<MappedSliceMut<'_, T>> {
+        let len = self.len;
+        let mut mapped = self.memory.map_mut::<T>()?;
+        mapped.slice = &mut std::mem::take(&mut mapped.slice)[..len];
+        Ok(mapped)
+    }
+
+    /// Copies `data` to the start of the buffer, uploading it when the buffer is staged.
+    /// Fails with [`Error::Layout`] when `data` holds more values than the buffer.
+    pub fn write(&mut self, ctx: &VkCtx, data: &[T]) -> Result<()> {
+        self.check_fits(data.len())?;
+        self.map_mut()?[..data.len()].copy_from_slice(data);
+        self.memory.upload(ctx)
+    }
+
+    /// Copies the start of the buffer into `data`, downloading it first when the buffer is staged.
+    /// Fails with [`Error::Layout`] when `data` holds more values than the buffer.
+    pub fn read(&mut self, ctx: &VkCtx, data: &mut [T]) -> Result<()> {
+        self.check_fits(data.len())?;
+        self.memory.download(ctx)?;
+        data.copy_from_slice(&self.map()?[..data.len()]);
+        Ok(())
+    }
+
+    fn check_fits(&self, len: usize) -> Result<()> {
+        check_len::<T>(len, self.len)
+    }
+
+    /// Downloads the buffer when staged and copies out every value.
+    pub fn to_vec(&mut self, ctx: &VkCtx) -> Result<Vec<T>> {
+        self.memory.download(ctx)?;
+        Ok(self.map()?.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn misaligned_mappings_are_errors() {
+        let mut words = [0u32; 4];
+        let ptr = words.as_mut_ptr() as *mut u8;
+        let (aligned, len) = aligned_parts::<u32>(ptr, 16).unwrap();
+        assert_eq!((aligned as *mut u8, len), (ptr, 4));
+        assert!(matches!(aligned_parts::<u32>(ptr.wrapping_add(2), 12),
+            Err(Error::MemoryMap(vk::Result::ERROR_MEMORY_MAP_FAILED))));
+        assert!(aligned_parts::<u8>(ptr.wrapping_add(1), 15).is_ok());
+    }
+
+    #[test]
+    fn mappings_hold_whole_values() {
+        let mut words = [0u64; 2];
+        let ptr = words.as_mut_ptr() as *mut u8;
+        assert_eq!(aligned_parts::<[u32; 3]>(ptr, 16).unwrap().1, 1);
+        assert_eq!(aligned_parts::<u64>(ptr, 4).unwrap().1, 0);
+    }
+
+    #[test]
+    fn oversized_slices_are_errors() {
+        assert!(check_len::<f32>(4, 4).is_ok());
+        assert!(check_len::<f32>(0, 4).is_ok());
+        let Err(Error::Layout(message)) = check_len::<f32>(5, 4) else { panic!("5 values fit in 4") };
+        assert_eq!(message, "5 values don't fit in a buffer of 4 f32");
+    }
+
+    #[test]
+    fn buffer_sizes_are_checked() {
+        assert_eq!(values_size::<f32>(3).unwrap(), 12);
+        assert_eq!(values_size::<[f32; 4]>(2).unwrap(), 32);
+        assert!(matches!(values_size::<f32>(0), Err(Error::Layout(_))));
+        assert!(matches!(values_size::<u64>(usize::MAX / 4), Err(Error::Layout(_))));
+    }
+}
@@ -0,0 +1,258 @@
+use std::fmt::Write as _;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use ash::vk;
+use sha2::{Digest, Sha256};
+use crate::compile::CompileOptions;
+use crate::device::PhysicalDeviceInfo;
+use crate::error::{Error, Result};
+use crate::loader::{self, ShaderSource};
+
+/// Size of `VkPipelineCacheHeaderVersionOne`: header size, version, vendor, device and UUID.
+const PIPELINE_CACHE_HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+/// A directory that keeps compiled SPIR-V and each device's pipeline cache between runs.
+///
+/// SPIR-V is stored under a hash of the source, its file name, the entry point and the
+/// [`CompileOptions`], together with hashes of the files it included, so editing a header
+/// recompiles everything that includes it. Pipeline cache data is stored per device UUID.
+/// Entries that are missing, corrupt or stale are compiled again, and failing to write an
+/// entry only logs a warning.
+#[derive(Clone, Debug)]
+pub struct ShaderCache {
+    dir: PathBuf,
+}
+
+impl ShaderCache {
+    /// Uses `dir` as the cache directory, creating it if it doesn't exist.
+    pub fn new(dir: impl Into<PathBuf>) -> Result<ShaderCache> {
+        let dir = dir.into();
+        for subdir in [dir.join("spirv"), dir.join("pipelines")] {
+            std::fs::create_dir_all(&subdir).map_err(|error| Error::Io { path: subdir, error })?;
+        }
+        Ok(ShaderCache { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The SPIR-V of `source`, read from the cache when it holds a fresh entry and
+    /// otherwise compiled and stored. SPIR-V sources are returned as they are.
+    pub fn spirv(&self, source: &ShaderSource, entry_point: &str, options: &CompileOptions) -> Result<Vec<u32>> {
+        let ShaderSource::Code { language, code, file_name } = source else {
+            return source.to_spirv_with(entry_point, options);
+        };
+        let mut hasher = DigestHasher(Sha256::new());
+        (env!("CARGO_PKG_VERSION"), language, code, file_name, entry_point, options).hash(&mut hasher);
+        let path = self.dir.join("spirv").join(format!("{}.spv", hex(&hasher.0.finalize())));
+
+        match self.load_spirv(&path) {
+            Ok(Some(spirv)) => {
+                tracing::debug!(file_name, path = %path.display(), "loaded cached SPIR-V");
+                return Ok(spirv);
+            }
+            Ok(None) => {}
+            Err(reason) => tracing::warn!(file_name, path = %path.display(), "ignoring cached SPIR-V: {}", reason),
+        }
+
+        let compiled = source.compile(entry_point, options)?;
+        for warning in &compiled.warnings {
+            tracing::warn!("{}", warning);
+        }
+        let mut deps = String::new();
+        for include in &compiled.includes {
+            match std::fs::read(include) {
+                Ok(content) => { let _ = writeln!(deps, "{} {}", hex(&Sha256::digest(&content)), include.display()); }
+                // The entry couldn't be checked for staleness, so don't store it
+                Err(_) => return Ok(compiled.spirv),
+            }
+        }
+        let bytes = compiled.spirv.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>();
+        if let Err(error) = write_atomic(&path.with_extension("deps"), deps.as_bytes())
+            .and_then(|()| write_atomic(&path, &bytes)) {
+            tracing::warn!(path = %path.display(), %error, "failed to cache SPIR-V");
+        }
+        Ok(compiled.spirv)
+    }
+
+    /// Reads a SPIR-V entry, `None` when there is none and an error when it is stale or corrupt.
+    fn load_spirv(&self, path: &Path) -> std::result::Result<Option<Vec<u32>>, String> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.to_string()),
+        };
+        let deps = std::fs::read_to_string(path.with_extension("deps"))
+            .map_err(|error| format!("failed to read its includes: {}", error))?;
+        for line in deps.lines() {
+            let (hash, include) = line.split_once(' ')
+                .ok_or_else(|| format!("malformed include line {:?}", line))?;
+            let content = std::fs::read(include)
+                .map_err(|error| format!("failed to read {}: {}", include, error))?;
+            if hex(&Sha256::digest(&content)) != hash {
+                return Err(format!("{} changed", include));
+            }
+        }
+        loader::spirv_from_bytes(&bytes).map(Some).map_err(|error| error.to_string())
+    }
+
+    fn pipeline_cache_path(&self, info: &PhysicalDeviceInfo) -> PathBuf {
+        self.dir.join("pipelines").join(format!("{:04x}-{:04x}-{}.bin",
+            info.vendor_id, info.device_id, hex(&info.pipeline_cache_uuid)))
+    }
+
+    /// The device's stored pipeline cache data, or `None` when there is none or its header
+    /// doesn't match the device, such as after a driver update.
+    pub fn load_pipeline_cache(&self, info: &PhysicalDeviceInfo) -> Option<Vec<u8>> {
+        let path = self.pipeline_cache_path(info);
+        let data = std::fs::read(&path).ok()?;
+        match check_pipeline_cache_header(&data, info.vendor_id, info.device_id, &info.pipeline_cache_uuid) {
+            Ok(()) => {
+                tracing::debug!(path = %path.display(), bytes = data.len(), "loaded pipeline cache");
+                Some(data)
+            }
+            Err(reason) => {
+                tracing::warn!(path = %path.display(), "ignoring stale pipeline cache: {}", reason);
+                None
+            }
+        }
+    }
+
+    pub fn store_pipeline_cache(&self, info: &PhysicalDeviceInfo, data: &[u8]) -> Result<()> {
+        let path = self.pipeline_cache_path(info);
+        write_atomic(&path, data).map_err(|error| Error::Io { path: path.clone(), error })?;
+        tracing::debug!(path = %path.display(), bytes = data.len(), "stored pipeline cache");
+        Ok(())
+    }
+}
+
+/// Checks that pipeline cache data starts with a version one header written by the device
+/// with these IDs and pipeline cache UUID.
+fn check_pipeline_cache_header(data: &[u8], vendor_id: u32, device_id: u32, uuid: &[u8; vk::UUID_SIZE])
+    -> std::result::Result<(), String> {
+    if data.len() < PIPELINE_CACHE_HEADER_SIZE {
+        return Err(format!("{} bytes is too short for the header", data.len()));
+    }
+    // Header fields are written least significant byte first
+    let field = |index: usize| u32::from_le_bytes(data[index * 4..index * 4 + 4].try_into().unwrap());
+    let (header_size, version) = (field(0), field(1));
+    let header_uuid = &data[16..PIPELINE_CACHE_HEADER_SIZE];
+    if (header_size as usize) < PIPELINE_CACHE_HEADER_SIZE || header_size as usize > data.len() {
+        Err(format!("header size {} is invalid", header_size))
+    } else if vk::PipelineCacheHeaderVersion::from_raw(version as i32) != vk::PipelineCacheHeaderVersion::ONE {
+        Err(format!("header version {} is unknown", version))
+    } else if field(2) != vendor_id || field(3) != device_id {
+        Err(format!("written by device {:04x}:{:04x}", field(2), field(3)))
+    } else if header_uuid != uuid {
+        Err(format!("written with pipeline cache UUID {}", hex(header_uuid)))
+    } else {
+        Ok(())
+    }
+}
+
+/// Writes to a temporary file and renames it, so readers never see a partly written entry.
+fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let temp = path.with_extension(format!("tmp{}", std::process::id()));
+    std::fs::write(&temp, data)?;
+    std::fs::rename(&temp, path).inspect_err(|_| { let _ = std::fs::remove_file(&temp); })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+/// Feeds [`Hash`] implementations into a SHA-256 digest, which unlike the std hashers is the
+/// same on every run.
+struct DigestHasher(Sha256);
+
+impl Hasher for DigestHasher {
+    fn finish(&self) -> u64 {
+        let digest = self.0.clone().finalize();
+        u64::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VENDOR_ID: u32 = 0x10de;
+    const DEVICE_ID: u32 = 0x2684;
+    const UUID: [u8; vk::UUID_SIZE] = [7; vk::UUID_SIZE];
+
+    /// A version one header for the test device followed by `payload` bytes of cache data.
+    fn cache_data(payload: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for field in [PIPELINE_CACHE_HEADER_SIZE as u32, vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32, VENDOR_ID, DEVICE_ID] {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+        data.extend_from_slice(&UUID);
+        data.resize(data.len() + payload, 0xaa);
+        data
+    }
+
+    fn check(data: &[u8]) -> std::result::Result<(), String> {
+        check_pipeline_cache_header(data, VENDOR_ID, DEVICE_ID, &UUID)
+    }
+
+    fn set_field(data: &mut [u8], index: usize, value: u32) {
+        data[index * 4..index * 4 + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn header_of_this_device_is_accepted() {
+        assert_eq!(check(&cache_data(0)), Ok(()));
+        assert_eq!(check(&cache_data(64)), Ok(()));
+    }
+
+    #[test]
+    fn short_data_is_rejected() {
+        assert_eq!(check(&cache_data(0)[..PIPELINE_CACHE_HEADER_SIZE - 1]), Err("31 bytes is too short for the header".to_string()));
+        assert_eq!(check(&[]), Err("0 bytes is too short for the header".to_string()));
+    }
+
+    #[test]
+    fn header_size_must_fit_the_header_and_the_data() {
+        let mut data = cache_data(8);
+        set_field(&mut data, 0, 16);
+        assert_eq!(check(&data), Err("header size 16 is invalid".to_string()));
+        set_field(&mut data, 0, 41);
+        assert_eq!(check(&data), Err("header size 41 is invalid".to_string()));
+        // A longer header, as a later header version may have, is fine while the data holds it
+        set_field(&mut data, 0, 40);
+        assert_eq!(check(&data), Ok(()));
+    }
+
+    #[test]
+    fn unknown_header_version_is_rejected() {
+        let mut data = cache_data(0);
+        set_field(&mut data, 1, 2);
+        assert_eq!(check(&data), Err("header version 2 is unknown".to_string()));
+    }
+
+    #[test]
+    fn header_of_another_device_is_rejected() {
+        let mut data = cache_data(0);
+        set_field(&mut data, 2, 0x1002);
+        assert_eq!(check(&data), Err("written by device 1002:2684".to_string()));
+
+        let mut data = cache_data(0);
+        set_field(&mut data, 3, 0x1234);
+        assert_eq!(check(&data), Err("written by device 10de:1234".to_string()));
+    }
+
+    #[test]
+    fn header_with_another_uuid_is_rejected() {
+        let mut data = cache_data(0);
+        data[PIPELINE_CACHE_HEADER_SIZE - 1] = 8;
+        assert_eq!(check(&data), Err(format!("written with pipeline cache UUID {}08", "07".repeat(vk::UUID_SIZE - 1))));
+    }
+}
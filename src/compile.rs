@@ -0,0 +1,576 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use crate::error::{Error, Result};
+use crate::loader::ShaderLanguage;
+use crate::specialization::{SpecializationConstants, SpecializationValue};
+
+/// Includes nested deeper than this are reported as an error, which catches include cycles
+/// without a header guard.
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum OptimizationLevel {
+    #[default]
+    Zero,
+    Size,
+    Performance,
+}
+
+/// The Vulkan version shaders are compiled for. It also picks the default SPIR-V version:
+/// 1.0 for Vulkan 1.0, 1.3 for Vulkan 1.1, 1.5 for Vulkan 1.2 and 1.6 for Vulkan 1.3.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TargetEnv {
+    #[default]
+    Vulkan1_0,
+    Vulkan1_1,
+    Vulkan1_2,
+    Vulkan1_3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SpirvVersion {
+    V1_0,
+    V1_1,
+    V1_2,
+    V1_3,
+    V1_4,
+    V1_5,
+    V1_6,
+}
+
+/// The register classes of HLSL resources, named by their register letter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HlslRegisterClass {
+    /// `b`, constant buffers
+    ConstantBuffer,
+    /// `t`, shader resource views such as `StructuredBuffer` and `Texture2D`
+    ShaderResource,
+    /// `s`, samplers
+    Sampler,
+    /// `u`, unordered access views such as `RWStructuredBuffer` and `RWTexture2D`
+    UnorderedAccess,
+}
+
+impl HlslRegisterClass {
+    fn resource_kind(self) -> shaderc::ResourceKind {
+        match self {
+            HlslRegisterClass::ConstantBuffer => shaderc::ResourceKind::Buffer,
+            HlslRegisterClass::ShaderResource => shaderc::ResourceKind::Texture,
+            HlslRegisterClass::Sampler => shaderc::ResourceKind::Sampler,
+            HlslRegisterClass::UnorderedAccess => shaderc::ResourceKind::UnorderedAccessView,
+        }
+    }
+}
+
+/// How HLSL registers map to descriptor bindings. Each class of registers counts from zero,
+/// so `t0` and `u0` would both be binding 0 of their space's set; shifting a class moves its
+/// registers past the others'. A register can also be given a set and binding of its own.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct HlslOptions {
+    /// Added to the register number of every register of the class
+    pub binding_shifts: Vec<(HlslRegisterClass, u32)>,
+    /// Registers such as `"t4"` and the set and binding they are given, ahead of any shift
+    pub register_bindings: Vec<(String, u32, u32)>,
+}
+
+/// How shader source code is compiled to SPIR-V.
+///
+/// `#include "file"` is looked up next to the including file first, when it is a file on
+/// disk, and then in `include_paths`, `#include <file>` only in `include_paths`, in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CompileOptions {
+    /// `#define`s as names and optional values
+    pub macros: Vec<(String, Option<String>)>,
+    pub include_paths: Vec<PathBuf>,
+    pub optimization: OptimizationLevel,
+    pub target_env: TargetEnv,
+    /// Overrides the SPIR-V version `target_env` implies
+    pub spirv_version: Option<SpirvVersion>,
+    /// Keeps names and source lines in the SPIR-V for debuggers and validation messages
+    pub debug_info: bool,
+    /// Only used for HLSL source
+    pub hlsl: HlslOptions,
+    /// Values for WGSL `override` declarations by their `@id`, which naga compiles in while
+    /// translating. Other languages take specialization constants when the pipeline is built.
+    pub wgsl_overrides: SpecializationConstants,
+}
+
+impl CompileOptions {
+    pub fn new() -> CompileOptions {
+        CompileOptions::default()
+    }
+
+    /// Adds `#define name value`, or `#define name` when `value` is `None`.
+    pub fn define(mut self, name: impl Into<String>, value: Option<&str>) -> Self {
+        self.macros.push((name.into(), value.map(String::from)));
+        self
+    }
+
+    pub fn include_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.include_paths.push(path.into());
+        self
+    }
+
+    pub fn optimization(mut self, level: OptimizationLevel) -> Self {
+        self.optimization = level;
+        self
+    }
+
+    pub fn target_env(mut self, target_env: TargetEnv) -> Self {
+        self.target_env = target_env;
+        self
+    }
+
+    pub fn spirv_version(mut self, version: SpirvVersion) -> Self {
+        self.spirv_version = Some(version);
+        self
+    }
+
+    pub fn debug_info(mut self, enabled: bool) -> Self {
+        self.debug_info = enabled;
+        self
+    }
+
+    /// Adds `shift` to the binding of every HLSL register of `class`.
+    pub fn hlsl_binding_shift(mut self, class: HlslRegisterClass, shift: u32) -> Self {
+        self.hlsl.binding_shifts.push((class, shift));
+        self
+    }
+
+    /// Gives the HLSL `register`, such as `"u0"`, the descriptor `set` and `binding`.
+    pub fn hlsl_register_binding(mut self, register: impl Into<String>, set: u32, binding: u32) -> Self {
+        self.hlsl.register_bindings.push((register.into(), set, binding));
+        self
+    }
+
+    /// Sets the WGSL `override` declared with `@id(id)` to `value`.
+    pub fn wgsl_override(mut self, id: u32, value: impl Into<SpecializationValue>) -> Self {
+        self.wgsl_overrides.insert(id, value);
+        self
+    }
+
+    /// Finds and reads the file an `#include` in `requesting_source` refers to.
+    fn resolve_include(
+        &self,
+        requested: &str,
+        include_type: shaderc::IncludeType,
+        requesting_source: &str,
+        depth: usize,
+    ) -> shaderc::IncludeCallbackResult {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(format!("#include nested deeper than {} levels, is {} including itself?",
+                MAX_INCLUDE_DEPTH, requested));
+        }
+        // A source that isn't a file on disk, such as one built in under a bare file name,
+        // has no directory of its own, so its includes are only looked up in the include paths
+        let requesting_path = Path::new(requesting_source);
+        let relative_dir = match include_type {
+            shaderc::IncludeType::Relative if requesting_path.is_file() => requesting_path.parent()
+                .map(|dir| if dir.as_os_str().is_empty() { Path::new(".") } else { dir }),
+            _ => None,
+        };
+        let mut searched = Vec::new();
+        for dir in relative_dir.into_iter().chain(self.include_paths.iter().map(PathBuf::as_path)) {
+            let path = dir.join(requested);
+            if path.is_file() {
+                let content = std::fs::read_to_string(&path)
+                    .map_err(|error| format!("failed to read {}: {}", path.display(), error))?;
+                tracing::trace!(requested, requesting_source, path = %path.display(), "resolved include");
+                return Ok(shaderc::ResolvedInclude { resolved_name: path.display().to_string(), content });
+            }
+            searched.push(dir.display().to_string());
+        }
+        if searched.is_empty() {
+            Err(format!("cannot find {}, there are no include paths", requested))
+        } else {
+            Err(format!("cannot find {} in {}", requested, searched.join(", ")))
+        }
+    }
+
+    /// The shaderc options, with an include callback that records every included file in
+    /// `includes` so diagnostics can quote them.
+    fn to_shaderc<'a>(&'a self, language: shaderc::SourceLanguage, includes: &'a RefCell<HashMap<String, String>>)
+        -> Option<shaderc::CompileOptions<'a>> {
+        let mut options = shaderc::CompileOptions::new()?;
+        options.set_source_language(language);
+        for (name, value) in &self.macros {
+            options.add_macro_definition(name, value.as_deref());
+        }
+        options.set_include_callback(|requested, include_type, requesting_source, depth| {
+            let resolved = self.resolve_include(requested, include_type, requesting_source, depth)?;
+            includes.borrow_mut().insert(resolved.resolved_name.clone(), resolved.content.clone());
+            Ok(resolved)
+        });
+        options.set_optimization_level(match self.optimization {
+            OptimizationLevel::Zero => shaderc::OptimizationLevel::Zero,
+            OptimizationLevel::Size => shaderc::OptimizationLevel::Size,
+            OptimizationLevel::Performance => shaderc::OptimizationLevel::Performance,
+        });
+        let env_version = match self.target_env {
+            TargetEnv::Vulkan1_0 => shaderc::EnvVersion::Vulkan1_0,
+            TargetEnv::Vulkan1_1 => shaderc::EnvVersion::Vulkan1_1,
+            TargetEnv::Vulkan1_2 => shaderc::EnvVersion::Vulkan1_2,
+            TargetEnv::Vulkan1_3 => shaderc::EnvVersion::Vulkan1_3,
+        };
+        options.set_target_env(shaderc::TargetEnv::Vulkan, env_version as u32);
+        if let Some(version) = self.spirv_version {
+            options.set_target_spirv(match version {
+                SpirvVersion::V1_0 => shaderc::SpirvVersion::V1_0,
+                SpirvVersion::V1_1 => shaderc::SpirvVersion::V1_1,
+                SpirvVersion::V1_2 => shaderc::SpirvVersion::V1_2,
+                SpirvVersion::V1_3 => shaderc::SpirvVersion::V1_3,
+                SpirvVersion::V1_4 => shaderc::SpirvVersion::V1_4,
+                SpirvVersion::V1_5 => shaderc::SpirvVersion::V1_5,
+                SpirvVersion::V1_6 => shaderc::SpirvVersion::V1_6,
+            });
+        }
+        if self.debug_info {
+            options.set_generate_debug_info();
+        }
+        if language == shaderc::SourceLanguage::HLSL {
+            // Bindings follow the register numbers, each class counting on its own
+            options.set_hlsl_io_mapping(true);
+            for &(class, shift) in &self.hlsl.binding_shifts {
+                options.set_binding_base(class.resource_kind(), shift);
+            }
+            for (register, set, binding) in &self.hlsl.register_bindings {
+                options.set_hlsl_register_set_and_binding(register, &set.to_string(), &binding.to_string());
+            }
+        }
+        Some(options)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// One error or warning from the compiler. Its `Display` quotes the source line with the
+/// token the message is about underlined, when the line and token can be found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The file the message is about, which is the included file for messages about a header
+    pub file_name: String,
+    /// 1-based line the message is about
+    pub line: Option<u32>,
+    /// 1-based column and length in characters of the token the message is about
+    pub span: Option<(usize, usize)>,
+    pub message: String,
+    /// The line of source code at `line`
+    pub source_line: Option<String>,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(severity: Severity, file_name: &str, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity,
+            file_name: file_name.to_string(),
+            line: None,
+            span: None,
+            message: message.into(),
+            source_line: None,
+        }
+    }
+
+    /// Parses one line of shaderc's output, such as `kernel.comp:12: error: 'x' : undeclared identifier`,
+    /// looking the line up in `sources` by file name.
+    fn parse(output: &str, file_name: &str, sources: &HashMap<String, String>) -> Diagnostic {
+        let parsed = [(": error: ", Severity::Error), (": warning: ", Severity::Warning)].into_iter()
+            .find_map(|(separator, severity)| output.split_once(separator)
+                .map(|(location, message)| (location, severity, message)));
+        let Some((location, severity, message)) = parsed else {
+            return Diagnostic::new(Severity::Error, file_name, output.trim());
+        };
+        // The file name may itself contain colons, so the line number is whatever follows the last one
+        let (file_name, line) = match location.rsplit_once(':') {
+            Some((file, line)) => match line.trim().parse::<u32>() {
+                Ok(line) => (file, Some(line)),
+                Err(_) => (location, None),
+            },
+            None => (location, None),
+        };
+        let mut diagnostic = Diagnostic::new(severity, file_name, message.trim());
+        diagnostic.line = line;
+        diagnostic.source_line = line.and_then(|line| sources.get(file_name)?
+            .lines()
+            .nth(line.checked_sub(1)? as usize)
+            .map(String::from));
+        diagnostic.span = diagnostic.source_line.as_deref().and_then(|source_line| quoted_token_span(message, source_line));
+        diagnostic
+    }
+}
+
+/// Finds the token glslang quotes at the start of its messages, `'token' : message`, in `source_line`.
+fn quoted_token_span(message: &str, source_line: &str) -> Option<(usize, usize)> {
+    let token = message.trim_start().strip_prefix('\'')?.split('\'').next()?.trim();
+    if token.is_empty() {
+        return None;
+    }
+    let byte_offset = source_line.find(token)?;
+    Some((source_line[..byte_offset].chars().count() + 1, token.chars().count()))
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file_name)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some((column, _)) = self.span {
+                write!(f, ":{}", column)?;
+            }
+        }
+        write!(f, ": {}: {}", self.severity, self.message)?;
+
+        let (Some(line), Some(source_line)) = (self.line, &self.source_line) else {
+            return Ok(());
+        };
+        let gutter = " ".repeat(line.to_string().len());
+        write!(f, "\n{} |\n{} | {}", gutter, line, source_line)?;
+        if let Some((column, length)) = self.span {
+            // Keep the tabs before the token so the carets line up however tabs are displayed
+            let indent = source_line.chars()
+                .take(column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect::<String>();
+            write!(f, "\n{} | {}{}", gutter, indent, "^".repeat(length))?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses shaderc's error or warning output, one message per line. The `N errors generated.`
+/// summary it ends with is left out.
+fn parse_diagnostics(output: &str, file_name: &str, sources: &HashMap<String, String>) -> Vec<Diagnostic> {
+    output.lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_end().ends_with(" generated."))
+        .map(|line| Diagnostic::parse(line, file_name, sources))
+        .collect()
+}
+
+/// SPIR-V and the warnings the compiler gave for it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompiledShader {
+    pub spirv: Vec<u32>,
+    pub warnings: Vec<Diagnostic>,
+    /// Every file the source included, directly or through other includes
+    pub includes: Vec<PathBuf>,
+}
+
+/// Compiles compute shader source code to SPIR-V, logging any warnings.
+pub fn compile(
+    language: ShaderLanguage,
+    code: &str,
+    file_name: &str,
+    entry_point: &str,
+    options: &CompileOptions,
+) -> Result<Vec<u32>> {
+    let compiled = compile_with_warnings(language, code, file_name, entry_point, options)?;
+    for warning in &compiled.warnings {
+        tracing::warn!("{}", warning);
+    }
+    Ok(compiled.spirv)
+}
+
+/// Compiles compute shader source code to SPIR-V with shaderc, or translates WGSL with naga,
+/// returning the warnings instead of logging them. Errors are returned as [`Error::ShaderCompile`].
+pub fn compile_with_warnings(
+    language: ShaderLanguage,
+    code: &str,
+    file_name: &str,
+    entry_point: &str,
+    options: &CompileOptions,
+) -> Result<CompiledShader> {
+    let _span = tracing::debug_span!("compile_shader", file_name, entry_point, ?language,
+        optimization = ?options.optimization, target_env = ?options.target_env).entered();
+    let setup_error = |message: &str| Error::ShaderCompile {
+        file_name: file_name.to_string(),
+        error_count: 1,
+        diagnostics: vec![Diagnostic::new(Severity::Error, file_name, message)],
+    };
+    let source_language = match language {
+        ShaderLanguage::Glsl => shaderc::SourceLanguage::GLSL,
+        ShaderLanguage::Hlsl => shaderc::SourceLanguage::HLSL,
+        // WGSL is translated with naga rather than compiled with shaderc
+        #[cfg(feature = "wgsl")]
+        ShaderLanguage::Wgsl => return crate::wgsl::translate(code, file_name, entry_point, options),
+        #[cfg(not(feature = "wgsl"))]
+        ShaderLanguage::Wgsl => return Err(setup_error("WGSL source needs the wgsl feature")),
+    };
+    let compiler = shaderc::Compiler::new()
+        .ok_or_else(|| setup_error("failed to create shader compiler"))?;
+    let includes = RefCell::new(HashMap::new());
+    let shaderc_options = options.to_shaderc(source_language, &includes)
+        .ok_or_else(|| setup_error("failed to create shader compile options"))?;
+    let result = compiler.compile_into_spirv(
+        code,
+        shaderc::ShaderKind::Compute,
+        file_name,
+        entry_point,
+        Some(&shaderc_options));
+    drop(shaderc_options);
+
+    let mut sources = includes.into_inner();
+    let mut included = sources.keys().map(PathBuf::from).collect::<Vec<PathBuf>>();
+    included.sort();
+    sources.insert(file_name.to_string(), code.to_string());
+    match result {
+        Ok(binary_result) => {
+            let warnings = parse_diagnostics(&binary_result.get_warning_messages(), file_name, &sources);
+            tracing::debug!(words = binary_result.len(), warnings = warnings.len(), "compiled shader");
+            Ok(CompiledShader { spirv: binary_result.as_binary().to_vec(), warnings, includes: included })
+        }
+        Err(shaderc::Error::CompilationError(error_count, messages)) => Err(Error::ShaderCompile {
+            file_name: file_name.to_string(),
+            error_count,
+            diagnostics: parse_diagnostics(&messages, file_name, &sources),
+        }),
+        Err(other) => Err(setup_error(&other.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory holding `shaders/kernel.comp`, `shaders/common.glsl` and `include/common.glsl`.
+    fn include_tree(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rscompute-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, content) in [
+            ("shaders/kernel.comp", "#include \"common.glsl\"\n"),
+            ("shaders/common.glsl", "// shaders\n"),
+            ("include/common.glsl", "// include\n"),
+        ] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    fn resolve(options: &CompileOptions, requested: &str, include_type: shaderc::IncludeType,
+               requesting_source: &str) -> shaderc::IncludeCallbackResult {
+        options.resolve_include(requested, include_type, requesting_source, 1)
+    }
+
+    fn kernel_sources() -> HashMap<String, String> {
+        let code = "#version 450\n\tfloat y = x * 2.0;\n    float z = y;\n";
+        HashMap::from([("kernel.comp".to_string(), code.to_string())])
+    }
+
+    #[test]
+    fn diagnostic_points_at_the_quoted_token() {
+        let diagnostic = Diagnostic::parse("kernel.comp:2: error: 'x' : undeclared identifier", "kernel.comp", &kernel_sources());
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.file_name, "kernel.comp");
+        assert_eq!(diagnostic.line, Some(2));
+        assert_eq!(diagnostic.message, "'x' : undeclared identifier");
+        assert_eq!(diagnostic.source_line.as_deref(), Some("\tfloat y = x * 2.0;"));
+        assert_eq!(diagnostic.span, Some((12, 1)));
+        // The tab before the token is kept so the caret lines up under it
+        assert_eq!(diagnostic.to_string(), "kernel.comp:2:12: error: 'x' : undeclared identifier\n  |\n2 | \tfloat y = x * 2.0;\n  | \t          ^");
+    }
+
+    #[test]
+    fn diagnostic_without_a_quoted_token_has_no_span() {
+        let diagnostic = Diagnostic::parse("kernel.comp:3: warning: unused variable", "kernel.comp", &kernel_sources());
+        assert_eq!(diagnostic.severity, Severity::Warning);
+        assert_eq!(diagnostic.line, Some(3));
+        assert_eq!(diagnostic.source_line.as_deref(), Some("    float z = y;"));
+        assert_eq!(diagnostic.span, None);
+    }
+
+    #[test]
+    fn diagnostic_file_name_may_contain_colons() {
+        let diagnostic = Diagnostic::parse("C:\\shaders\\kernel.comp:7: error: 'main' : no such function", "kernel.comp", &kernel_sources());
+        assert_eq!(diagnostic.file_name, "C:\\shaders\\kernel.comp");
+        assert_eq!(diagnostic.line, Some(7));
+        // There is no source for that file to quote
+        assert_eq!(diagnostic.source_line, None);
+    }
+
+    #[test]
+    fn diagnostic_line_past_the_end_has_no_source_line() {
+        let diagnostic = Diagnostic::parse("kernel.comp:40: error: 'x' : undeclared identifier", "kernel.comp", &kernel_sources());
+        assert_eq!(diagnostic.line, Some(40));
+        assert_eq!((diagnostic.source_line, diagnostic.span), (None, None));
+    }
+
+    #[test]
+    fn diagnostic_without_a_line_keeps_its_location_as_the_file_name() {
+        let diagnostic = Diagnostic::parse("kernel.comp: error: #version: bad profile", "other.comp", &kernel_sources());
+        assert_eq!(diagnostic.file_name, "kernel.comp");
+        assert_eq!(diagnostic.line, None);
+        assert_eq!(diagnostic.to_string(), "kernel.comp: error: #version: bad profile");
+    }
+
+    #[test]
+    fn unrecognised_output_is_an_error_for_the_compiled_file() {
+        let diagnostic = Diagnostic::parse("  internal compiler failure  ", "kernel.comp", &kernel_sources());
+        assert_eq!(diagnostic, Diagnostic::new(Severity::Error, "kernel.comp", "internal compiler failure"));
+    }
+
+    #[test]
+    fn error_count_summary_is_left_out() {
+        let output = "kernel.comp:2: error: 'x' : undeclared identifier\n\n1 error generated.\n";
+        let diagnostics = parse_diagnostics(output, "kernel.comp", &kernel_sources());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, Some(2));
+    }
+
+    #[test]
+    fn relative_include_is_found_next_to_the_including_file() {
+        let dir = include_tree("relative-include");
+        let options = CompileOptions::new().include_path(dir.join("include"));
+        let kernel = dir.join("shaders/kernel.comp").display().to_string();
+        let resolved = resolve(&options, "common.glsl", shaderc::IncludeType::Relative, &kernel).unwrap();
+        assert_eq!(resolved.resolved_name, dir.join("shaders/common.glsl").display().to_string());
+        assert_eq!(resolved.content, "// shaders\n");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn standard_include_is_only_found_in_include_paths() {
+        let dir = include_tree("standard-include");
+        let options = CompileOptions::new().include_path(dir.join("include"));
+        let kernel = dir.join("shaders/kernel.comp").display().to_string();
+        let resolved = resolve(&options, "common.glsl", shaderc::IncludeType::Standard, &kernel).unwrap();
+        assert_eq!(resolved.content, "// include\n");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn source_that_isnt_a_file_only_uses_include_paths() {
+        let dir = include_tree("bare-include");
+        let options = CompileOptions::new().include_path(dir.join("include"));
+        let resolved = resolve(&options, "common.glsl", shaderc::IncludeType::Relative, "kernel.comp").unwrap();
+        assert_eq!(resolved.content, "// include\n");
+
+        // Cargo.toml is in the working directory, which isn't the built-in kernel's
+        let error = resolve(&CompileOptions::new(), "Cargo.toml", shaderc::IncludeType::Relative, "kernel.comp").unwrap_err();
+        assert_eq!(error, "cannot find Cargo.toml, there are no include paths");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn include_cycle_is_an_error() {
+        let error = CompileOptions::new()
+            .resolve_include("common.glsl", shaderc::IncludeType::Relative, "kernel.comp", MAX_INCLUDE_DEPTH + 1)
+            .unwrap_err();
+        assert!(error.starts_with("#include nested deeper than"), "{}", error);
+    }
+}
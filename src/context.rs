@@ -1,62 +1,342 @@
-use std::ffi::{CStr, CString};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use ash::vk;
-use ash::vk::{API_VERSION_1_1, DescriptorPoolCreateFlags};
+use ash::vk::API_VERSION_1_1;
 use vk::ApplicationInfo;
+use crate::allocator::{find_memory_type, Allocator, AllocatorStats, BlockAllocator};
+use crate::cache::ShaderCache;
+use crate::compile::CompileOptions;
+use crate::descriptor::{DescriptorAllocator, DescriptorAllocatorConfig, DescriptorStats};
+use crate::device::{enumerate_physical_devices, DeviceSelector, PhysicalDeviceInfo, QueueFamilyInfo};
+use crate::error::{Error, Result};
+use crate::handle::Owned;
+use crate::loader::ShaderSource;
+use crate::validation::{layer_available, validation_from_env, DebugMessenger, ValidationMessages,
+                        VALIDATION_DEFAULT, VALIDATION_LAYER_NAME};
 
-pub struct VkCtx {
-    pub command_buffer: vk::CommandBuffer,
-    pub command_pool: vk::CommandPool,
-    pub device : ash::Device,
-    pub physical_device: vk::PhysicalDevice,
+/// Owns the Vulkan instance along with the loader it was created from.
+pub struct Instance {
+    pub entry: ash::Entry,
     pub instance: ash::Instance,
-    pub queue: vk::Queue,
-    pub descriptor_pool: vk::DescriptorPool,
+    /// Present when validation was enabled
+    pub debug_messenger: Option<DebugMessenger>,
+    /// What the messenger chained into the instance create info reports to. It keeps
+    /// reporting until the instance is destroyed, even when creating `debug_messenger` failed,
+    /// so the messages are held here until then.
+    validation_messages: Option<Arc<ValidationMessages>>,
 }
 
-impl VkCtx {
-    pub fn destroy(&self) {
-        println!("Destroying VkCtx");
+impl Deref for Instance {
+    type Target = ash::Instance;
+
+    fn deref(&self) -> &ash::Instance {
+        &self.instance
+    }
+}
+
+impl Drop for Instance {
+    fn drop(&mut self) {
+        drop(self.debug_messenger.take());
+        tracing::debug!(instance = ?self.instance.handle(), "destroying instance");
+        unsafe { self.instance.destroy_instance(None) };
+        drop(self.validation_messages.take());
+    }
+}
+
+/// Owns the logical device, and the instance it was created from so that the
+/// instance always outlives it. Everything created from the device holds an
+/// `Arc<Device>`, so the device is only destroyed once all of them are gone.
+pub struct Device {
+    pub device: ash::Device,
+    pub physical_device: vk::PhysicalDevice,
+    pub info: PhysicalDeviceInfo,
+    pub instance: Instance,
+}
+
+impl Deref for Device {
+    type Target = ash::Device;
+
+    fn deref(&self) -> &ash::Device {
+        &self.device
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        tracing::debug!(device = ?self.device.handle(), "destroying device");
         unsafe {
-            println!("Destroying descriptor pool {:?}", self.descriptor_pool);
-            self.device.destroy_descriptor_pool(self.descriptor_pool, None);
-            println!("Destroying command buffers {:?}", self.command_buffer);
-            self.device.free_command_buffers(self.command_pool, &[self.command_buffer]);
-            println!("Destroying command pool {:?}", self.command_pool);
-            self.device.destroy_command_pool(self.command_pool, None);
-            println!("Destroying device {:?}", self.device.handle());
+            // Nothing useful can be done if waiting fails, the device is destroyed either way
+            let _ = self.device.device_wait_idle();
             self.device.destroy_device(None);
-            println!("Destroying instance");
-            self.instance.destroy_instance(None);
         }
     }
 }
 
+pub struct VkCtx {
+    pub command_buffer: vk::CommandBuffer,
+    pub command_pool: Owned<vk::CommandPool>,
+    pub queue: vk::Queue,
+    /// The family `queue` and `command_pool` belong to
+    pub queue_family: QueueFamilyInfo,
+    /// Where descriptor sets come from, growing its pools as shaders need more
+    pub descriptor_allocator: Arc<DescriptorAllocator>,
+    /// How long to wait on a fence for submitted work, in nanoseconds
+    pub fence_timeout: u64,
+    /// Where buffer memory comes from, a [`BlockAllocator`] unless the builder was given another
+    pub allocator: Arc<dyn Allocator>,
+    /// Every pipeline is created through this cache, which starts from the shader cache's data
+    pub pipeline_cache: Owned<vk::PipelineCache>,
+    /// Keeps compiled SPIR-V and pipeline cache data between runs, when the builder was given a directory
+    pub shader_cache: Option<ShaderCache>,
+    pub device: Arc<Device>,
+}
+
 impl VkCtx {
-    pub unsafe fn create_compute_ctx(entry: &ash::Entry) -> Result<VkCtx, ash::LoadingError> {
-        // set up validation layers
-
-        let layer_names = [CString::new("VK_LAYER_KHRONOS_validation").expect("Failed to create CString")];
-
-        // Check if validation layers are available
-
-        let layer_properties = unsafe { entry.enumerate_instance_layer_properties() }
-            .expect("Failed to enumerate instance layer properties");
-        for layer_name in layer_names.iter() {
-            let mut layer_found = false;
-            for layer_property in layer_properties.iter() {
-                let test_layer_name = unsafe { CStr::from_ptr(layer_property.layer_name.as_ptr()) };
-                if test_layer_name == layer_name.as_c_str() {
-                    layer_found = true;
-                    break;
+    /// Creates a context on the default device, use [`VkCtx::builder`] to choose the device.
+    pub fn create_compute_ctx(entry: &ash::Entry) -> Result<VkCtx> {
+        VkCtx::builder().entry(entry.clone()).build()
+    }
+
+    pub fn builder() -> VkCtxBuilder {
+        VkCtxBuilder::default()
+    }
+
+    /// Messages from the validation layer, when validation is enabled.
+    pub fn validation_messages(&self) -> Option<&Arc<ValidationMessages>> {
+        self.device.instance.debug_messenger.as_ref().map(|messenger| messenger.messages())
+    }
+
+    /// Describes the physical device this context was created on.
+    pub fn device_info(&self) -> &PhysicalDeviceInfo {
+        &self.device.info
+    }
+
+    pub fn create_shader_module(&self, source: Vec<u32>) -> Result<Owned<vk::ShaderModule>> {
+        let shader_info = vk::ShaderModuleCreateInfo {
+            code_size: source.len() * 4,
+            p_code: source.as_ptr(),
+            ..Default::default()
+        };
+        let shader_module = unsafe {
+            self.device.create_shader_module(&shader_info, None)
+        }.map_err(|r| Error::from_vk(r, Error::Pipeline))?;
+        tracing::debug!(?shader_module, words = source.len(), "created shader module");
+        Ok(Owned::new(&self.device, shader_module))
+    }
+
+    pub fn find_memory_type(
+        &self,
+        memory_type_bits: u32,
+        flags: vk::MemoryPropertyFlags) -> Option<u32> {
+        let memory_properties = unsafe {
+            self.device.instance.get_physical_device_memory_properties(self.device.physical_device)
+        };
+        find_memory_type(&memory_properties, memory_type_bits, flags)
+    }
+
+    /// Records commands into the context's command buffer, submits them to its queue
+    /// and waits for them to finish, failing with [`Error::Timeout`] after `fence_timeout`.
+    /// After a timeout the device is waited on until idle before returning, since the fence
+    /// can't be destroyed nor the command buffer re-recorded while the submission is pending.
+    pub fn execute_commands<F>(&self, record: F) -> Result<()>
+        where F: FnOnce(&ash::Device, vk::CommandBuffer) {
+        let command_buffer = self.command_buffer;
+        unsafe {
+            let fence = Owned::new(&self.device,
+                self.device.create_fence(&vk::FenceCreateInfo::default(), None)
+                    .map_err(|r| Error::from_vk(r, Error::Allocation))?);
+            self.device.begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo {
+                flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                ..Default::default()
+            }).map_err(|r| Error::from_vk(r, Error::Submission))?;
+            record(&self.device, command_buffer);
+            self.device.end_command_buffer(command_buffer)
+                .map_err(|r| Error::from_vk(r, Error::Submission))?;
+            self.device.queue_submit(self.queue, &[vk::SubmitInfo {
+                command_buffer_count: 1,
+                p_command_buffers: &command_buffer,
+                ..Default::default()
+            }], *fence)
+                .map_err(|r| Error::from_vk(r, Error::Submission))?;
+            tracing::trace!(fence = ?*fence, "waiting for submitted commands");
+            if let Err(result) = self.device.wait_for_fences(&[*fence], true, self.fence_timeout) {
+                if let Err(idle_result) = self.device.device_wait_idle() {
+                    tracing::warn!(?idle_result, "failed to wait for a submission that timed out");
                 }
-            }
-            if layer_found == false {
-                panic!("Validation layer {:?} not available", layer_name);
+                return Err(Error::from_vk(result, Error::Submission));
             }
         }
+        Ok(())
+    }
 
-        let app_name = CString::new("matrix-multiplication").expect("Failed to create CString");
-        let engine_name = CString::new("No Engine").expect("Failed to create CString");
+    /// How much device memory the context's allocator holds, and how much of it is in use.
+    pub fn memory_stats(&self) -> AllocatorStats {
+        self.allocator.stats()
+    }
+
+    /// How many descriptor pools and sets the context holds.
+    pub fn descriptor_stats(&self) -> DescriptorStats {
+        self.descriptor_allocator.stats()
+    }
+
+    /// The SPIR-V of `source`, compiled for `entry_point` or read from the shader cache.
+    pub fn compile_shader(&self, source: &ShaderSource, entry_point: &str, options: &CompileOptions) -> Result<Vec<u32>> {
+        match &self.shader_cache {
+            Some(cache) => cache.spirv(source, entry_point, options),
+            None => source.to_spirv_with(entry_point, options),
+        }
+    }
+
+    /// Writes the pipeline cache's data to the shader cache, which also happens when the
+    /// context is dropped. Does nothing without a shader cache.
+    pub fn save_pipeline_cache(&self) -> Result<()> {
+        let Some(cache) = &self.shader_cache else {
+            return Ok(());
+        };
+        let data = unsafe { self.device.get_pipeline_cache_data(*self.pipeline_cache) }
+            .map_err(|r| Error::from_vk(r, Error::Pipeline))?;
+        cache.store_pipeline_cache(self.device_info(), &data)
+    }
+}
+
+impl Drop for VkCtx {
+    fn drop(&mut self) {
+        if let Err(error) = self.save_pipeline_cache() {
+            tracing::warn!(%error, "failed to save the pipeline cache");
+        }
+    }
+}
+
+/// Creates the allocator for a context once its device exists.
+pub type AllocatorFactory = dyn FnOnce(&Arc<Device>) -> Arc<dyn Allocator>;
+
+/// Configures and creates a [`VkCtx`].
+///
+/// The physical device is chosen by the [`DeviceSelector`], unless the
+/// [`crate::device::DEVICE_ENV_VAR`] environment variable is set, which always takes precedence
+/// so CI can force a software device like lavapipe.
+#[derive(Default)]
+pub struct VkCtxBuilder {
+    entry: Option<ash::Entry>,
+    selector: DeviceSelector,
+    fence_timeout: Option<Duration>,
+    validation: Option<bool>,
+    collect_validation_messages: bool,
+    allocator: Option<Box<AllocatorFactory>>,
+    descriptor_allocator: DescriptorAllocatorConfig,
+    shader_cache: Option<PathBuf>,
+}
+
+impl VkCtxBuilder {
+    /// Uses an already loaded Vulkan library instead of loading it in [`VkCtxBuilder::build`].
+    pub fn entry(mut self, entry: ash::Entry) -> Self {
+        self.entry = Some(entry);
+        self
+    }
+
+    pub fn select_device(mut self, selector: DeviceSelector) -> Self {
+        self.selector = selector;
+        self
+    }
+
+    /// Selects the device at `index` in [`VkCtxBuilder::enumerate_devices`].
+    pub fn device_index(self, index: usize) -> Self {
+        self.select_device(DeviceSelector::Index(index))
+    }
+
+    /// Selects the first device whose name contains `name`, ignoring case.
+    pub fn device_name(self, name: impl Into<String>) -> Self {
+        self.select_device(DeviceSelector::Name(name.into()))
+    }
+
+    /// Selects the first device of the earliest type in `device_types`.
+    pub fn prefer_device_types(self, device_types: &[vk::PhysicalDeviceType]) -> Self {
+        self.select_device(DeviceSelector::DeviceType(device_types.to_vec()))
+    }
+
+    /// Selects the device with the highest score, never selecting devices scored `None`.
+    pub fn device_score<F>(self, score: F) -> Self
+        where F: Fn(&PhysicalDeviceInfo) -> Option<u64> + 'static {
+        self.select_device(DeviceSelector::Score(Box::new(score)))
+    }
+
+    /// How long to wait for submitted work before failing with [`Error::Timeout`].
+    pub fn fence_timeout(mut self, timeout: Duration) -> Self {
+        self.fence_timeout = Some(timeout);
+        self
+    }
+
+    /// Enables `VK_LAYER_KHRONOS_validation` and forwards its messages to `tracing`.
+    ///
+    /// Defaults to on when the `validation` feature is enabled, and is overridden by
+    /// [`crate::validation::VALIDATION_ENV_VAR`]. Building fails with
+    /// `ERROR_LAYER_NOT_PRESENT` when validation is enabled but the layer is not installed.
+    pub fn validation(mut self, enabled: bool) -> Self {
+        self.validation = Some(enabled);
+        self
+    }
+
+    /// Keeps validation messages for [`VkCtx::validation_messages`] as well as logging them.
+    pub fn collect_validation_messages(mut self, collect: bool) -> Self {
+        self.collect_validation_messages = collect;
+        self
+    }
+
+    /// Replaces the default [`BlockAllocator`] with the allocator returned by `allocator`.
+    pub fn allocator<F>(mut self, allocator: F) -> Self
+        where F: FnOnce(&Arc<Device>) -> Arc<dyn Allocator> + 'static {
+        self.allocator = Some(Box::new(allocator));
+        self
+    }
+
+    /// Sizes the descriptor pools the context allocates sets from.
+    pub fn descriptor_allocator(mut self, config: DescriptorAllocatorConfig) -> Self {
+        self.descriptor_allocator = config;
+        self
+    }
+
+    /// Keeps compiled SPIR-V and pipeline cache data in `dir` between runs, see [`ShaderCache`].
+    pub fn shader_cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.shader_cache = Some(dir.into());
+        self
+    }
+
+    fn validation_enabled(&self) -> bool {
+        validation_from_env()
+            .or(self.validation)
+            .unwrap_or(VALIDATION_DEFAULT)
+    }
+
+    /// Lists the physical devices that [`VkCtxBuilder::build`] would choose from.
+    pub fn enumerate_devices(&self) -> Result<Vec<PhysicalDeviceInfo>> {
+        let instance = self.create_instance()?;
+        enumerate_physical_devices(&instance)
+    }
+
+    fn load_entry(&self) -> Result<ash::Entry> {
+        match &self.entry {
+            Some(entry) => Ok(entry.clone()),
+            None => Ok(unsafe { ash::Entry::load() }?),
+        }
+    }
+
+    fn create_instance(&self) -> Result<Instance> {
+        let entry = self.load_entry()?;
+
+        let validation = self.validation_enabled();
+        if validation && !layer_available(&entry, VALIDATION_LAYER_NAME)? {
+            return Err(Error::Instance(vk::Result::ERROR_LAYER_NOT_PRESENT));
+        }
+
+        let layer_names = if validation { vec![VALIDATION_LAYER_NAME.as_ptr()] } else { vec![] };
+        let extension_names = if validation { vec![ash::ext::debug_utils::NAME.as_ptr()] } else { vec![] };
+        let validation_messages = Arc::new(ValidationMessages::new(self.collect_validation_messages));
+        let mut debug_create_info = DebugMessenger::create_info(&validation_messages);
+
+        let app_name = c"matrix-multiplication";
+        let engine_name = c"No Engine";
 
         let app_info = ApplicationInfo {
             p_application_name : app_name.as_ptr(),
@@ -67,29 +347,50 @@ impl VkCtx {
             ..Default::default()
         };
 
-        let create_info = vk::InstanceCreateInfo {
+        let mut create_info = vk::InstanceCreateInfo {
             p_application_info: &app_info,
             enabled_layer_count: layer_names.len() as u32,
-            pp_enabled_layer_names: layer_names.as_ptr() as *const *const std::ffi::c_char,
+            pp_enabled_layer_names: layer_names.as_ptr(),
+            enabled_extension_count: extension_names.len() as u32,
+            pp_enabled_extension_names: extension_names.as_ptr(),
             ..Default::default()
         };
+        if validation {
+            create_info = create_info.push_next(&mut debug_create_info);
+        }
 
-        print!("Creating instance... ");
-        let instance = unsafe { entry.create_instance(&create_info, None) }.expect("Failed to create instance");
-        println!("handle is {:?}", instance.handle());
-        print!("Getting a Physical Device... ");
-        let physical_device = unsafe { instance.enumerate_physical_devices() }
-            .expect("Failed to enumerate physical devices")
-            .into_iter()
-            .next()
-            .expect("No physical devices found");
-        println!("found {:?}", physical_device);
+        let mut instance = Instance {
+            entry: entry.clone(),
+            instance: unsafe { entry.create_instance(&create_info, None) }.map_err(Error::Instance)?,
+            debug_messenger: None,
+            validation_messages: validation.then(|| validation_messages.clone()),
+        };
+        tracing::debug!(instance = ?instance.handle(), validation, "created instance");
+        if validation {
+            instance.debug_messenger = Some(DebugMessenger::new(&entry, &instance, validation_messages)?);
+        }
+        Ok(instance)
+    }
+
+    pub fn build(self) -> Result<VkCtx> {
+        let _span = tracing::info_span!("create_context").entered();
+        let instance = self.create_instance()?;
 
+        let devices = enumerate_physical_devices(&instance)?
+            .into_iter()
+            .filter(|device| device.compute_queue_family().is_some())
+            .collect::<Vec<_>>();
+        let env_selector = DeviceSelector::from_env().transpose()?;
+        let selector = env_selector.as_ref().unwrap_or(&self.selector);
+        let info = devices[selector.select(&devices)?].clone();
+        let physical_device = info.handle;
+        let queue_family = *info.compute_queue_family()
+            .ok_or_else(|| Error::DeviceSelection(format!("{} has no compute queue family", info.name)))?;
+        tracing::info!(device = %info, "selected physical device");
 
-        print!("Creating device... ");
         let device = unsafe {
             let queue_create_info = vk::DeviceQueueCreateInfo {
-                queue_family_index: 0,
+                queue_family_index: queue_family.index,
                 queue_count: 1,
                 p_queue_priorities: &1.0f32,
                 ..Default::default()
@@ -100,93 +401,78 @@ impl VkCtx {
                 ..Default::default()
             };
             instance.create_device(physical_device, &device_create_info, None)
-        }.expect("Failed to create device");
-        println!("handle is {:?}", device.handle());
+        }.map_err(|r| Error::from_vk(r, Error::Device))?;
+        let device = Arc::new(Device {
+            device,
+            physical_device,
+            info,
+            instance,
+        });
+        tracing::debug!(device = ?device.handle(), "created device");
 
-        print!("Getting a queue... ");
-        let queue = unsafe { device.get_device_queue(0, 0) };
-        println!("got queue {:?}", queue);
+        let queue = unsafe { device.get_device_queue(queue_family.index, 0) };
+        tracing::debug!(?queue, queue_family = queue_family.index, flags = ?queue_family.flags, "got queue");
 
-        print!("Creating command pool... ");
         let command_pool = unsafe {
             let command_pool_create_info = vk::CommandPoolCreateInfo {
-                queue_family_index: 0,
+                queue_family_index: queue_family.index,
+                // The one command buffer is re-recorded for every submission
+                flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
                 ..Default::default()
             };
-            device.create_command_pool(&command_pool_create_info, None)
-        }.expect("Failed to create command pool");
-        println!("handle is {:?}", command_pool);
+            Owned::new(&device, device.create_command_pool(&command_pool_create_info, None)
+                .map_err(|r| Error::from_vk(r, Error::Device))?)
+        };
+        tracing::debug!(?command_pool, "created command pool");
 
-        print!("Creating command buffer... ");
         let command_buffer = unsafe {
             let command_buffer_allocate_info = vk::CommandBufferAllocateInfo {
-                command_pool,
+                command_pool: *command_pool,
                 level: vk::CommandBufferLevel::PRIMARY,
                 command_buffer_count: 1,
                 ..Default::default()
             };
             device.allocate_command_buffers(&command_buffer_allocate_info)
-        }.expect("Failed to allocate command buffer")[0];
-        println!("handle is {:?}", command_buffer);
-        print!("Creating descriptor pool... ");
-        let descriptor_pool = unsafe {
-            let pool_sizes = [
-                vk::DescriptorPoolSize {
-                    ty: vk::DescriptorType::STORAGE_BUFFER,
-                    descriptor_count: 3,
-                },
-            ];
-            let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo {
-                max_sets: 1,
-                pool_size_count: pool_sizes.len() as u32,
-                p_pool_sizes: pool_sizes.as_ptr(),
-                // VK_DESCRIPTOR_POOL_CREATE_FREE_DESCRIPTOR_SET_BIT
-                flags: DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
-                ..Default::default()
-            };
-            device.create_descriptor_pool(&descriptor_pool_create_info, None)
-        }.expect("Failed to create descriptor pool");
-        println!("handle is {:?}", descriptor_pool);
+        }.map_err(|r| Error::from_vk(r, Error::Allocation))?[0];
+        tracing::debug!(?command_buffer, "allocated command buffer");
+        let descriptor_allocator = Arc::new(DescriptorAllocator::new(&device, self.descriptor_allocator));
+
+        let allocator = match self.allocator {
+            Some(allocator) => allocator(&device),
+            None => Arc::new(BlockAllocator::new(&device, Default::default())),
+        };
+
+        let shader_cache = self.shader_cache.map(ShaderCache::new).transpose()?;
+        let initial_data = shader_cache.as_ref()
+            .and_then(|cache| cache.load_pipeline_cache(&device.info))
+            .unwrap_or_default();
+        let create_pipeline_cache = |initial_data: &[u8]| unsafe {
+            device.create_pipeline_cache(&vk::PipelineCacheCreateInfo::default().initial_data(initial_data), None)
+        };
+        let pipeline_cache = match create_pipeline_cache(&initial_data) {
+            // A driver may still reject data whose header matched, so start over with an empty cache
+            Err(r) if !initial_data.is_empty() => {
+                tracing::warn!(result = %r, "the driver rejected the stored pipeline cache");
+                create_pipeline_cache(&[])
+            }
+            result => result,
+        }.map_err(|r| Error::from_vk(r, Error::Device))?;
+        tracing::debug!(?pipeline_cache, initial_bytes = initial_data.len(), "created pipeline cache");
 
         let ctx = VkCtx {
             command_buffer,
             command_pool,
-            device,
-            physical_device,
-            instance,
             queue,
-            descriptor_pool,
+            queue_family,
+            descriptor_allocator,
+            fence_timeout: self.fence_timeout
+                .map_or(u64::MAX, |timeout| timeout.as_nanos().min(u64::MAX as u128) as u64),
+            allocator,
+            pipeline_cache: Owned::new(&device, pipeline_cache),
+            shader_cache,
+            device,
         };
 
         Ok(ctx)
     }
-
-    pub fn create_shader_module(&self, source: Vec<u32>) -> Result<vk::ShaderModule, vk::Result> {
-        println!("Creating shader module");
-        let shader_info = vk::ShaderModuleCreateInfo {
-            code_size: source.len() * 4,
-            p_code: source.as_ptr(),
-            ..Default::default()
-        };
-        unsafe {
-            self.device.create_shader_module(&shader_info, None)
-        }
-    }
-
-
-    pub unsafe fn find_memory_type(
-        &self,
-        memory_type_bits: u32,
-        flags: vk::MemoryPropertyFlags) -> Option<u32> {
-        let memory_properties = unsafe {
-            self.instance.get_physical_device_memory_properties(self.physical_device)
-        };
-        for i in 0..memory_properties.memory_type_count {
-            if (memory_type_bits & (1 << i)) != 0
-                && (memory_properties.memory_types[i as usize].property_flags & flags) == flags {
-                return Some(i);
-            }
-        }
-        None
-    }
-}
\ No newline at end of file
+}
@@ -1,63 +1,353 @@
+use crate::allocator::{AllocationRequest, OwnedAllocation};
 use crate::context::VkCtx;
-use ash;
-use ash::vk::{Buffer, DeviceMemory};
+use crate::device::PhysicalDeviceInfo;
+use crate::error::{Error, Result};
+use crate::handle::Owned;
+use crate::image::{check_format_features, texel_size, LinkedImage};
+use ash::vk::{self, Buffer, BufferView};
 
-#[derive(Clone, Debug)]
+/// Where a buffer's memory should live.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryPlacement {
+    /// Device-local memory, mapped directly on unified memory devices and
+    /// staged through host-visible buffers everywhere else
+    #[default]
+    Auto,
+    /// Device-local memory, which is only mapped directly if the chosen memory type
+    /// happens to be host-visible
+    DeviceLocal,
+    /// Host-visible memory that the shader accesses over the bus, coherent when available
+    HostVisible,
+    /// Host-visible memory that is also cached on the host, for results read back by the CPU.
+    /// Cached memory is often not coherent, in which case reads are invalidated first.
+    HostCachedReadback,
+}
+
+impl MemoryPlacement {
+    /// The required and preferred memory flags for this placement.
+    pub fn memory_flags(self, device_info: &PhysicalDeviceInfo) -> (vk::MemoryPropertyFlags, vk::MemoryPropertyFlags) {
+        let host_visible = vk::MemoryPropertyFlags::HOST_VISIBLE;
+        let host_coherent = vk::MemoryPropertyFlags::HOST_COHERENT;
+        match self {
+            MemoryPlacement::Auto if device_info.is_unified_memory() =>
+                (vk::MemoryPropertyFlags::DEVICE_LOCAL, host_visible | host_coherent),
+            MemoryPlacement::Auto | MemoryPlacement::DeviceLocal =>
+                (vk::MemoryPropertyFlags::DEVICE_LOCAL, vk::MemoryPropertyFlags::empty()),
+            MemoryPlacement::HostVisible => (host_visible, host_coherent),
+            MemoryPlacement::HostCachedReadback => (host_visible, vk::MemoryPropertyFlags::HOST_CACHED),
+        }
+    }
+}
+
+/// A host-visible copy of a buffer whose own memory cannot be mapped. Data is copied
+/// between the two with `vkCmdCopyBuffer` before the shader runs and after it finishes.
+#[derive(Debug)]
+pub struct StagingBuffer {
+    pub buffer: Owned<Buffer>,
+    pub memory: OwnedAllocation,
+}
+
+/// A buffer and the memory bound to it. The buffer is destroyed before its memory is returned
+/// to the allocator when this is dropped.
+#[derive(Debug)]
 pub struct LinkedMemory {
     pub binding: u32,
-    pub buffer: Buffer,
-    pub memory: DeviceMemory,
+    pub size: u64,
+    /// The buffer viewed as texels, for texel buffer bindings
+    pub texel_view: Option<Owned<BufferView>>,
+    pub buffer: Owned<Buffer>,
+    pub memory: OwnedAllocation,
+    /// Present when `memory` is not host-visible
+    pub staging: Option<StagingBuffer>,
+    /// For image bindings, the image the buffer's texels are copied to and from
+    pub image: Option<LinkedImage>,
+}
+
+/// The buffer usage a binding of `descriptor_type` needs, empty for non-buffer bindings.
+pub(crate) fn buffer_usage(descriptor_type: vk::DescriptorType) -> vk::BufferUsageFlags {
+    match descriptor_type {
+        vk::DescriptorType::STORAGE_BUFFER => vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::DescriptorType::UNIFORM_BUFFER => vk::BufferUsageFlags::UNIFORM_BUFFER,
+        vk::DescriptorType::UNIFORM_TEXEL_BUFFER => vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER,
+        vk::DescriptorType::STORAGE_TEXEL_BUFFER => vk::BufferUsageFlags::STORAGE_TEXEL_BUFFER,
+        _ => vk::BufferUsageFlags::empty(),
+    }
 }
 
 impl LinkedMemory {
-    pub fn destroy(&self, device: &ash::Device) {
-        unsafe {
-            println!("Destroying buffer {:?} and freeing memory {:?}", self.buffer, self.memory);
-            device.destroy_buffer(self.buffer, None);
-            device.free_memory(self.memory, None);
+    /// Creates a storage buffer with memory for `placement`, adding a staging buffer when that
+    /// memory cannot be mapped. `readback` marks buffers whose contents are read by the host,
+    /// which makes their staging buffers prefer host-cached memory.
+    pub fn new(ctx: &VkCtx, binding: u32, size: u64, placement: MemoryPlacement, readback: bool) -> Result<LinkedMemory> {
+        LinkedMemory::with_usage(ctx, binding, size, vk::BufferUsageFlags::STORAGE_BUFFER, placement, readback)
+    }
+
+    /// Creates a buffer like [`LinkedMemory::new`] with `usage` instead of `STORAGE_BUFFER`.
+    /// Transfers are always allowed.
+    pub fn with_usage(
+        ctx: &VkCtx,
+        binding: u32,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+        placement: MemoryPlacement,
+        readback: bool) -> Result<LinkedMemory> {
+        let (required_flags, preferred_flags) = placement.memory_flags(ctx.device_info());
+        let (buffer, memory) = create_buffer(ctx, size,
+            usage
+                | vk::BufferUsageFlags::TRANSFER_SRC
+                | vk::BufferUsageFlags::TRANSFER_DST,
+            required_flags, preferred_flags)?;
+
+        let staging = match memory.mapped_ptr {
+            Some(_) => None,
+            None => {
+                let (required_flags, preferred_flags) = if readback {
+                    MemoryPlacement::HostCachedReadback.memory_flags(ctx.device_info())
+                } else {
+                    MemoryPlacement::HostVisible.memory_flags(ctx.device_info())
+                };
+                let (buffer, memory) = create_buffer(ctx, size,
+                    vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST,
+                    required_flags, preferred_flags)?;
+                tracing::debug!(buffer = ?*buffer, binding, "created staging buffer");
+                Some(StagingBuffer { buffer, memory })
+            }
+        };
+
+        Ok(LinkedMemory { binding, size, texel_view: None, buffer, memory, staging, image: None })
+    }
+
+    /// Creates a buffer for a uniform or storage texel buffer binding, and a view of it as
+    /// texels of `format`.
+    pub fn texel_buffer(
+        ctx: &VkCtx,
+        binding: u32,
+        size: u64,
+        format: vk::Format,
+        descriptor_type: vk::DescriptorType,
+        placement: MemoryPlacement,
+        readback: bool) -> Result<LinkedMemory> {
+        let (usage, features) = match descriptor_type {
+            vk::DescriptorType::UNIFORM_TEXEL_BUFFER => (vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER, vk::FormatFeatureFlags::UNIFORM_TEXEL_BUFFER),
+            vk::DescriptorType::STORAGE_TEXEL_BUFFER => (vk::BufferUsageFlags::STORAGE_TEXEL_BUFFER, vk::FormatFeatureFlags::STORAGE_TEXEL_BUFFER),
+            other => return Err(Error::Layout(format!("a {:?} binding is not a texel buffer", other))),
+        };
+        check_format_features(ctx, format, features, true)?;
+        let texels = texel_size(format)
+            .ok_or(Error::UnsupportedFormat { format, features: vk::FormatFeatureFlags::empty() })
+            .map(|texel_size| size / texel_size)?;
+        let max_texels = ctx.device_info().limits.max_texel_buffer_elements as u64;
+        if texels > max_texels {
+            return Err(Error::Layout(format!("{} texels exceed the device's limit of {} per texel buffer", texels, max_texels)));
         }
+
+        let mut memory = LinkedMemory::with_usage(ctx, binding, size, usage, placement, readback)?;
+        let view = unsafe {
+            ctx.device.create_buffer_view(&vk::BufferViewCreateInfo {
+                buffer: *memory.buffer,
+                format,
+                offset: 0,
+                range: vk::WHOLE_SIZE,
+                ..Default::default()
+            }, None)
+        }.map_err(|r| Error::from_vk(r, Error::Allocation))?;
+        tracing::debug!(buffer = ?*memory.buffer, ?view, ?format, binding, "created texel buffer view");
+        memory.texel_view = Some(Owned::new(&ctx.device, view));
+        Ok(memory)
+    }
+
+    /// Whether reads and writes go through a copy: a staging buffer, or the image of an image binding.
+    pub fn is_staged(&self) -> bool {
+        self.staging.is_some() || self.image.is_some()
+    }
+
+    /// The allocation the host reads and writes, which is the staging buffer's when staged.
+    pub fn mapped_memory(&self) -> &OwnedAllocation {
+        self.staging.as_ref().map_or(&self.memory, |staging| &staging.memory)
     }
 
-    pub fn get_mapped_pointer<'a, T>(&self, ctx: &VkCtx, length: u64) -> MappedMemoryPointer<'a, T> {
-        let ptr = unsafe {
-            println!("Mapping memory {:?}", self.memory);
-            ctx.device.map_memory(
-                self.memory,
-                0,
-                std::mem::size_of::<T>() as u64 * length,
-                ash::vk::MemoryMapFlags::empty())
-        }.expect("Failed to map pointer for host memory") as *mut T;
-
-        MappedMemoryPointer {
-            ptr,
-            device: ctx.device.clone(),
-            memory: self.memory,
-            _marker: std::marker::PhantomData,
+    /// Makes host writes to the mapped memory visible to the device. Does nothing for
+    /// coherent memory, and [`LinkedMemory::map_mut`] already flushes when its guard is dropped.
+    pub fn flush(&self, ctx: &VkCtx) -> Result<()> {
+        self.mapped_memory().flush(&ctx.device, 0, self.size)
+    }
+
+    /// Makes device writes visible to host reads of the mapped memory. Does nothing for
+    /// coherent memory, and [`LinkedMemory::map`] already invalidates.
+    pub fn invalidate(&self, ctx: &VkCtx) -> Result<()> {
+        self.mapped_memory().invalidate(&ctx.device, 0, self.size)
+    }
+
+    /// Records a copy from the staging buffer into the buffer, or from the buffer into the
+    /// image, if it is staged.
+    pub fn record_upload(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        if let Some(staging) = &self.staging {
+            unsafe {
+                device.cmd_copy_buffer(command_buffer, *staging.buffer, *self.buffer,
+                    &[vk::BufferCopy { src_offset: 0, dst_offset: 0, size: self.size }]);
+            }
+        }
+        if let Some(image) = &self.image {
+            image.record_upload(device, command_buffer, *self.buffer);
         }
     }
-}
 
-pub struct MappedMemoryPointer<'a, T> {
-    pub ptr: *mut T,
-    pub device: ash::Device,
-    pub memory: DeviceMemory,
-    _marker: std::marker::PhantomData<&'a ()>,
+    /// Records a copy from the buffer into its staging buffer, or from the image into the
+    /// buffer, if it is staged.
+    pub fn record_download(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        if let Some(image) = &self.image {
+            image.record_download(device, command_buffer, *self.buffer);
+        }
+        if let Some(staging) = &self.staging {
+            unsafe {
+                device.cmd_copy_buffer(command_buffer, *self.buffer, *staging.buffer,
+                    &[vk::BufferCopy { src_offset: 0, dst_offset: 0, size: self.size }]);
+            }
+        }
+    }
 }
 
-impl<'a, T> MappedMemoryPointer<'a, T> {
-    pub fn unmap(&self) {
-        unsafe {
-            println!("Unmapping memory {:?}", self.memory);
-            self.device.unmap_memory(self.memory)
+/// Creates a buffer and binds it to memory from the context's allocator.
+fn create_buffer(
+    ctx: &VkCtx,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+    required_flags: vk::MemoryPropertyFlags,
+    preferred_flags: vk::MemoryPropertyFlags) -> Result<(Owned<Buffer>, OwnedAllocation)> {
+    let buffer = unsafe {
+        let buffer_info = vk::BufferCreateInfo {
+            size,
+            usage,
+            ..Default::default()
         };
-    }
+
+        Owned::new(&ctx.device, ctx.device.create_buffer(&buffer_info, None)
+            .map_err(|r| Error::from_vk(r, Error::Allocation))?)
+    };
+    tracing::debug!(buffer = ?*buffer, size, ?usage, "created buffer");
+
+    let mut dedicated_requirements = vk::MemoryDedicatedRequirements::default();
+    let mut requirements = vk::MemoryRequirements2::default()
+        .push_next(&mut dedicated_requirements);
+    unsafe {
+        ctx.device.get_buffer_memory_requirements2(
+            &vk::BufferMemoryRequirementsInfo2::default().buffer(*buffer),
+            &mut requirements)
+    };
+    let requirements = requirements.memory_requirements;
+
+    let memory = OwnedAllocation::allocate(&ctx.allocator, &AllocationRequest {
+        requirements,
+        required_flags,
+        preferred_flags,
+        prefers_dedicated: dedicated_requirements.prefers_dedicated_allocation == vk::TRUE,
+    })?;
+    unsafe {
+        ctx.device.bind_buffer_memory(*buffer, memory.memory, memory.offset)
+    }.map_err(|r| Error::from_vk(r, Error::Allocation))?;
+    tracing::debug!(memory = ?memory.memory, offset = memory.offset, flags = ?memory.memory_flags,
+        buffer = ?*buffer, "allocated and bound memory");
+
+    Ok((buffer, memory))
+}
+
+/// Copies a `#[derive(GpuMappedMemory)]` struct's fields to and from the bytes of a mapped buffer,
+/// which the derived `write` and `read` have mapped and checked the size of.
+#[doc(hidden)]
+pub trait MappedFields {
+    fn write_fields(&self, mapped: &mut [u8]);
+
+    fn read_fields(&mut self, mapped: &[u8]);
 }
 
 pub trait GpuMappedMemory {
 
-    fn write(&self, ctx: &VkCtx, buffer: &LinkedMemory) -> ash::vk::Result;
+    fn write(&self, ctx: &VkCtx, buffer: &mut LinkedMemory) -> Result<()>;
 
-    fn read(&mut self, ctx: &VkCtx, buffer: &LinkedMemory) -> ash::vk::Result;
+    fn read(&mut self, ctx: &VkCtx, buffer: &mut LinkedMemory) -> Result<()>;
 
     fn buffer_size(&self) -> u64;
-}
\ No newline at end of file
+}
+
+/// A buffer of exactly the vector's values, as the buffers of a `#[compute_kernel]` are. An
+/// empty vector still gets room for one value, since Vulkan has no empty buffers.
+impl<T: bytemuck::Pod> GpuMappedMemory for Vec<T> {
+    fn write(&self, _ctx: &VkCtx, buffer: &mut LinkedMemory) -> Result<()> {
+        check_vec_fits(self, buffer)?;
+        buffer.map_mut::<T>()?[..self.len()].copy_from_slice(self);
+        Ok(())
+    }
+
+    fn read(&mut self, _ctx: &VkCtx, buffer: &mut LinkedMemory) -> Result<()> {
+        check_vec_fits(self, buffer)?;
+        let len = self.len();
+        self.copy_from_slice(&buffer.map::<T>()?[..len]);
+        Ok(())
+    }
+
+    fn buffer_size(&self) -> u64 {
+        std::mem::size_of_val(self.as_slice()).max(std::mem::size_of::<T>()) as u64
+    }
+}
+
+fn check_vec_fits<T>(values: &[T], buffer: &LinkedMemory) -> Result<()> {
+    let size = std::mem::size_of_val(values) as u64;
+    if size > buffer.size {
+        return Err(Error::Layout(format!("{} values of {} need {} bytes, but binding {} holds {}",
+            values.len(), std::any::type_name::<T>(), size, buffer.binding, buffer.size)));
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+    use crate::GpuMappedMemory;
+    use super::*;
+
+    #[derive(GpuMappedMemory, Debug, Default, PartialEq)]
+    struct Mixed {
+        count: u32,
+        #[gpu_mapped(skip)]
+        label: usize,
+        scale: f64,
+        pair: [u32; 2],
+        values: Vec<f32>,
+    }
+
+    #[derive(GpuMappedMemory)]
+    struct Fixed(u32, [f64; 2]);
+
+    #[test]
+    fn derived_fields_are_laid_out_at_their_alignment() {
+        let mixed = Mixed { count: 7, label: 1, scale: 0.5, pair: [1, 2], values: vec![1.0, 2.0, 3.0] };
+        // count at 0, scale at 8 after 4 bytes of padding, pair at 16 and values at 24
+        assert_eq!(mixed.buffer_size(), 36);
+        let mut mapped = vec![0xffu8; 36];
+        mixed.write_fields(&mut mapped);
+        assert_eq!(bytemuck::pod_read_unaligned::<u32>(&mapped[0..4]), 7);
+        assert_eq!(&mapped[4..8], [0xff; 4]);
+        assert_eq!(bytemuck::pod_read_unaligned::<f64>(&mapped[8..16]), 0.5);
+        assert_eq!(bytemuck::pod_read_unaligned::<[u32; 2]>(&mapped[16..24]), [1, 2]);
+        assert_eq!(bytemuck::pod_read_unaligned::<[f32; 3]>(&mapped[24..36]), [1.0, 2.0, 3.0]);
+
+        let mut read = Mixed { values: vec![0.0; 3], ..Mixed::default() };
+        read.read_fields(&mapped);
+        assert_eq!(read, Mixed { label: 0, ..mixed });
+    }
+
+    #[test]
+    fn derived_sizes() {
+        // An empty trailing `Vec` still takes one value
+        let empty = Mixed::default();
+        assert_eq!(empty.buffer_size(), 28);
+        let mut mapped = vec![0u8; 28];
+        empty.write_fields(&mut mapped);
+
+        let fixed = Fixed(3, [1.0, 2.0]);
+        assert_eq!(fixed.buffer_size(), 24);
+        let mut mapped = vec![0u8; 24];
+        fixed.write_fields(&mut mapped);
+        assert_eq!(bytemuck::pod_read_unaligned::<u32>(&mapped[0..4]), 3);
+        assert_eq!(bytemuck::pod_read_unaligned::<[f64; 2]>(&mapped[8..24]), [1.0, 2.0]);
+    }
+}
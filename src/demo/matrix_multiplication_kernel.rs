@@ -0,0 +1,18 @@
+use crate::compute_kernel;
+
+/// matrix_multiplication.comp written as a `#[compute_kernel]`, which generates the GLSL,
+/// the bindings and the push constants from the signature.
+#[compute_kernel(workgroup_size = [8, 8])]
+pub fn matrix_multiplication_kernel(a: &[f32], b: &[f32], c: &mut [f32], acbr: u32, ar: u32, bc: u32) {
+    let i = global_id.x;
+    let j = global_id.y;
+    // the last workgroups in each dimension can run past the edge of the result
+    if i >= ar || j >= bc {
+        return;
+    }
+    let mut sum = 0.0;
+    for k in 0..acbr {
+        sum += a[i * acbr + k] * b[k * bc + j];
+    }
+    c[i * bc + j] = sum;
+}
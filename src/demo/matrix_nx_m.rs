@@ -1,14 +1,15 @@
-use crate::context::VkCtx;
-use crate::data::{GpuMappedMemory, LinkedMemory, MappedMemoryPointer};
+use crate::GpuMappedMemory;
 
+#[derive(GpuMappedMemory)]
 pub struct MatrixNxM {
+    #[gpu_mapped(skip)]
     pub rows: usize,
     pub data: Vec<f32>
 }
 
 impl MatrixNxM {
     pub fn print(&self) {
-        let columns = self.data.len() / self.rows;
+        let columns = self.columns();
         for i in 0..self.rows {
             print!("| ");
             for j in 0..columns {
@@ -26,38 +27,45 @@ impl MatrixNxM {
             data
         }
     }
-}
-
-impl GpuMappedMemory for MatrixNxM {
-    fn write(&self, ctx: &VkCtx, linked_memory: &LinkedMemory) -> ash::vk::Result {
-        unsafe {
-            let pointer = linked_memory
-                .get_mapped_pointer(ctx, self.data.len() as u64);
-            std::ptr::copy_nonoverlapping(
-                self.data.as_ptr(),
-                pointer.ptr,
-                self.data.len());
-            pointer.unmap();
-        }
 
-        ash::vk::Result::SUCCESS
+    pub fn columns(&self) -> usize {
+        self.data.len() / self.rows
     }
 
-    fn read(&mut self, ctx: &VkCtx, linked_memory: &LinkedMemory) -> ash::vk::Result {
-        unsafe {
-            let pointer = linked_memory
-                .get_mapped_pointer(ctx, self.data.len() as u64);
-            std::ptr::copy_nonoverlapping(
-                pointer.ptr,
-                self.data.as_mut_ptr(),
-                self.data.len());
-            pointer.unmap();
-        };
-        ash::vk::Result::SUCCESS
+    /// The product computed on the CPU, which the shaders' results are checked against.
+    pub fn multiply(&self, other: &MatrixNxM) -> MatrixNxM {
+        assert_eq!(self.columns(), other.rows);
+        let columns = other.columns();
+        let data = (0..self.rows * columns)
+            .map(|index| {
+                let (i, j) = (index / columns, index % columns);
+                (0..self.columns()).map(|k| self.data[i * self.columns() + k] * other.data[k * columns + j]).sum()
+            })
+            .collect();
+        MatrixNxM::new(self.rows, data)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn buffer_size(&self) -> u64 {
-        std::mem::size_of::<f32>() as u64
-            * self.data.len() as u64
+    #[test]
+    fn non_square_product_is_laid_out_by_result_columns() {
+        let a = MatrixNxM::new(2, vec![
+            1.0, 2.0, 3.0,
+            4.0, 5.0, 6.0,
+        ]);
+        let b = MatrixNxM::new(3, vec![
+            1.0, 0.0, 0.0, 1.0,
+            0.0, 1.0, 0.0, 1.0,
+            0.0, 0.0, 1.0, 1.0,
+        ]);
+        let c = a.multiply(&b);
+        assert_eq!((c.rows, c.columns()), (2, 4));
+        assert_eq!(c.data, [
+            1.0, 2.0, 3.0, 6.0,
+            4.0, 5.0, 6.0, 15.0,
+        ]);
     }
-}
\ No newline at end of file
+}
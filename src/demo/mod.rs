@@ -1,2 +1,3 @@
+pub mod matrix_multiplication_kernel;
 pub mod matrix_nx_m;
-pub mod multiply_nx_m_shader;
\ No newline at end of file
+pub mod multiply_nx_m_shader;
@@ -1,25 +1,30 @@
 use crate::demo::matrix_nx_m::MatrixNxM;
-use ash::vk;
-use ash::vk::{
-    DescriptorSet, DescriptorType, DescriptorSetLayout,
-    DescriptorSetLayoutBinding, DescriptorSetLayoutCreateInfo, DescriptorBufferInfo,
-    WriteDescriptorSet,
-    Pipeline, PipelineCache, PipelineLayout, PipelineLayoutCreateInfo,
-    PipelineShaderStageCreateInfo, ComputePipelineCreateInfo,
-    FenceCreateInfo, CommandBufferBeginInfo, PipelineBindPoint, ShaderModule, SubmitInfo,
-    ShaderStageFlags };
-use crate::context::VkCtx;
-use crate::shader::{ComputeShader, LayoutDescription, LayoutDescriptorIndex, ShaderExecutionContext};
-use crate::data::{GpuMappedMemory, LinkedMemory};
-use shaderc;
+use crate::shader::{BindingResource, ComputeShader, LayoutDescription};
+use crate::data::{GpuMappedMemory, MemoryPlacement};
+use crate::compile::{CompileOptions, HlslRegisterClass};
+use crate::error::Result;
+use crate::loader::{ShaderLanguage, ShaderSource};
+use crate::specialization::SpecializationConstants;
+use crate::ShaderLayout;
 use crate::shader::LayoutDescriptorIndex::{ReadIndex, WriteIndex};
 
 pub struct MatrixNxMShader {
     pub a: MatrixNxM,
     pub b: MatrixNxM,
     pub result: MatrixNxM,
+    /// The local size of the shader's workgroups, which are tiles of the result. The GLSL kernel
+    /// takes it as specialization constants and the WGSL kernel as overrides, while the HLSL
+    /// kernel always uses [`FIXED_WORKGROUP_SIZE`].
+    pub workgroup_size: [u32; 2],
+    /// Which kernel to run: matrix_multiplication.comp, .hlsl or .wgsl
+    pub language: ShaderLanguage,
 }
 
+/// The `numthreads` of matrix_multiplication.hlsl.
+pub const FIXED_WORKGROUP_SIZE: [u32; 2] = [8, 8];
+
+/// The `PushConstants` block of the matrix multiplication kernels.
+#[derive(Clone, Copy, Debug, ShaderLayout)]
 pub struct MatrixPairSizes {
     pub acbr: u32,
     pub ar: u32,
@@ -40,40 +45,98 @@ impl MatrixNxMShader {
             result: MatrixNxM {
                 rows: a_rows,
                 data: vec![0.0f32; a_rows * b_columns]
-            }
+            },
+            workgroup_size: [8, 8],
+            language: ShaderLanguage::Glsl,
+        }
+    }
+
+    /// Runs the HLSL kernel instead of the GLSL one.
+    pub fn new_hlsl(a: MatrixNxM, b: MatrixNxM) -> MatrixNxMShader {
+        MatrixNxMShader {
+            workgroup_size: FIXED_WORKGROUP_SIZE,
+            language: ShaderLanguage::Hlsl,
+            ..MatrixNxMShader::new(a, b)
         }
     }
 
+    /// Runs the WGSL kernel instead of the GLSL one.
+    pub fn new_wgsl(a: MatrixNxM, b: MatrixNxM) -> MatrixNxMShader {
+        MatrixNxMShader {
+            language: ShaderLanguage::Wgsl,
+            ..MatrixNxMShader::new(a, b)
+        }
+    }
+
+    /// Sets the GLSL or WGSL kernel's workgroup size.
+    pub fn with_workgroup_size(mut self, x: u32, y: u32) -> MatrixNxMShader {
+        assert_ne!(self.language, ShaderLanguage::Hlsl, "the HLSL kernel's workgroup size can't be changed");
+        self.workgroup_size = [x, y];
+        self
+    }
+
     pub fn source() -> String {
         String::from(
         include_str!("../shaders/matrix_multiplication.comp"))
     }
+
+    pub fn hlsl_source() -> String {
+        String::from(
+        include_str!("../shaders/matrix_multiplication.hlsl"))
+    }
+
+    pub fn wgsl_source() -> String {
+        String::from(
+        include_str!("../shaders/matrix_multiplication.wgsl"))
+    }
 }
 
 impl ComputeShader<MatrixPairSizes> for MatrixNxMShader {
-    fn get_spirv() -> Result<Vec<u32>, vk::Result> {
-        Self::compile_to_spirv(
-            &MatrixNxMShader::source(),
-            "matrix_multiplication.comp",
-            "main")
+    fn get_source(&self) -> Result<ShaderSource> {
+        Ok(match self.language {
+            ShaderLanguage::Glsl => ShaderSource::glsl(MatrixNxMShader::source(), "matrix_multiplication.comp"),
+            ShaderLanguage::Hlsl => ShaderSource::hlsl(MatrixNxMShader::hlsl_source(), "matrix_multiplication.hlsl"),
+            ShaderLanguage::Wgsl => ShaderSource::wgsl(MatrixNxMShader::wgsl_source(), "matrix_multiplication.wgsl"),
+        })
+    }
+
+    fn compile_options(&self) -> CompileOptions {
+        // The result is u0, moved after a and b in t0 and t1 to match the GLSL bindings
+        CompileOptions::new().hlsl_binding_shift(HlslRegisterClass::UnorderedAccess, 2)
+    }
+
+    fn get_entry_point(&self) -> &str {
+        match self.language {
+            ShaderLanguage::Glsl | ShaderLanguage::Wgsl => "main",
+            ShaderLanguage::Hlsl => "CSMain",
+        }
     }
 
     fn get_layout_descriptors(&self) -> Vec<LayoutDescription> {
         vec![
             LayoutDescription {
+                set: 0,
                 binding: 0,
                 buffer_size: self.a.buffer_size(),
                 index: WriteIndex(0),
+                placement: MemoryPlacement::Auto,
+                resource: BindingResource::Buffer,
             },
             LayoutDescription {
+                set: 0,
                 binding: 1,
                 buffer_size: self.b.buffer_size(),
                 index: WriteIndex(1),
+                placement: MemoryPlacement::Auto,
+                resource: BindingResource::Buffer,
             },
             LayoutDescription {
+                set: 0,
                 binding: 2,
                 buffer_size: self.result.buffer_size(),
                 index: ReadIndex(0),
+                placement: MemoryPlacement::Auto,
+                resource: BindingResource::Buffer,
             }
         ]
     }
@@ -88,7 +151,16 @@ impl ComputeShader<MatrixPairSizes> for MatrixNxMShader {
 
     fn get_group_vec(&self) -> (u32, u32, u32) {
         let push_constants = self.get_push_constants().expect("Failed to get push constants when required");
-        (push_constants.ar, push_constants.bc, 1)
+        (push_constants.ar.div_ceil(self.workgroup_size[0]), push_constants.bc.div_ceil(self.workgroup_size[1]), 1)
+    }
+
+    fn get_specialization_constants(&self) -> SpecializationConstants {
+        if self.language == ShaderLanguage::Hlsl {
+            return SpecializationConstants::new();
+        }
+        SpecializationConstants::new()
+            .set(0, self.workgroup_size[0])
+            .set(1, self.workgroup_size[1])
     }
 
     fn get_push_constants(&self) -> Option<MatrixPairSizes> {
@@ -0,0 +1,287 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use ash::vk;
+use crate::context::Device;
+use crate::error::{Error, Result};
+use crate::handle::OwnedDescriptorSet;
+
+/// The descriptor types a pool has room for.
+const POOL_DESCRIPTOR_TYPES: [vk::DescriptorType; 8] = [
+    vk::DescriptorType::STORAGE_BUFFER,
+    vk::DescriptorType::UNIFORM_BUFFER,
+    vk::DescriptorType::STORAGE_IMAGE,
+    vk::DescriptorType::SAMPLED_IMAGE,
+    vk::DescriptorType::SAMPLER,
+    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+    vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+    vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct DescriptorAllocatorConfig {
+    /// Sets in the first pool. Each new pool holds twice as many as the one before it.
+    pub initial_sets_per_pool: u32,
+    /// Pools stop growing at this many sets
+    pub max_sets_per_pool: u32,
+    /// Descriptors of each type a pool reserves per set
+    pub descriptors_per_set: u32,
+}
+
+impl Default for DescriptorAllocatorConfig {
+    fn default() -> Self {
+        DescriptorAllocatorConfig {
+            initial_sets_per_pool: 16,
+            max_sets_per_pool: 1024,
+            descriptors_per_set: 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DescriptorStats {
+    pub pool_count: usize,
+    pub set_count: usize,
+    /// Sets all pools together have room for
+    pub capacity: usize,
+}
+
+/// Allocates descriptor sets from a growing list of pools. When every pool is full a new,
+/// larger one is created, freed sets go back to the pool they came from, and pools that
+/// empty out are destroyed, except for the last one.
+pub struct DescriptorAllocator {
+    device: Arc<Device>,
+    config: DescriptorAllocatorConfig,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    pools: Vec<Pool>,
+    next_pool_sets: u32,
+}
+
+struct Pool {
+    pool: vk::DescriptorPool,
+    max_sets: u32,
+    set_count: u32,
+}
+
+impl DescriptorAllocator {
+    pub fn new(device: &Arc<Device>, config: DescriptorAllocatorConfig) -> DescriptorAllocator {
+        DescriptorAllocator {
+            device: device.clone(),
+            config,
+            state: Mutex::new(State {
+                pools: Vec::new(),
+                next_pool_sets: config.initial_sets_per_pool.max(1),
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // The state is consistent between calls, so a panic elsewhere cannot have corrupted it
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Allocates a set with `layout`, whose bindings need `sizes` descriptors of each type.
+    pub fn allocate(self: &Arc<Self>, layout: vk::DescriptorSetLayout, sizes: &[vk::DescriptorPoolSize]) -> Result<OwnedDescriptorSet> {
+        let mut state = self.lock();
+        for pool in state.pools.iter_mut().rev().filter(|pool| pool.set_count < pool.max_sets) {
+            match self.allocate_from(pool.pool, layout) {
+                Ok(set) => {
+                    pool.set_count += 1;
+                    return Ok(OwnedDescriptorSet::new(self, pool.pool, set));
+                }
+                // The pool ran out of descriptors of a type the layout uses, try the next one
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => continue,
+                Err(r) => return Err(Error::from_vk(r, Error::Allocation)),
+            }
+        }
+
+        let max_sets = state.grow(self.config.max_sets_per_pool);
+        let pool = self.create_pool(max_sets, sizes)?;
+        // A fresh pool has room for at least one set of these sizes
+        let set = self.allocate_from(pool, layout).map_err(|r| {
+            unsafe { self.device.destroy_descriptor_pool(pool, None) };
+            Error::from_vk(r, Error::Allocation)
+        })?;
+        state.pools.push(Pool { pool, max_sets, set_count: 1 });
+        Ok(OwnedDescriptorSet::new(self, pool, set))
+    }
+
+    fn allocate_from(&self, pool: vk::DescriptorPool, layout: vk::DescriptorSetLayout) -> std::result::Result<vk::DescriptorSet, vk::Result> {
+        let set = unsafe {
+            self.device.allocate_descriptor_sets(&vk::DescriptorSetAllocateInfo {
+                descriptor_pool: pool,
+                descriptor_set_count: 1,
+                p_set_layouts: &layout,
+                ..Default::default()
+            })
+        }?[0];
+        tracing::trace!(descriptor_set = ?set, descriptor_pool = ?pool, "allocated descriptor set");
+        Ok(set)
+    }
+
+    fn create_pool(&self, max_sets: u32, sizes: &[vk::DescriptorPoolSize]) -> Result<vk::DescriptorPool> {
+        let pool_sizes = pool_sizes(max_sets, self.config.descriptors_per_set, sizes);
+        let pool = unsafe {
+            self.device.create_descriptor_pool(&vk::DescriptorPoolCreateInfo {
+                max_sets,
+                pool_size_count: pool_sizes.len() as u32,
+                p_pool_sizes: pool_sizes.as_ptr(),
+                // Sets are freed one at a time as their owners are dropped
+                flags: vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+                ..Default::default()
+            }, None)
+        }.map_err(|r| Error::from_vk(r, Error::Allocation))?;
+        tracing::debug!(descriptor_pool = ?pool, max_sets, "created descriptor pool");
+        Ok(pool)
+    }
+
+    /// Returns a set to its pool, called when an [`OwnedDescriptorSet`] is dropped.
+    pub(crate) fn free(&self, pool: vk::DescriptorPool, set: vk::DescriptorSet) {
+        let mut state = self.lock();
+        if !state.pools.iter().any(|found| found.pool == pool) {
+            tracing::warn!(descriptor_set = ?set, descriptor_pool = ?pool, "freed a descriptor set from an unknown pool");
+            return;
+        }
+        // Freeing only fails on a lost device, where there is nothing left to release
+        let _ = unsafe { self.device.free_descriptor_sets(pool, &[set]) };
+        tracing::trace!(descriptor_set = ?set, descriptor_pool = ?pool, "freed descriptor set");
+
+        if let Some(empty) = state.release(pool) {
+            tracing::debug!(descriptor_pool = ?empty, "destroying empty descriptor pool");
+            unsafe { self.device.destroy_descriptor_pool(empty, None) };
+        }
+    }
+
+    pub fn stats(&self) -> DescriptorStats {
+        self.lock().stats()
+    }
+}
+
+impl State {
+    /// The number of sets for a new pool, doubling the next one's up to `max_sets_per_pool`.
+    /// A limit below the current size keeps pools at the current size.
+    fn grow(&mut self, max_sets_per_pool: u32) -> u32 {
+        let max_sets = self.next_pool_sets;
+        self.next_pool_sets = max_sets.saturating_mul(2).min(max_sets_per_pool.max(max_sets));
+        max_sets
+    }
+
+    /// Counts a set of `pool` as freed, and removes the pool once it is empty, returning it to be
+    /// destroyed. The last pool is kept so the next allocation doesn't have to create it again.
+    fn release(&mut self, pool: vk::DescriptorPool) -> Option<vk::DescriptorPool> {
+        let index = self.pools.iter().position(|found| found.pool == pool)?;
+        self.pools[index].set_count -= 1;
+        if self.pools[index].set_count == 0 && self.pools.len() > 1 {
+            return Some(self.pools.remove(index).pool);
+        }
+        None
+    }
+
+    fn stats(&self) -> DescriptorStats {
+        DescriptorStats {
+            pool_count: self.pools.len(),
+            set_count: self.pools.iter().map(|pool| pool.set_count as usize).sum(),
+            capacity: self.pools.iter().map(|pool| pool.max_sets as usize).sum(),
+        }
+    }
+}
+
+/// The descriptors a pool of `max_sets` sets reserves: `descriptors_per_set` of every type for
+/// each set, or more of a type when the set it is created for needs more than that.
+fn pool_sizes(max_sets: u32, descriptors_per_set: u32, sizes: &[vk::DescriptorPoolSize]) -> [vk::DescriptorPoolSize; 8] {
+    POOL_DESCRIPTOR_TYPES.map(|ty| {
+        let needed = sizes.iter()
+            .filter(|size| size.ty == ty)
+            .map(|size| size.descriptor_count)
+            .sum::<u32>();
+        vk::DescriptorPoolSize {
+            ty,
+            descriptor_count: max_sets.saturating_mul(descriptors_per_set).max(needed),
+        }
+    })
+}
+
+impl Drop for DescriptorAllocator {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for pool in &state.pools {
+            if pool.set_count > 0 {
+                tracing::warn!(descriptor_pool = ?pool.pool, sets = pool.set_count,
+                    "destroying a descriptor pool that still has live sets");
+            }
+            unsafe { self.device.destroy_descriptor_pool(pool.pool, None) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+    use super::*;
+
+    fn pool(raw: u64, max_sets: u32, set_count: u32) -> Pool {
+        Pool { pool: vk::DescriptorPool::from_raw(raw), max_sets, set_count }
+    }
+
+    #[test]
+    fn pools_double_up_to_the_limit() {
+        let config = DescriptorAllocatorConfig::default();
+        let mut state = State { pools: Vec::new(), next_pool_sets: config.initial_sets_per_pool };
+        let sizes = (0..9).map(|_| state.grow(config.max_sets_per_pool)).collect::<Vec<u32>>();
+        assert_eq!(sizes, [16, 32, 64, 128, 256, 512, 1024, 1024, 1024]);
+    }
+
+    #[test]
+    fn growth_without_room_to_double() {
+        // A limit that isn't a power of two of the first size is reached exactly
+        let mut state = State { pools: Vec::new(), next_pool_sets: 3 };
+        assert_eq!((0..4).map(|_| state.grow(10)).collect::<Vec<u32>>(), [3, 6, 10, 10]);
+        // A limit below the first size keeps every pool at the first size
+        let mut state = State { pools: Vec::new(), next_pool_sets: 64 };
+        assert_eq!((0..3).map(|_| state.grow(16)).collect::<Vec<u32>>(), [64, 64, 64]);
+        // Doubling saturates rather than overflowing
+        let mut state = State { pools: Vec::new(), next_pool_sets: u32::MAX / 2 + 1 };
+        assert_eq!((0..2).map(|_| state.grow(u32::MAX)).collect::<Vec<u32>>(), [u32::MAX / 2 + 1, u32::MAX]);
+    }
+
+    #[test]
+    fn pools_have_room_for_every_type() {
+        let sizes = pool_sizes(16, 4, &[]);
+        assert_eq!(sizes.map(|size| size.ty), POOL_DESCRIPTOR_TYPES);
+        assert!(sizes.iter().any(|size| size.ty == vk::DescriptorType::SAMPLER));
+        assert!(sizes.iter().all(|size| size.descriptor_count == 64));
+    }
+
+    #[test]
+    fn pools_grow_for_sets_with_many_descriptors() {
+        let needed = [
+            vk::DescriptorPoolSize { ty: vk::DescriptorType::SAMPLER, descriptor_count: 40 },
+            vk::DescriptorPoolSize { ty: vk::DescriptorType::SAMPLER, descriptor_count: 40 },
+            vk::DescriptorPoolSize { ty: vk::DescriptorType::STORAGE_BUFFER, descriptor_count: 8 },
+        ];
+        let sizes = pool_sizes(16, 4, &needed);
+        let count = |ty| sizes.iter().find(|size| size.ty == ty).unwrap().descriptor_count;
+        assert_eq!(count(vk::DescriptorType::SAMPLER), 80);
+        assert_eq!(count(vk::DescriptorType::STORAGE_BUFFER), 64);
+        assert_eq!(count(vk::DescriptorType::UNIFORM_BUFFER), 64);
+        assert_eq!(pool_sizes(u32::MAX, 4, &[])[0].descriptor_count, u32::MAX);
+    }
+
+    #[test]
+    fn emptied_pools_are_recycled_except_the_last() {
+        let mut state = State { pools: vec![pool(1, 16, 1), pool(2, 32, 2)], next_pool_sets: 64 };
+        assert_eq!(state.stats(), DescriptorStats { pool_count: 2, set_count: 3, capacity: 48 });
+
+        assert_eq!(state.release(vk::DescriptorPool::from_raw(2)), None);
+        assert_eq!(state.release(vk::DescriptorPool::from_raw(1)), Some(vk::DescriptorPool::from_raw(1)));
+        assert_eq!(state.stats(), DescriptorStats { pool_count: 1, set_count: 1, capacity: 32 });
+
+        // The last pool stays, empty, for the next allocation
+        assert_eq!(state.release(vk::DescriptorPool::from_raw(2)), None);
+        assert_eq!(state.stats(), DescriptorStats { pool_count: 1, set_count: 0, capacity: 32 });
+        // Pools that were already destroyed are ignored
+        assert_eq!(state.release(vk::DescriptorPool::from_raw(1)), None);
+    }
+}
@@ -0,0 +1,441 @@
+use std::fmt;
+use ash::vk;
+use crate::error::{Error, Result};
+
+/// Environment variable that overrides the device selection of every [`crate::context::VkCtxBuilder`].
+///
+/// It accepts a device index (`1`), a device type (`discrete`, `integrated`, `virtual` or `cpu`),
+/// or otherwise a case-insensitive substring of the device name (`llvmpipe`).
+pub const DEVICE_ENV_VAR: &str = "RSCOMPUTE_DEVICE";
+
+/// Describes one physical device, as reported by the driver.
+#[derive(Clone, Debug)]
+pub struct PhysicalDeviceInfo {
+    /// Position in `vkEnumeratePhysicalDevices`, usable with [`DeviceSelector::Index`]
+    pub index: usize,
+    pub handle: vk::PhysicalDevice,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub api_version: u32,
+    pub driver_version: u32,
+    /// Only known when the driver supports `VK_KHR_driver_properties`
+    pub driver_name: Option<String>,
+    pub driver_info: Option<String>,
+    pub pipeline_cache_uuid: [u8; vk::UUID_SIZE],
+    pub memory_heaps: Vec<MemoryHeapInfo>,
+    pub queue_families: Vec<QueueFamilyInfo>,
+    pub limits: ComputeLimits,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct QueueFamilyInfo {
+    pub index: u32,
+    pub flags: vk::QueueFlags,
+    pub queue_count: u32,
+    /// Zero when queues of this family cannot write timestamps
+    pub timestamp_valid_bits: u32,
+    /// Granularity of image transfers, zero when only whole mip levels can be transferred
+    pub min_image_transfer_granularity: vk::Extent3D,
+}
+
+impl QueueFamilyInfo {
+    pub fn supports_compute(&self) -> bool {
+        self.flags.contains(vk::QueueFlags::COMPUTE)
+    }
+
+    /// Compute and graphics queues always support transfers, even without the TRANSFER flag.
+    pub fn supports_transfer(&self) -> bool {
+        self.flags.intersects(vk::QueueFlags::TRANSFER | vk::QueueFlags::COMPUTE | vk::QueueFlags::GRAPHICS)
+    }
+
+    pub fn supports_timestamps(&self) -> bool {
+        self.timestamp_valid_bits > 0
+    }
+
+    /// A compute family without graphics support, which runs asynchronously to graphics work.
+    pub fn is_dedicated_compute(&self) -> bool {
+        self.supports_compute() && !self.flags.contains(vk::QueueFlags::GRAPHICS)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryHeapInfo {
+    pub size: u64,
+    pub device_local: bool,
+}
+
+/// The subset of `VkPhysicalDeviceLimits` that matters when running compute shaders.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ComputeLimits {
+    pub max_compute_shared_memory_size: u32,
+    pub max_compute_work_group_count: [u32; 3],
+    pub max_compute_work_group_invocations: u32,
+    pub max_compute_work_group_size: [u32; 3],
+    pub max_push_constants_size: u32,
+    pub max_storage_buffer_range: u32,
+    pub max_uniform_buffer_range: u32,
+    pub max_bound_descriptor_sets: u32,
+    pub max_memory_allocation_count: u32,
+    pub max_texel_buffer_elements: u32,
+    pub max_image_dimension_2d: u32,
+    pub max_image_dimension_3d: u32,
+    pub min_storage_buffer_offset_alignment: u64,
+    pub min_uniform_buffer_offset_alignment: u64,
+    pub non_coherent_atom_size: u64,
+    /// Alignment that keeps buffers and optimally tiled images out of each other's pages
+    pub buffer_image_granularity: u64,
+    /// Nanoseconds per timestamp tick
+    pub timestamp_period: f32,
+}
+
+impl PhysicalDeviceInfo {
+    /// Queries everything the crate needs to know about a physical device.
+    pub fn query(instance: &ash::Instance, handle: vk::PhysicalDevice, index: usize) -> Result<PhysicalDeviceInfo> {
+        let has_driver_properties = unsafe { instance.enumerate_device_extension_properties(handle) }
+            .map_err(Error::Instance)?
+            .iter()
+            .any(|extension| extension.extension_name_as_c_str() == Ok(ash::khr::driver_properties::NAME));
+
+        let mut driver_properties = vk::PhysicalDeviceDriverProperties::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::default();
+        if has_driver_properties {
+            properties2 = properties2.push_next(&mut driver_properties);
+        }
+        unsafe { instance.get_physical_device_properties2(handle, &mut properties2) };
+        let properties = properties2.properties;
+
+        let (driver_name, driver_info) = if has_driver_properties {
+            (
+                driver_properties.driver_name_as_c_str().ok().map(|name| name.to_string_lossy().into_owned()),
+                driver_properties.driver_info_as_c_str().ok().map(|info| info.to_string_lossy().into_owned()),
+            )
+        } else {
+            (None, None)
+        };
+
+        let memory_properties = unsafe { instance.get_physical_device_memory_properties(handle) };
+        let memory_heaps = memory_properties.memory_heaps_as_slice().iter()
+            .map(|heap| MemoryHeapInfo {
+                size: heap.size,
+                device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+            })
+            .collect();
+
+        let queue_families = unsafe { instance.get_physical_device_queue_family_properties(handle) }
+            .iter()
+            .enumerate()
+            .map(|(index, family)| QueueFamilyInfo {
+                index: index as u32,
+                flags: family.queue_flags,
+                queue_count: family.queue_count,
+                timestamp_valid_bits: family.timestamp_valid_bits,
+                min_image_transfer_granularity: family.min_image_transfer_granularity,
+            })
+            .collect();
+
+        let limits = &properties.limits;
+        Ok(PhysicalDeviceInfo {
+            index,
+            handle,
+            name: properties.device_name_as_c_str()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            device_type: properties.device_type,
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            api_version: properties.api_version,
+            driver_version: properties.driver_version,
+            driver_name,
+            driver_info,
+            pipeline_cache_uuid: properties.pipeline_cache_uuid,
+            memory_heaps,
+            queue_families,
+            limits: ComputeLimits {
+                max_compute_shared_memory_size: limits.max_compute_shared_memory_size,
+                max_compute_work_group_count: limits.max_compute_work_group_count,
+                max_compute_work_group_invocations: limits.max_compute_work_group_invocations,
+                max_compute_work_group_size: limits.max_compute_work_group_size,
+                max_push_constants_size: limits.max_push_constants_size,
+                max_storage_buffer_range: limits.max_storage_buffer_range,
+                max_uniform_buffer_range: limits.max_uniform_buffer_range,
+                max_bound_descriptor_sets: limits.max_bound_descriptor_sets,
+                max_memory_allocation_count: limits.max_memory_allocation_count,
+                max_texel_buffer_elements: limits.max_texel_buffer_elements,
+                max_image_dimension_2d: limits.max_image_dimension2_d,
+                max_image_dimension_3d: limits.max_image_dimension3_d,
+                min_storage_buffer_offset_alignment: limits.min_storage_buffer_offset_alignment,
+                min_uniform_buffer_offset_alignment: limits.min_uniform_buffer_offset_alignment,
+                non_coherent_atom_size: limits.non_coherent_atom_size,
+                buffer_image_granularity: limits.buffer_image_granularity,
+                timestamp_period: limits.timestamp_period,
+            },
+        })
+    }
+
+    /// The vendor's name for well known PCI vendor ids, or the Khronos vendor id name.
+    pub fn vendor_name(&self) -> &'static str {
+        match self.vendor_id {
+            0x1002 => "AMD",
+            0x1010 => "ImgTec",
+            0x106B => "Apple",
+            0x10DE => "NVIDIA",
+            0x13B5 => "ARM",
+            0x5143 => "Qualcomm",
+            0x8086 => "Intel",
+            0x10005 => "Mesa",
+            _ => "Unknown",
+        }
+    }
+
+    /// The queue family compute work is submitted to: a dedicated compute family when
+    /// the device has one, otherwise the first family supporting graphics and compute.
+    pub fn compute_queue_family(&self) -> Option<&QueueFamilyInfo> {
+        self.queue_families.iter()
+            .find(|family| family.is_dedicated_compute())
+            .or_else(|| self.queue_families.iter().find(|family| family.supports_compute()))
+    }
+
+    /// Total size of the device-local memory heaps in bytes.
+    pub fn device_local_memory(&self) -> u64 {
+        self.memory_heaps.iter()
+            .filter(|heap| heap.device_local)
+            .map(|heap| heap.size)
+            .sum()
+    }
+
+    /// Whether every memory heap is device-local, as on integrated GPUs and software
+    /// rasterizers, where the host can map the memory shaders use without a copy.
+    pub fn is_unified_memory(&self) -> bool {
+        self.memory_heaps.iter().all(|heap| heap.device_local)
+    }
+}
+
+impl fmt::Display for PhysicalDeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {} ({:?}, {}, Vulkan {}.{}.{}",
+            self.index,
+            self.name,
+            self.device_type,
+            self.vendor_name(),
+            vk::api_version_major(self.api_version),
+            vk::api_version_minor(self.api_version),
+            vk::api_version_patch(self.api_version))?;
+        if let Some(driver_name) = &self.driver_name {
+            write!(f, ", driver {}", driver_name)?;
+            if let Some(driver_info) = self.driver_info.as_ref().filter(|info| !info.is_empty()) {
+                write!(f, " {}", driver_info)?;
+            }
+        }
+        write!(f, ", {} MiB device-local)", self.device_local_memory() / (1024 * 1024))
+    }
+}
+
+/// Lists the physical devices of an instance.
+pub fn enumerate_physical_devices(instance: &ash::Instance) -> Result<Vec<PhysicalDeviceInfo>> {
+    unsafe { instance.enumerate_physical_devices() }
+        .map_err(Error::Instance)?
+        .into_iter()
+        .enumerate()
+        .map(|(index, handle)| PhysicalDeviceInfo::query(instance, handle, index))
+        .collect()
+}
+
+/// Scores a device for [`DeviceSelector::Score`], `None` rules the device out.
+pub type DeviceScore = dyn Fn(&PhysicalDeviceInfo) -> Option<u64>;
+
+/// How a [`crate::context::VkCtxBuilder`] picks between the available physical devices.
+pub enum DeviceSelector {
+    /// The device at this position in the enumeration order
+    Index(usize),
+    /// The first device whose name contains this text, ignoring case
+    Name(String),
+    /// The first device of the earliest type in this list
+    DeviceType(Vec<vk::PhysicalDeviceType>),
+    /// The device with the highest score, skipping devices scored `None`
+    Score(Box<DeviceScore>),
+}
+
+impl Default for DeviceSelector {
+    /// Prefers discrete GPUs, then integrated, virtual and finally software devices.
+    fn default() -> Self {
+        DeviceSelector::DeviceType(vec![
+            vk::PhysicalDeviceType::DISCRETE_GPU,
+            vk::PhysicalDeviceType::INTEGRATED_GPU,
+            vk::PhysicalDeviceType::VIRTUAL_GPU,
+            vk::PhysicalDeviceType::CPU,
+            vk::PhysicalDeviceType::OTHER,
+        ])
+    }
+}
+
+impl fmt::Debug for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSelector::Index(index) => f.debug_tuple("Index").field(index).finish(),
+            DeviceSelector::Name(name) => f.debug_tuple("Name").field(name).finish(),
+            DeviceSelector::DeviceType(types) => f.debug_tuple("DeviceType").field(types).finish(),
+            DeviceSelector::Score(_) => f.write_str("Score(..)"),
+        }
+    }
+}
+
+impl DeviceSelector {
+    /// Parses the [`DEVICE_ENV_VAR`] override, if it is set to something other than whitespace.
+    pub fn from_env() -> Option<Result<DeviceSelector>> {
+        let value = std::env::var(DEVICE_ENV_VAR).ok()?;
+        Some(value).filter(|value| !value.trim().is_empty()).map(|value| DeviceSelector::parse(&value))
+    }
+
+    /// Parses an index, a device type name, or falls back to a name substring. Fails with
+    /// [`Error::DeviceSelection`] for an empty value, which would match every name, and for
+    /// negative indices.
+    pub fn parse(value: &str) -> Result<DeviceSelector> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(Error::DeviceSelection(String::from("the device to select is empty")));
+        }
+        if let Ok(index) = value.parse::<usize>() {
+            return Ok(DeviceSelector::Index(index));
+        }
+        if value.parse::<i64>().is_ok() {
+            return Err(Error::DeviceSelection(format!("{} is not a device index, they start at 0", value)));
+        }
+        let device_type = match value.to_ascii_lowercase().as_str() {
+            "discrete" => Some(vk::PhysicalDeviceType::DISCRETE_GPU),
+            "integrated" => Some(vk::PhysicalDeviceType::INTEGRATED_GPU),
+            "virtual" => Some(vk::PhysicalDeviceType::VIRTUAL_GPU),
+            "cpu" => Some(vk::PhysicalDeviceType::CPU),
+            _ => None,
+        };
+        Ok(match device_type {
+            Some(device_type) => DeviceSelector::DeviceType(vec![device_type]),
+            None => DeviceSelector::Name(value.to_string()),
+        })
+    }
+
+    /// Picks one of `devices`, returning its position in the slice.
+    pub fn select(&self, devices: &[PhysicalDeviceInfo]) -> Result<usize> {
+        if devices.is_empty() {
+            return Err(Error::DeviceSelection(String::from("no physical devices found")));
+        }
+        let selected = match self {
+            DeviceSelector::Index(index) => devices.iter().position(|device| device.index == *index),
+            DeviceSelector::Name(name) => {
+                let name = name.to_lowercase();
+                devices.iter().position(|device| device.name.to_lowercase().contains(&name))
+            }
+            DeviceSelector::DeviceType(types) => types.iter()
+                .find_map(|device_type| devices.iter().position(|device| device.device_type == *device_type)),
+            DeviceSelector::Score(score) => devices.iter()
+                .enumerate()
+                .filter_map(|(position, device)| score(device).map(|score| (position, score)))
+                .max_by(|(a_position, a_score), (b_position, b_score)|
+                    a_score.cmp(b_score).then(b_position.cmp(a_position)))
+                .map(|(position, _)| position),
+        };
+        selected.ok_or_else(|| {
+            let available = devices.iter()
+                .map(|device| device.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            Error::DeviceSelection(format!("no device matches {:?}, available devices are {}", self, available))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(index: usize, name: &str, device_type: vk::PhysicalDeviceType) -> PhysicalDeviceInfo {
+        PhysicalDeviceInfo {
+            index,
+            handle: vk::PhysicalDevice::null(),
+            name: name.to_string(),
+            device_type,
+            vendor_id: 0,
+            device_id: 0,
+            api_version: vk::API_VERSION_1_2,
+            driver_version: 0,
+            driver_name: None,
+            driver_info: None,
+            pipeline_cache_uuid: [0; vk::UUID_SIZE],
+            memory_heaps: Vec::new(),
+            queue_families: Vec::new(),
+            limits: ComputeLimits::default(),
+        }
+    }
+
+    /// A software rasterizer enumerated before an integrated and a discrete GPU.
+    fn devices() -> Vec<PhysicalDeviceInfo> {
+        vec![
+            device(0, "llvmpipe (LLVM 17.0.6, 256 bits)", vk::PhysicalDeviceType::CPU),
+            device(1, "Intel(R) UHD Graphics 620", vk::PhysicalDeviceType::INTEGRATED_GPU),
+            device(2, "NVIDIA GeForce RTX 3060", vk::PhysicalDeviceType::DISCRETE_GPU),
+        ]
+    }
+
+    fn select(selector: DeviceSelector) -> Result<usize> {
+        selector.select(&devices())
+    }
+
+    #[test]
+    fn parse_index_type_and_name() {
+        assert!(matches!(DeviceSelector::parse("1"), Ok(DeviceSelector::Index(1))));
+        assert!(matches!(DeviceSelector::parse(" 2\n"), Ok(DeviceSelector::Index(2))));
+        for (value, device_type) in [
+            ("discrete", vk::PhysicalDeviceType::DISCRETE_GPU),
+            ("Integrated", vk::PhysicalDeviceType::INTEGRATED_GPU),
+            ("VIRTUAL", vk::PhysicalDeviceType::VIRTUAL_GPU),
+            ("cpu", vk::PhysicalDeviceType::CPU),
+        ] {
+            let Ok(DeviceSelector::DeviceType(types)) = DeviceSelector::parse(value) else { panic!("{} is a type", value) };
+            assert_eq!(types, [device_type]);
+        }
+        let Ok(DeviceSelector::Name(name)) = DeviceSelector::parse("GeForce RTX") else { panic!("expected a name") };
+        assert_eq!(name, "GeForce RTX");
+    }
+
+    #[test]
+    fn parse_rejects_bad_input() {
+        for value in ["", "   ", "-1"] {
+            assert!(matches!(DeviceSelector::parse(value), Err(Error::DeviceSelection(_))), "{:?} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn select_by_index_type_and_name() {
+        assert_eq!(select(DeviceSelector::Index(1)).unwrap(), 1);
+        assert_eq!(select(DeviceSelector::Name(String::from("LLVMPIPE"))).unwrap(), 0);
+        assert_eq!(select(DeviceSelector::default()).unwrap(), 2);
+        // The earliest type in the list wins over enumeration order
+        let types = vec![vk::PhysicalDeviceType::VIRTUAL_GPU, vk::PhysicalDeviceType::INTEGRATED_GPU, vk::PhysicalDeviceType::CPU];
+        assert_eq!(select(DeviceSelector::DeviceType(types)).unwrap(), 1);
+        // Indices are the enumeration order, not positions in a filtered list
+        assert_eq!(DeviceSelector::Index(2).select(&devices()[1..]).unwrap(), 1);
+    }
+
+    #[test]
+    fn select_reports_what_is_available() {
+        let Err(Error::DeviceSelection(message)) = select(DeviceSelector::Index(3)) else { panic!("there is no device 3") };
+        assert!(message.starts_with("no device matches Index(3), available devices are [0] llvmpipe"), "{}", message);
+        assert!(select(DeviceSelector::DeviceType(vec![vk::PhysicalDeviceType::VIRTUAL_GPU])).is_err());
+        assert!(matches!(DeviceSelector::default().select(&[]), Err(Error::DeviceSelection(_))));
+    }
+
+    #[test]
+    fn score_ties_go_to_the_earlier_device() {
+        let same = DeviceSelector::Score(Box::new(|_| Some(1)));
+        assert_eq!(select(same).unwrap(), 0);
+        let gpus = DeviceSelector::Score(Box::new(|device| match device.device_type {
+            vk::PhysicalDeviceType::CPU => None,
+            _ => Some(10),
+        }));
+        assert_eq!(select(gpus).unwrap(), 1);
+        let highest = DeviceSelector::Score(Box::new(|device| Some(device.index as u64 % 2)));
+        assert_eq!(select(highest).unwrap(), 1);
+        assert!(select(DeviceSelector::Score(Box::new(|_| None))).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+use ash::vk;
+use crate::compile::Diagnostic;
+
+/// Errors returned by every fallible operation in the crate.
+#[derive(Debug)]
+pub enum Error {
+    /// The Vulkan loader library could not be loaded
+    Loading(ash::LoadingError),
+    /// Creating the instance failed, including missing layers or extensions
+    Instance(vk::Result),
+    /// No physical device matched the requested selection
+    DeviceSelection(String),
+    /// Creating the logical device, queue or command/descriptor pools failed
+    Device(vk::Result),
+    /// Reading a shader file or directory failed
+    Io {
+        path: std::path::PathBuf,
+        error: std::io::Error,
+    },
+    /// A shader couldn't be loaded, such as a file that isn't a shader or an unregistered name
+    ShaderLoad(String),
+    /// Bytes or words that were given as SPIR-V are not a SPIR-V module
+    InvalidSpirv(String),
+    /// Compiling shader source code to SPIR-V failed
+    ShaderCompile {
+        file_name: String,
+        error_count: u32,
+        /// The compiler's errors, and any warnings it gave before them
+        diagnostics: Vec<Diagnostic>,
+    },
+    /// A SPIR-V module could not be reflected
+    Reflection(String),
+    /// A host type's layout or a layout descriptor does not match what the shader declares, or does not fit
+    Layout(String),
+    /// Creating a shader module, descriptor set layout, pipeline layout or pipeline failed
+    Pipeline(vk::Result),
+    /// Allocating device memory, buffers, descriptor sets or command buffers failed
+    Allocation(vk::Result),
+    /// None of the device's memory types satisfy a buffer's requirements
+    NoSuitableMemoryType {
+        memory_type_bits: u32,
+        flags: vk::MemoryPropertyFlags,
+    },
+    /// The device cannot use a format the way a binding needs it
+    UnsupportedFormat {
+        format: vk::Format,
+        features: vk::FormatFeatureFlags,
+    },
+    /// Mapping device memory into host address space failed
+    MemoryMap(vk::Result),
+    /// Recording or submitting a command buffer failed
+    Submission(vk::Result),
+    /// Waiting for submitted work timed out
+    Timeout,
+    /// The logical device was lost and must be recreated
+    DeviceLost,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Wraps a Vulkan error code in the variant for the failed operation, pulling out
+    /// device loss and timeouts since callers handle those the same wherever they happen.
+    pub(crate) fn from_vk(result: vk::Result, wrap: fn(vk::Result) -> Error) -> Error {
+        match result {
+            vk::Result::ERROR_DEVICE_LOST => Error::DeviceLost,
+            vk::Result::TIMEOUT => Error::Timeout,
+            other => wrap(other),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Loading(e) => write!(f, "failed to load the Vulkan library: {}", e),
+            Error::Instance(r) => write!(f, "failed to create Vulkan instance: {}", r),
+            Error::DeviceSelection(reason) => write!(f, "failed to select a physical device: {}", reason),
+            Error::Device(r) => write!(f, "failed to create Vulkan device: {}", r),
+            Error::Io { path, error } => write!(f, "failed to read {}: {}", path.display(), error),
+            Error::ShaderLoad(reason) => write!(f, "failed to load shader: {}", reason),
+            Error::InvalidSpirv(reason) => write!(f, "invalid SPIR-V: {}", reason),
+            Error::ShaderCompile { file_name, error_count, diagnostics } => {
+                let plural = if *error_count == 1 { "" } else { "s" };
+                write!(f, "failed to compile {} ({} error{})", file_name, error_count, plural)?;
+                diagnostics.iter().try_for_each(|diagnostic| write!(f, "\n{}", diagnostic))
+            }
+            Error::Reflection(reason) => write!(f, "failed to reflect shader: {}", reason),
+            Error::Layout(reason) => write!(f, "layout mismatch: {}", reason),
+            Error::Pipeline(r) => write!(f, "failed to create shader pipeline: {}", r),
+            Error::Allocation(r) => write!(f, "failed to allocate: {}", r),
+            Error::NoSuitableMemoryType { memory_type_bits, flags } =>
+                write!(f, "no memory type in {:#b} supports {:?}", memory_type_bits, flags),
+            Error::UnsupportedFormat { format, features } =>
+                write!(f, "{:?} does not support {:?} on this device", format, features),
+            Error::MemoryMap(r) => write!(f, "failed to map device memory: {}", r),
+            Error::Submission(r) => write!(f, "failed to submit work to the device: {}", r),
+            Error::Timeout => write!(f, "timed out waiting for the device"),
+            Error::DeviceLost => write!(f, "the Vulkan device was lost"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Loading(e) => Some(e),
+            Error::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<ash::LoadingError> for Error {
+    fn from(error: ash::LoadingError) -> Self {
+        Error::Loading(error)
+    }
+}
@@ -0,0 +1,176 @@
+use std::ops::Deref;
+use std::sync::Arc;
+use ash::vk;
+use crate::context::Device;
+use crate::descriptor::DescriptorAllocator;
+
+/// A Vulkan object created from a logical device that knows how to destroy itself.
+pub trait DeviceObject: Copy + std::fmt::Debug {
+    /// # Safety
+    ///
+    /// The handle must have been created from `device` and must no longer be in use by the GPU.
+    unsafe fn destroy(self, device: &ash::Device);
+}
+
+/// Owns a Vulkan handle and destroys it when dropped.
+///
+/// Holding an `Arc` to the [`Device`] means the device (and its instance) cannot be
+/// destroyed while any object created from it is still alive.
+pub struct Owned<T: DeviceObject> {
+    handle: T,
+    device: Arc<Device>,
+}
+
+impl<T: DeviceObject> Owned<T> {
+    /// Takes ownership of a handle that was just created from `device`.
+    pub fn new(device: &Arc<Device>, handle: T) -> Owned<T> {
+        Owned {
+            handle,
+            device: device.clone(),
+        }
+    }
+
+    pub fn handle(&self) -> T {
+        self.handle
+    }
+
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+}
+
+impl<T: DeviceObject> Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.handle
+    }
+}
+
+impl<T: DeviceObject> std::fmt::Debug for Owned<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.handle.fmt(f)
+    }
+}
+
+impl<T: DeviceObject> Drop for Owned<T> {
+    fn drop(&mut self) {
+        tracing::trace!(handle = ?self.handle, "destroying {}", std::any::type_name::<T>());
+        unsafe { self.handle.destroy(&self.device) }
+    }
+}
+
+/// A descriptor set that is returned to its pool when dropped.
+pub struct OwnedDescriptorSet {
+    set: vk::DescriptorSet,
+    pool: vk::DescriptorPool,
+    allocator: Arc<DescriptorAllocator>,
+}
+
+impl OwnedDescriptorSet {
+    pub(crate) fn new(allocator: &Arc<DescriptorAllocator>, pool: vk::DescriptorPool, set: vk::DescriptorSet) -> OwnedDescriptorSet {
+        OwnedDescriptorSet {
+            set,
+            pool,
+            allocator: allocator.clone(),
+        }
+    }
+}
+
+impl Deref for OwnedDescriptorSet {
+    type Target = vk::DescriptorSet;
+
+    fn deref(&self) -> &vk::DescriptorSet {
+        &self.set
+    }
+}
+
+impl std::fmt::Debug for OwnedDescriptorSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.set.fmt(f)
+    }
+}
+
+impl Drop for OwnedDescriptorSet {
+    fn drop(&mut self) {
+        self.allocator.free(self.pool, self.set);
+    }
+}
+
+impl DeviceObject for vk::Buffer {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_buffer(self, None)
+    }
+}
+
+impl DeviceObject for vk::BufferView {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_buffer_view(self, None)
+    }
+}
+
+impl DeviceObject for vk::Image {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_image(self, None)
+    }
+}
+
+impl DeviceObject for vk::ImageView {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_image_view(self, None)
+    }
+}
+
+impl DeviceObject for vk::Sampler {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_sampler(self, None)
+    }
+}
+
+impl DeviceObject for vk::DeviceMemory {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.free_memory(self, None)
+    }
+}
+
+impl DeviceObject for vk::ShaderModule {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_shader_module(self, None)
+    }
+}
+
+impl DeviceObject for vk::Pipeline {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_pipeline(self, None)
+    }
+}
+
+impl DeviceObject for vk::PipelineCache {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_pipeline_cache(self, None)
+    }
+}
+
+impl DeviceObject for vk::PipelineLayout {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_pipeline_layout(self, None)
+    }
+}
+
+impl DeviceObject for vk::DescriptorSetLayout {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_descriptor_set_layout(self, None)
+    }
+}
+
+impl DeviceObject for vk::CommandPool {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_command_pool(self, None)
+    }
+}
+
+impl DeviceObject for vk::Fence {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_fence(self, None)
+    }
+}
@@ -0,0 +1,323 @@
+use ash::vk::{self, Image, ImageView, Sampler};
+use crate::allocator::{align_up, AllocationRequest, OwnedAllocation};
+use crate::context::VkCtx;
+use crate::data::{LinkedMemory, MemoryPlacement};
+use crate::error::{Error, Result};
+use crate::handle::Owned;
+
+/// The image behind an image binding. Which kind of image it is, storage or sampled,
+/// comes from the descriptor type the shader declares.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageDescription {
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    /// `TYPE_2D` or `TYPE_3D`
+    pub image_type: vk::ImageType,
+    /// Filter of the sampler made for a combined image sampler
+    pub filter: vk::Filter,
+    /// Address mode of the sampler made for a combined image sampler, on every axis
+    pub address_mode: vk::SamplerAddressMode,
+}
+
+impl ImageDescription {
+    pub fn new_2d(format: vk::Format, width: u32, height: u32) -> ImageDescription {
+        ImageDescription {
+            format,
+            extent: vk::Extent3D { width, height, depth: 1 },
+            image_type: vk::ImageType::TYPE_2D,
+            filter: vk::Filter::NEAREST,
+            address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        }
+    }
+
+    pub fn new_3d(format: vk::Format, width: u32, height: u32, depth: u32) -> ImageDescription {
+        ImageDescription {
+            extent: vk::Extent3D { width, height, depth },
+            image_type: vk::ImageType::TYPE_3D,
+            ..ImageDescription::new_2d(format, width, height)
+        }
+    }
+
+    pub fn sampler(mut self, filter: vk::Filter, address_mode: vk::SamplerAddressMode) -> ImageDescription {
+        self.filter = filter;
+        self.address_mode = address_mode;
+        self
+    }
+
+    pub fn texel_count(&self) -> u64 {
+        self.extent.width as u64 * self.extent.height as u64 * self.extent.depth as u64
+    }
+
+    /// The size of the image's texels packed tightly, which is how they are laid out on the host.
+    pub fn size(&self) -> Option<u64> {
+        texel_size(self.format).map(|texel_size| texel_size * self.texel_count())
+    }
+}
+
+/// Bytes per texel of the uncompressed color formats compute shaders commonly use.
+pub fn texel_size(format: vk::Format) -> Option<u64> {
+    use vk::Format as F;
+    Some(match format {
+        F::R8_UNORM | F::R8_SNORM | F::R8_UINT | F::R8_SINT => 1,
+        F::R8G8_UNORM | F::R8G8_SNORM | F::R8G8_UINT | F::R8G8_SINT
+            | F::R16_UNORM | F::R16_SNORM | F::R16_UINT | F::R16_SINT | F::R16_SFLOAT => 2,
+        F::R8G8B8A8_UNORM | F::R8G8B8A8_SNORM | F::R8G8B8A8_UINT | F::R8G8B8A8_SINT | F::R8G8B8A8_SRGB
+            | F::B8G8R8A8_UNORM | F::B8G8R8A8_SRGB | F::A2B10G10R10_UNORM_PACK32 | F::B10G11R11_UFLOAT_PACK32
+            | F::R16G16_UNORM | F::R16G16_SNORM | F::R16G16_UINT | F::R16G16_SINT | F::R16G16_SFLOAT
+            | F::R32_UINT | F::R32_SINT | F::R32_SFLOAT => 4,
+        F::R16G16B16A16_UNORM | F::R16G16B16A16_SNORM | F::R16G16B16A16_UINT | F::R16G16B16A16_SINT
+            | F::R16G16B16A16_SFLOAT | F::R32G32_UINT | F::R32G32_SINT | F::R32G32_SFLOAT => 8,
+        F::R32G32B32_UINT | F::R32G32B32_SINT | F::R32G32B32_SFLOAT => 12,
+        F::R32G32B32A32_UINT | F::R32G32B32A32_SINT | F::R32G32B32A32_SFLOAT => 16,
+        _ => return None,
+    })
+}
+
+/// Returns an error unless the device supports `features` for `format`, in optimally tiled
+/// images or in buffers.
+pub(crate) fn check_format_features(ctx: &VkCtx, format: vk::Format, features: vk::FormatFeatureFlags, buffer: bool) -> Result<()> {
+    let properties = unsafe {
+        ctx.device.instance.get_physical_device_format_properties(ctx.device.physical_device, format)
+    };
+    let supported = if buffer { properties.buffer_features } else { properties.optimal_tiling_features };
+    if !supported.contains(features) {
+        return Err(Error::UnsupportedFormat { format, features });
+    }
+    Ok(())
+}
+
+/// A device-local image with its view, and a sampler for combined image samplers. Its
+/// texels are copied to and from the buffer of the [`LinkedMemory`] that holds it.
+///
+/// Between transfers the image stays in `layout`: `GENERAL` for storage images and
+/// `SHADER_READ_ONLY_OPTIMAL` for images that are only sampled.
+#[derive(Debug)]
+pub struct LinkedImage {
+    pub sampler: Option<Owned<Sampler>>,
+    pub view: Owned<ImageView>,
+    pub image: Owned<Image>,
+    pub memory: OwnedAllocation,
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    pub layout: vk::ImageLayout,
+}
+
+/// A layout along with the stages and accesses on one side of a transition.
+type LayoutState = (vk::ImageLayout, vk::PipelineStageFlags, vk::AccessFlags);
+
+impl LinkedImage {
+    /// Creates an image for a binding of `descriptor_type` and transitions it to the layout
+    /// it is kept in.
+    pub fn new(ctx: &VkCtx, description: &ImageDescription, descriptor_type: vk::DescriptorType) -> Result<LinkedImage> {
+        let (usage, features, layout) = match descriptor_type {
+            vk::DescriptorType::STORAGE_IMAGE => (
+                vk::ImageUsageFlags::STORAGE,
+                vk::FormatFeatureFlags::STORAGE_IMAGE,
+                vk::ImageLayout::GENERAL),
+            vk::DescriptorType::SAMPLED_IMAGE | vk::DescriptorType::COMBINED_IMAGE_SAMPLER => (
+                vk::ImageUsageFlags::SAMPLED,
+                vk::FormatFeatureFlags::SAMPLED_IMAGE,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            other => return Err(Error::Layout(format!("a {:?} binding cannot hold an image", other))),
+        };
+        let combined = descriptor_type == vk::DescriptorType::COMBINED_IMAGE_SAMPLER;
+        let features = if combined && description.filter == vk::Filter::LINEAR {
+            features | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR
+        } else {
+            features
+        };
+        check_format_features(ctx, description.format, features, false)?;
+
+        let limits = &ctx.device_info().limits;
+        let extent = description.extent;
+        let max_dimension = match description.image_type {
+            vk::ImageType::TYPE_3D => limits.max_image_dimension_3d,
+            _ => limits.max_image_dimension_2d,
+        };
+        if extent.width.max(extent.height).max(extent.depth) > max_dimension {
+            return Err(Error::Layout(format!("a {}x{}x{} image exceeds the device's limit of {} per side",
+                extent.width, extent.height, extent.depth, max_dimension)));
+        }
+
+        let image = Owned::new(&ctx.device, unsafe {
+            ctx.device.create_image(&vk::ImageCreateInfo {
+                image_type: description.image_type,
+                format: description.format,
+                extent,
+                mip_levels: 1,
+                array_layers: 1,
+                samples: vk::SampleCountFlags::TYPE_1,
+                tiling: vk::ImageTiling::OPTIMAL,
+                usage: usage | vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST,
+                sharing_mode: vk::SharingMode::EXCLUSIVE,
+                initial_layout: vk::ImageLayout::UNDEFINED,
+                ..Default::default()
+            }, None)
+        }.map_err(|r| Error::from_vk(r, Error::Allocation))?);
+        tracing::debug!(image = ?*image, format = ?description.format, width = extent.width,
+            height = extent.height, depth = extent.depth, ?usage, "created image");
+
+        let mut dedicated_requirements = vk::MemoryDedicatedRequirements::default();
+        let mut requirements = vk::MemoryRequirements2::default()
+            .push_next(&mut dedicated_requirements);
+        unsafe {
+            ctx.device.get_image_memory_requirements2(
+                &vk::ImageMemoryRequirementsInfo2::default().image(*image),
+                &mut requirements)
+        };
+        let mut requirements = requirements.memory_requirements;
+        // Images share blocks with buffers, so keep them on pages of their own
+        let granularity = limits.buffer_image_granularity;
+        requirements.alignment = requirements.alignment.max(granularity);
+        requirements.size = align_up(requirements.size, granularity);
+
+        let (required_flags, preferred_flags) = MemoryPlacement::DeviceLocal.memory_flags(ctx.device_info());
+        let memory = OwnedAllocation::allocate(&ctx.allocator, &AllocationRequest {
+            requirements,
+            required_flags,
+            preferred_flags,
+            prefers_dedicated: dedicated_requirements.prefers_dedicated_allocation == vk::TRUE,
+        })?;
+        unsafe {
+            ctx.device.bind_image_memory(*image, memory.memory, memory.offset)
+        }.map_err(|r| Error::from_vk(r, Error::Allocation))?;
+        tracing::debug!(memory = ?memory.memory, offset = memory.offset, image = ?*image, "allocated and bound image memory");
+
+        let view = Owned::new(&ctx.device, unsafe {
+            ctx.device.create_image_view(&vk::ImageViewCreateInfo {
+                image: *image,
+                view_type: match description.image_type {
+                    vk::ImageType::TYPE_3D => vk::ImageViewType::TYPE_3D,
+                    _ => vk::ImageViewType::TYPE_2D,
+                },
+                format: description.format,
+                subresource_range: SUBRESOURCE_RANGE,
+                ..Default::default()
+            }, None)
+        }.map_err(|r| Error::from_vk(r, Error::Allocation))?);
+
+        let sampler = combined.then(|| unsafe {
+            ctx.device.create_sampler(&vk::SamplerCreateInfo {
+                mag_filter: description.filter,
+                min_filter: description.filter,
+                mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+                address_mode_u: description.address_mode,
+                address_mode_v: description.address_mode,
+                address_mode_w: description.address_mode,
+                max_lod: 0.0,
+                ..Default::default()
+            }, None)
+                .map(|sampler| Owned::new(&ctx.device, sampler))
+                .map_err(|r| Error::from_vk(r, Error::Allocation))
+        }).transpose()?;
+
+        let linked_image = LinkedImage {
+            sampler,
+            view,
+            image,
+            memory,
+            format: description.format,
+            extent,
+            layout,
+        };
+        ctx.execute_commands(|device, command_buffer| linked_image.record_transition(device, command_buffer,
+            (vk::ImageLayout::UNDEFINED, vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty()),
+            linked_image.shader_state()))?;
+        Ok(linked_image)
+    }
+
+    /// The image as the shader uses it between transfers.
+    fn shader_state(&self) -> LayoutState {
+        (self.layout, vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE)
+    }
+
+    fn record_transition(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, from: LayoutState, to: LayoutState) {
+        let (old_layout, src_stage, src_access_mask) = from;
+        let (new_layout, dst_stage, dst_access_mask) = to;
+        unsafe {
+            device.cmd_pipeline_barrier(command_buffer, src_stage, dst_stage, vk::DependencyFlags::empty(), &[], &[],
+                &[vk::ImageMemoryBarrier {
+                    src_access_mask,
+                    dst_access_mask,
+                    old_layout,
+                    new_layout,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    image: *self.image,
+                    subresource_range: SUBRESOURCE_RANGE,
+                    ..Default::default()
+                }]);
+        }
+    }
+
+    fn copy_region(&self) -> vk::BufferImageCopy {
+        vk::BufferImageCopy {
+            buffer_offset: 0,
+            // Zero means the texels are packed tightly
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D::default(),
+            image_extent: self.extent,
+        }
+    }
+
+    /// Records copying the texels in `buffer` into the image.
+    pub fn record_upload(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, buffer: vk::Buffer) {
+        let transfer = (vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE);
+        self.record_transition(device, command_buffer, self.shader_state(), transfer);
+        unsafe {
+            device.cmd_copy_buffer_to_image(command_buffer, buffer, *self.image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[self.copy_region()]);
+        }
+        self.record_transition(device, command_buffer, transfer, self.shader_state());
+    }
+
+    /// Records copying the image's texels into `buffer`.
+    pub fn record_download(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, buffer: vk::Buffer) {
+        let transfer = (vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ);
+        self.record_transition(device, command_buffer, self.shader_state(), transfer);
+        unsafe {
+            device.cmd_copy_image_to_buffer(command_buffer, *self.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL, buffer, &[self.copy_region()]);
+        }
+        self.record_transition(device, command_buffer, transfer, self.shader_state());
+    }
+
+    pub fn descriptor_info(&self) -> vk::DescriptorImageInfo {
+        vk::DescriptorImageInfo {
+            sampler: self.sampler.as_ref().map_or(vk::Sampler::null(), |sampler| **sampler),
+            image_view: *self.view,
+            image_layout: self.layout,
+        }
+    }
+}
+
+const SUBRESOURCE_RANGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::COLOR,
+    base_mip_level: 0,
+    level_count: 1,
+    base_array_layer: 0,
+    layer_count: 1,
+};
+
+impl LinkedMemory {
+    /// Creates an image for a binding of `descriptor_type`, along with a host-visible buffer
+    /// holding its texels packed tightly, which is what is mapped and what the image is
+    /// uploaded from and downloaded to.
+    pub fn image(ctx: &VkCtx, binding: u32, description: &ImageDescription, descriptor_type: vk::DescriptorType, readback: bool) -> Result<LinkedMemory> {
+        let size = description.size()
+            .ok_or(Error::UnsupportedFormat { format: description.format, features: vk::FormatFeatureFlags::empty() })?;
+        let image = LinkedImage::new(ctx, description, descriptor_type)?;
+        let placement = if readback { MemoryPlacement::HostCachedReadback } else { MemoryPlacement::HostVisible };
+        let mut memory = LinkedMemory::with_usage(ctx, binding, size,
+            vk::BufferUsageFlags::empty(), placement, readback)?;
+        memory.image = Some(image);
+        Ok(memory)
+    }
+}
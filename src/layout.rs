@@ -0,0 +1,498 @@
+use std::fmt;
+use bytemuck::Pod;
+use crate::error::{Error, Result};
+
+/// The rules used to place the members of a shader block in memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BlockLayout {
+    /// Uniform buffers: arrays and structs are aligned to 16 bytes
+    Std140,
+    /// Storage buffers and push constants
+    #[default]
+    Std430,
+    /// `GL_EXT_scalar_block_layout`: everything is aligned to its scalar type
+    Scalar,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ScalarType {
+    Float,
+    Int,
+    Uint,
+    Double,
+}
+
+impl ScalarType {
+    pub fn size(self) -> u64 {
+        match self {
+            ScalarType::Double => 8,
+            ScalarType::Float | ScalarType::Int | ScalarType::Uint => 4,
+        }
+    }
+}
+
+/// A type as the shader sees it, which decides where a host value's bytes go.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ShaderType {
+    Scalar(ScalarType),
+    Vector(ScalarType, u32),
+    /// A column-major matrix of `columns` vectors with `rows` components each
+    Matrix { scalar: ScalarType, columns: u32, rows: u32 },
+    Array(Box<ShaderType>, u32),
+    Struct(Vec<StructMember>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructMember {
+    pub name: String,
+    pub ty: ShaderType,
+}
+
+impl ShaderType {
+    pub fn alignment(&self, layout: BlockLayout) -> u64 {
+        match self {
+            ShaderType::Scalar(scalar) => scalar.size(),
+            ShaderType::Vector(scalar, components) => match layout {
+                BlockLayout::Scalar => scalar.size(),
+                // A vec3 is aligned like a vec4
+                BlockLayout::Std140 | BlockLayout::Std430 => scalar.size() * if *components == 2 { 2 } else { 4 },
+            },
+            ShaderType::Matrix { scalar, columns, rows } =>
+                ShaderType::Array(Box::new(ShaderType::Vector(*scalar, *rows)), *columns).alignment(layout),
+            ShaderType::Array(element, _) => round_to_vec4(element.alignment(layout), layout),
+            ShaderType::Struct(members) => {
+                let alignment = members.iter()
+                    .map(|member| member.ty.alignment(layout))
+                    .max()
+                    .unwrap_or(1);
+                round_to_vec4(alignment, layout)
+            }
+        }
+    }
+
+    pub fn size(&self, layout: BlockLayout) -> u64 {
+        match self {
+            ShaderType::Scalar(scalar) => scalar.size(),
+            ShaderType::Vector(scalar, components) => scalar.size() * *components as u64,
+            ShaderType::Matrix { scalar, columns, rows } =>
+                ShaderType::Vector(*scalar, *rows).array_stride(layout) * *columns as u64,
+            ShaderType::Array(element, length) => element.array_stride(layout) * *length as u64,
+            ShaderType::Struct(members) => {
+                let end = ShaderType::member_offsets(members, layout).last()
+                    .zip(members.last())
+                    .map_or(0, |(offset, member)| offset + member.ty.size(layout));
+                end.next_multiple_of(self.alignment(layout))
+            }
+        }
+    }
+
+    /// The distance between consecutive elements of an array of this type.
+    pub fn array_stride(&self, layout: BlockLayout) -> u64 {
+        let alignment = round_to_vec4(self.alignment(layout), layout);
+        self.size(layout).next_multiple_of(alignment)
+    }
+
+    /// The offset of each member of a struct, in declaration order.
+    pub fn member_offsets(members: &[StructMember], layout: BlockLayout) -> Vec<u64> {
+        let mut offset = 0u64;
+        members.iter()
+            .map(|member| {
+                let member_offset = offset.next_multiple_of(member.ty.alignment(layout));
+                offset = member_offset + member.ty.size(layout);
+                member_offset
+            })
+            .collect()
+    }
+}
+
+fn round_to_vec4(alignment: u64, layout: BlockLayout) -> u64 {
+    match layout {
+        BlockLayout::Std140 => alignment.next_multiple_of(16),
+        BlockLayout::Std430 | BlockLayout::Scalar => alignment,
+    }
+}
+
+impl fmt::Display for ShaderType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = |scalar: &ScalarType| match scalar {
+            ScalarType::Float => "",
+            ScalarType::Int => "i",
+            ScalarType::Uint => "u",
+            ScalarType::Double => "d",
+        };
+        match self {
+            ShaderType::Scalar(scalar) => write!(f, "{}", match scalar {
+                ScalarType::Float => "float",
+                ScalarType::Int => "int",
+                ScalarType::Uint => "uint",
+                ScalarType::Double => "double",
+            }),
+            ShaderType::Vector(scalar, components) => write!(f, "{}vec{}", prefix(scalar), components),
+            ShaderType::Matrix { scalar, columns, rows } if columns == rows => write!(f, "{}mat{}", prefix(scalar), columns),
+            ShaderType::Matrix { scalar, columns, rows } => write!(f, "{}mat{}x{}", prefix(scalar), columns, rows),
+            ShaderType::Array(element, length) => write!(f, "{}[{}]", element, length),
+            ShaderType::Struct(members) => {
+                write!(f, "struct {{ ")?;
+                for member in members {
+                    write!(f, "{} {}; ", member.ty, member.name)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// A host value that can be written to, and read from, a shader block.
+///
+/// Implemented for `f32`, `i32`, `u32`, `f64`, the [`Vector`] and [`Matrix`] types, arrays
+/// of any of them, and `()` for shaders without push constants. Structs implement it with
+/// `#[derive(ShaderLayout)]`, which lays their fields out as the members of a GLSL struct.
+pub trait ShaderLayout {
+    fn shader_type() -> ShaderType;
+
+    /// Writes the value to the start of `bytes`, which is at least the value's size.
+    fn write_to(&self, layout: BlockLayout, bytes: &mut [u8]);
+
+    /// Reads the value from the start of `bytes`, which is at least the value's size.
+    fn read_from(&mut self, layout: BlockLayout, bytes: &[u8]);
+}
+
+/// A scalar type that vectors and matrices can be made of.
+pub trait LayoutScalar: ShaderLayout + Pod {
+    const SCALAR: ScalarType;
+}
+
+macro_rules! scalar_layout {
+    ($($ty:ty => $scalar:ident),*) => {$(
+        impl LayoutScalar for $ty {
+            const SCALAR: ScalarType = ScalarType::$scalar;
+        }
+
+        impl ShaderLayout for $ty {
+            fn shader_type() -> ShaderType {
+                ShaderType::Scalar(ScalarType::$scalar)
+            }
+
+            fn write_to(&self, _layout: BlockLayout, bytes: &mut [u8]) {
+                bytes[..std::mem::size_of::<$ty>()].copy_from_slice(bytemuck::bytes_of(self));
+            }
+
+            fn read_from(&mut self, _layout: BlockLayout, bytes: &[u8]) {
+                *self = bytemuck::pod_read_unaligned(&bytes[..std::mem::size_of::<$ty>()]);
+            }
+        }
+    )*};
+}
+
+scalar_layout!(f32 => Float, i32 => Int, u32 => Uint, f64 => Double);
+
+/// A GLSL vector, `vecN`, `ivecN`, `uvecN` or `dvecN`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vector<T: LayoutScalar, const N: usize>(pub [T; N]);
+
+impl<T: LayoutScalar, const N: usize> Default for Vector<T, N> {
+    fn default() -> Self {
+        Vector([T::zeroed(); N])
+    }
+}
+
+pub type Vec2 = Vector<f32, 2>;
+pub type Vec3 = Vector<f32, 3>;
+pub type Vec4 = Vector<f32, 4>;
+pub type IVec2 = Vector<i32, 2>;
+pub type IVec3 = Vector<i32, 3>;
+pub type IVec4 = Vector<i32, 4>;
+pub type UVec2 = Vector<u32, 2>;
+pub type UVec3 = Vector<u32, 3>;
+pub type UVec4 = Vector<u32, 4>;
+
+impl<T: LayoutScalar, const N: usize> ShaderLayout for Vector<T, N> {
+    fn shader_type() -> ShaderType {
+        ShaderType::Vector(T::SCALAR, N as u32)
+    }
+
+    fn write_to(&self, _layout: BlockLayout, bytes: &mut [u8]) {
+        let components = bytemuck::cast_slice::<T, u8>(&self.0);
+        bytes[..components.len()].copy_from_slice(components);
+    }
+
+    fn read_from(&mut self, _layout: BlockLayout, bytes: &[u8]) {
+        let components = bytemuck::cast_slice_mut::<T, u8>(&mut self.0);
+        let len = components.len();
+        components.copy_from_slice(&bytes[..len]);
+    }
+}
+
+/// A column-major GLSL matrix with `C` columns of `R` rows, `matC` or `matCxR`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Matrix<T: LayoutScalar, const C: usize, const R: usize>(pub [[T; R]; C]);
+
+impl<T: LayoutScalar, const C: usize, const R: usize> Default for Matrix<T, C, R> {
+    fn default() -> Self {
+        Matrix([[T::zeroed(); R]; C])
+    }
+}
+
+pub type Mat2 = Matrix<f32, 2, 2>;
+pub type Mat3 = Matrix<f32, 3, 3>;
+pub type Mat4 = Matrix<f32, 4, 4>;
+
+impl<T: LayoutScalar, const C: usize, const R: usize> ShaderLayout for Matrix<T, C, R> {
+    fn shader_type() -> ShaderType {
+        ShaderType::Matrix { scalar: T::SCALAR, columns: C as u32, rows: R as u32 }
+    }
+
+    fn write_to(&self, layout: BlockLayout, bytes: &mut [u8]) {
+        let stride = ShaderType::Vector(T::SCALAR, R as u32).array_stride(layout) as usize;
+        for (i, column) in self.0.iter().enumerate() {
+            Vector(*column).write_to(layout, &mut bytes[i * stride..]);
+        }
+    }
+
+    fn read_from(&mut self, layout: BlockLayout, bytes: &[u8]) {
+        let stride = ShaderType::Vector(T::SCALAR, R as u32).array_stride(layout) as usize;
+        for (i, column) in self.0.iter_mut().enumerate() {
+            let mut vector = Vector(*column);
+            vector.read_from(layout, &bytes[i * stride..]);
+            *column = vector.0;
+        }
+    }
+}
+
+impl<T: ShaderLayout, const N: usize> ShaderLayout for [T; N] {
+    fn shader_type() -> ShaderType {
+        ShaderType::Array(Box::new(T::shader_type()), N as u32)
+    }
+
+    fn write_to(&self, layout: BlockLayout, bytes: &mut [u8]) {
+        let stride = T::shader_type().array_stride(layout) as usize;
+        for (i, element) in self.iter().enumerate() {
+            element.write_to(layout, &mut bytes[i * stride..]);
+        }
+    }
+
+    fn read_from(&mut self, layout: BlockLayout, bytes: &[u8]) {
+        let stride = T::shader_type().array_stride(layout) as usize;
+        for (i, element) in self.iter_mut().enumerate() {
+            element.read_from(layout, &bytes[i * stride..]);
+        }
+    }
+}
+
+impl ShaderLayout for () {
+    fn shader_type() -> ShaderType {
+        ShaderType::Struct(Vec::new())
+    }
+
+    fn write_to(&self, _layout: BlockLayout, _bytes: &mut [u8]) {}
+
+    fn read_from(&mut self, _layout: BlockLayout, _bytes: &[u8]) {}
+}
+
+/// The member offsets of a struct implementing [`ShaderLayout`], used by the derive.
+pub fn member_offsets<T: ShaderLayout>(layout: BlockLayout) -> Vec<usize> {
+    match T::shader_type() {
+        ShaderType::Struct(members) => ShaderType::member_offsets(&members, layout)
+            .into_iter()
+            .map(|offset| offset as usize)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Lays `value` out as `layout`, padding included.
+pub fn to_bytes<T: ShaderLayout>(value: &T, layout: BlockLayout) -> Vec<u8> {
+    let mut bytes = vec![0; T::shader_type().size(layout) as usize];
+    value.write_to(layout, &mut bytes);
+    bytes
+}
+
+/// A member of a block as the shader declares it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeclaredMember {
+    pub name: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// A block as the shader declares it, with the offsets its compiler chose.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeclaredBlock {
+    pub name: String,
+    pub size: u64,
+    pub members: Vec<DeclaredMember>,
+}
+
+impl DeclaredBlock {
+    /// Checks that `T` laid out as `layout` puts every member where the shader expects it.
+    pub fn check<T: ShaderLayout>(&self, layout: BlockLayout) -> Result<()> {
+        let host_type = T::shader_type();
+        let host_name = std::any::type_name::<T>();
+        let mismatch = |detail: String| Err(Error::Layout(format!(
+            "{} does not match the shader's block {} as {:?}: {}", host_name, self.name, layout, detail)));
+
+        let ShaderType::Struct(members) = &host_type else {
+            return mismatch(format!("{} is not a struct", host_type));
+        };
+        if members.len() != self.members.len() {
+            return mismatch(format!("it has {} members, the block has {}", members.len(), self.members.len()));
+        }
+        let offsets = ShaderType::member_offsets(members, layout);
+        for ((member, offset), declared) in members.iter().zip(offsets).zip(&self.members) {
+            let size = member.ty.size(layout);
+            if offset != declared.offset || size != declared.size {
+                return mismatch(format!(
+                    "{} {} is {} bytes at offset {}, but {} is {} bytes at offset {}",
+                    member.ty, member.name, size, offset, declared.name, declared.size, declared.offset));
+            }
+        }
+        // The block's size can be smaller, it doesn't count padding after the last member
+        let host_size = host_type.size(layout);
+        if host_size < self.size {
+            return mismatch(format!("it is {} bytes, the block is {}", host_size, self.size));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLOAT: ShaderType = ShaderType::Scalar(ScalarType::Float);
+    const VEC2: ShaderType = ShaderType::Vector(ScalarType::Float, 2);
+    const VEC3: ShaderType = ShaderType::Vector(ScalarType::Float, 3);
+
+    fn member(name: &str, ty: ShaderType) -> StructMember {
+        StructMember { name: name.to_string(), ty }
+    }
+
+    /// `struct { float x; vec3 v; float y; }`
+    fn vec3_between_floats() -> Vec<StructMember> {
+        vec![member("x", FLOAT), member("v", VEC3), member("y", FLOAT)]
+    }
+
+    /// `struct Outer { float x; Inner inner; float y; }` where `Inner` is `struct { float a; vec2 b; }`
+    fn nested() -> Vec<StructMember> {
+        let inner = ShaderType::Struct(vec![member("a", FLOAT), member("b", VEC2)]);
+        vec![member("x", FLOAT), member("inner", inner), member("y", FLOAT)]
+    }
+
+    #[test]
+    fn vec3_is_aligned_like_vec4_except_in_scalar() {
+        let members = vec3_between_floats();
+        let ty = ShaderType::Struct(members.clone());
+        for layout in [BlockLayout::Std140, BlockLayout::Std430] {
+            assert_eq!(VEC3.alignment(layout), 16);
+            // The float after a vec3 fills its fourth component
+            assert_eq!(ShaderType::member_offsets(&members, layout), [0, 16, 28]);
+            assert_eq!(ty.size(layout), 32);
+        }
+        assert_eq!(VEC3.alignment(BlockLayout::Scalar), 4);
+        assert_eq!(ShaderType::member_offsets(&members, BlockLayout::Scalar), [0, 4, 16]);
+        assert_eq!(ty.size(BlockLayout::Scalar), 20);
+    }
+
+    #[test]
+    fn std140_rounds_array_strides_to_vec4() {
+        let array = ShaderType::Array(Box::new(VEC2), 3);
+        assert_eq!(VEC2.array_stride(BlockLayout::Std140), 16);
+        assert_eq!(array.alignment(BlockLayout::Std140), 16);
+        assert_eq!(array.size(BlockLayout::Std140), 48);
+        for layout in [BlockLayout::Std430, BlockLayout::Scalar] {
+            assert_eq!(VEC2.array_stride(layout), 8);
+            assert_eq!(array.size(layout), 24);
+        }
+        assert_eq!(FLOAT.array_stride(BlockLayout::Std140), 16);
+        assert_eq!(FLOAT.array_stride(BlockLayout::Std430), 4);
+    }
+
+    #[test]
+    fn matrix_columns_are_laid_out_like_an_array() {
+        let mat3 = ShaderType::Matrix { scalar: ScalarType::Float, columns: 3, rows: 3 };
+        assert_eq!(mat3.size(BlockLayout::Std140), 48);
+        assert_eq!(mat3.size(BlockLayout::Std430), 48);
+        assert_eq!(mat3.size(BlockLayout::Scalar), 36);
+        let mat2 = ShaderType::Matrix { scalar: ScalarType::Float, columns: 2, rows: 2 };
+        assert_eq!(mat2.size(BlockLayout::Std140), 32);
+        assert_eq!(mat2.size(BlockLayout::Std430), 16);
+    }
+
+    #[test]
+    fn nested_structs_are_aligned_to_their_largest_member() {
+        let members = nested();
+        let ty = ShaderType::Struct(members.clone());
+        assert_eq!(ShaderType::member_offsets(&members, BlockLayout::Std430), [0, 8, 24]);
+        assert_eq!(ty.size(BlockLayout::Std430), 32);
+        // std140 rounds the inner struct's alignment up to 16
+        assert_eq!(ShaderType::member_offsets(&members, BlockLayout::Std140), [0, 16, 32]);
+        assert_eq!(ty.size(BlockLayout::Std140), 48);
+        assert_eq!(ShaderType::member_offsets(&members, BlockLayout::Scalar), [0, 4, 16]);
+        assert_eq!(ty.size(BlockLayout::Scalar), 20);
+    }
+
+    #[test]
+    fn arrays_are_written_at_their_stride() {
+        let value = [Vec2::default(), Vector([1.0f32, 2.0])];
+        let std140 = to_bytes(&value, BlockLayout::Std140);
+        assert_eq!(std140.len(), 32);
+        assert_eq!(&std140[16..24], bytemuck::bytes_of(&[1.0f32, 2.0]));
+        let std430 = to_bytes(&value, BlockLayout::Std430);
+        assert_eq!(std430.len(), 16);
+        assert_eq!(&std430[8..16], bytemuck::bytes_of(&[1.0f32, 2.0]));
+    }
+
+    /// A host struct laid out as [`vec3_between_floats`].
+    struct Host;
+
+    impl ShaderLayout for Host {
+        fn shader_type() -> ShaderType {
+            ShaderType::Struct(vec3_between_floats())
+        }
+
+        fn write_to(&self, _layout: BlockLayout, _bytes: &mut [u8]) {}
+
+        fn read_from(&mut self, _layout: BlockLayout, _bytes: &[u8]) {}
+    }
+
+    fn declared(offsets: [u64; 3], size: u64) -> DeclaredBlock {
+        DeclaredBlock {
+            name: "Block".to_string(),
+            size,
+            members: ["x", "v", "y"].into_iter()
+                .zip(offsets)
+                .zip([4, 12, 4])
+                .map(|((name, offset), size)| DeclaredMember { name: name.to_string(), offset, size })
+                .collect(),
+        }
+    }
+
+    fn check_error(block: &DeclaredBlock, layout: BlockLayout) -> String {
+        match block.check::<Host>(layout) {
+            Err(Error::Layout(message)) => message,
+            result => panic!("expected a layout error, got {:?}", result.map_err(|error| error.to_string())),
+        }
+    }
+
+    #[test]
+    fn declared_block_matches_its_layout() {
+        declared([0, 16, 28], 32).check::<Host>(BlockLayout::Std430).unwrap();
+        // The block doesn't count padding after its last member
+        declared([0, 16, 28], 28).check::<Host>(BlockLayout::Std430).unwrap();
+        declared([0, 4, 16], 20).check::<Host>(BlockLayout::Scalar).unwrap();
+    }
+
+    #[test]
+    fn declared_block_mismatches_are_errors() {
+        let message = check_error(&declared([0, 16, 28], 32), BlockLayout::Scalar);
+        assert!(message.ends_with("vec3 v is 12 bytes at offset 4, but v is 12 bytes at offset 16"), "{}", message);
+
+        let message = check_error(&declared([0, 16, 28], 48), BlockLayout::Std430);
+        assert!(message.ends_with("it is 32 bytes, the block is 48"), "{}", message);
+
+        let mut block = declared([0, 16, 28], 32);
+        block.members.pop();
+        let message = check_error(&block, BlockLayout::Std430);
+        assert!(message.ends_with("it has 3 members, the block has 2"), "{}", message);
+    }
+}
@@ -0,0 +1,58 @@
+//! Run Vulkan compute shaders from Rust using the Ash bindings.
+//!
+//! A [`VkCtx`] owns the instance, device, queue and pools, a [`ComputeShader`]
+//! describes its buffers through [`LayoutDescription`]s, and the
+//! [`ShaderExecutionContext`] built from it is used to write inputs, dispatch
+//! the shader and read the results back.
+
+// Lets the code generated by rscompute-derive name this crate from inside it, for the demo
+extern crate self as rscompute;
+
+pub mod allocator;
+pub mod buffer;
+pub mod cache;
+pub mod compile;
+pub mod context;
+pub mod data;
+pub mod descriptor;
+pub mod device;
+pub mod error;
+pub mod handle;
+pub mod image;
+pub mod layout;
+pub mod loader;
+pub mod pipeline;
+pub mod reflect;
+pub mod shader;
+pub mod specialization;
+pub mod validation;
+
+#[cfg(feature = "demo")]
+pub mod demo;
+#[cfg(feature = "wgsl")]
+mod wgsl;
+
+pub use ash;
+pub use ash::vk;
+pub use bytemuck;
+
+pub use allocator::{Allocation, AllocationRequest, Allocator, AllocatorStats, BlockAllocator, BlockAllocatorConfig, OwnedAllocation};
+pub use cache::ShaderCache;
+pub use compile::{CompileOptions, CompiledShader, Diagnostic, HlslOptions, HlslRegisterClass, OptimizationLevel, Severity, SpirvVersion, TargetEnv};
+pub use descriptor::{DescriptorAllocator, DescriptorAllocatorConfig, DescriptorStats};
+pub use context::{Device, Instance, VkCtx, VkCtxBuilder};
+pub use device::{DeviceSelector, PhysicalDeviceInfo, QueueFamilyInfo};
+pub use error::{Error, Result};
+pub use handle::{DeviceObject, Owned, OwnedDescriptorSet};
+pub use buffer::{GpuBuffer, MappedSlice, MappedSliceMut};
+#[cfg(feature = "derive")]
+pub use rscompute_derive::{compute_kernel, GpuMappedMemory, ShaderLayout};
+pub use image::{ImageDescription, LinkedImage};
+pub use layout::{BlockLayout, DeclaredBlock, ShaderLayout, ShaderType};
+pub use loader::{ShaderLanguage, ShaderRegistry, ShaderSource};
+pub use pipeline::{ComputeModule, ComputePipeline};
+pub use data::{GpuMappedMemory, LinkedMemory, MemoryPlacement, StagingBuffer};
+pub use validation::{ValidationMessage, ValidationMessages};
+pub use reflect::{BindingAccess, EntryPoint, ReflectedBinding, ShaderReflection, SpecializationConstant};
+pub use specialization::{SpecializationConstants, SpecializationType, SpecializationValue};
+pub use shader::{BindingResource, ComputeShader, LayoutDescription, LayoutDescriptorIndex, ShaderExecutionContext};
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use crate::compile::{compile, compile_with_warnings, CompileOptions, CompiledShader};
+use crate::error::{Error, Result};
+
+/// The first word of every SPIR-V module.
+pub const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// Words in a SPIR-V header: magic, version, generator, id bound and schema.
+const SPIRV_HEADER_WORDS: usize = 5;
+
+/// The language of a shader's source code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderLanguage {
+    Glsl,
+    Hlsl,
+    /// Translated to SPIR-V with naga rather than compiled with shaderc
+    Wgsl,
+}
+
+impl ShaderLanguage {
+    /// The language of a source file by its extension, `None` for extensions that aren't shaders.
+    /// `.hlsl` files are HLSL, `.wgsl` files are WGSL, and `.comp` and `.glsl` files are GLSL.
+    pub fn from_extension(extension: &str) -> Option<ShaderLanguage> {
+        match extension.to_ascii_lowercase().as_str() {
+            "comp" | "glsl" => Some(ShaderLanguage::Glsl),
+            "hlsl" => Some(ShaderLanguage::Hlsl),
+            "wgsl" => Some(ShaderLanguage::Wgsl),
+            _ => None,
+        }
+    }
+}
+
+/// Where a shader's SPIR-V comes from: source code compiled when it is needed, or
+/// SPIR-V that was compiled ahead of time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ShaderSource {
+    Code {
+        language: ShaderLanguage,
+        code: String,
+        /// The name compile errors refer to, usually the path the code was read from
+        file_name: String,
+    },
+    Spirv(Vec<u32>),
+}
+
+impl ShaderSource {
+    pub fn glsl(code: impl Into<String>, file_name: impl Into<String>) -> ShaderSource {
+        ShaderSource::Code { language: ShaderLanguage::Glsl, code: code.into(), file_name: file_name.into() }
+    }
+
+    pub fn hlsl(code: impl Into<String>, file_name: impl Into<String>) -> ShaderSource {
+        ShaderSource::Code { language: ShaderLanguage::Hlsl, code: code.into(), file_name: file_name.into() }
+    }
+
+    pub fn wgsl(code: impl Into<String>, file_name: impl Into<String>) -> ShaderSource {
+        ShaderSource::Code { language: ShaderLanguage::Wgsl, code: code.into(), file_name: file_name.into() }
+    }
+
+    /// The language of source code, `None` for SPIR-V.
+    pub fn language(&self) -> Option<ShaderLanguage> {
+        match self {
+            ShaderSource::Code { language, .. } => Some(*language),
+            ShaderSource::Spirv(_) => None,
+        }
+    }
+
+    /// Validates SPIR-V words, see [`spirv_from_bytes`] for what is checked.
+    pub fn spirv(words: Vec<u32>) -> Result<ShaderSource> {
+        check_spirv_header(&words)?;
+        Ok(ShaderSource::Spirv(words))
+    }
+
+    /// Reads SPIR-V from bytes, such as an `include_bytes!` of a `.spv` file.
+    pub fn spirv_bytes(bytes: &[u8]) -> Result<ShaderSource> {
+        spirv_from_bytes(bytes).map(ShaderSource::Spirv)
+    }
+
+    /// Reads a shader file, choosing how to load it by its extension: `.spv` files are SPIR-V,
+    /// and the extensions [`ShaderLanguage::from_extension`] knows are source code.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<ShaderSource> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+        let io_error = |error| Error::Io { path: path.to_path_buf(), error };
+        if extension.eq_ignore_ascii_case("spv") {
+            let bytes = std::fs::read(path).map_err(io_error)?;
+            return spirv_from_bytes(&bytes)
+                .map(ShaderSource::Spirv)
+                .map_err(|error| match error {
+                    Error::InvalidSpirv(reason) => Error::InvalidSpirv(format!("{}: {}", path.display(), reason)),
+                    other => other,
+                });
+        }
+        let language = ShaderLanguage::from_extension(extension)
+            .ok_or_else(|| Error::ShaderLoad(format!("{} is not a shader file, expected .spv, .comp, .glsl, .hlsl or .wgsl",
+                path.display())))?;
+        let code = std::fs::read_to_string(path).map_err(io_error)?;
+        tracing::debug!(path = %path.display(), ?language, bytes = code.len(), "read shader source");
+        Ok(ShaderSource::Code { language, code, file_name: path.display().to_string() })
+    }
+
+    /// The module's SPIR-V, compiling the source for `entry_point` with the default options if
+    /// it is source code.
+    pub fn to_spirv(&self, entry_point: &str) -> Result<Vec<u32>> {
+        self.to_spirv_with(entry_point, &CompileOptions::default())
+    }
+
+    /// Like [`ShaderSource::to_spirv`], compiling source code with `options`. SPIR-V is returned as it is.
+    pub fn to_spirv_with(&self, entry_point: &str, options: &CompileOptions) -> Result<Vec<u32>> {
+        match self {
+            ShaderSource::Code { language, code, file_name } => compile(*language, code, file_name, entry_point, options),
+            ShaderSource::Spirv(words) => Ok(words.clone()),
+        }
+    }
+
+    /// Like [`ShaderSource::to_spirv_with`], returning the compiler's warnings instead of logging
+    /// them. SPIR-V has no warnings.
+    pub fn compile(&self, entry_point: &str, options: &CompileOptions) -> Result<CompiledShader> {
+        match self {
+            ShaderSource::Code { language, code, file_name } =>
+                compile_with_warnings(*language, code, file_name, entry_point, options),
+            ShaderSource::Spirv(words) => Ok(CompiledShader { spirv: words.clone(), ..Default::default() }),
+        }
+    }
+}
+
+/// Converts the bytes of a SPIR-V module to words. The length must be a whole number of
+/// words, and the module must start with the SPIR-V magic number and a full header. Modules
+/// written in the other byte order, which the magic number reveals, are swapped.
+pub fn spirv_from_bytes(bytes: &[u8]) -> Result<Vec<u32>> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(Error::InvalidSpirv(format!("{} bytes is not a whole number of 4-byte words", bytes.len())));
+    }
+    let mut words = bytes.chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect::<Vec<u32>>();
+    if words.first() == Some(&SPIRV_MAGIC.swap_bytes()) {
+        tracing::debug!("swapping big-endian SPIR-V");
+        words.iter_mut().for_each(|word| *word = word.swap_bytes());
+    }
+    check_spirv_header(&words)?;
+    Ok(words)
+}
+
+fn check_spirv_header(words: &[u32]) -> Result<()> {
+    match words.first() {
+        None => Err(Error::InvalidSpirv(String::from("the module is empty"))),
+        Some(&magic) if magic != SPIRV_MAGIC =>
+            Err(Error::InvalidSpirv(format!("expected the magic number {:#010x}, found {:#010x}", SPIRV_MAGIC, magic))),
+        Some(_) if words.len() < SPIRV_HEADER_WORDS =>
+            Err(Error::InvalidSpirv(format!("{} words is too short for the {}-word header", words.len(), SPIRV_HEADER_WORDS))),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Shaders by name, so applications can ship kernels as files next to their binary and
+/// load them at runtime instead of compiling them into the crate.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderRegistry {
+    shaders: HashMap<String, ShaderSource>,
+    options: CompileOptions,
+}
+
+impl ShaderRegistry {
+    pub fn new() -> ShaderRegistry {
+        ShaderRegistry::default()
+    }
+
+    /// A registry that compiles its source code with `options`, for example to find the
+    /// headers its shaders include.
+    pub fn with_options(options: CompileOptions) -> ShaderRegistry {
+        ShaderRegistry { shaders: HashMap::new(), options }
+    }
+
+    pub fn options(&self) -> &CompileOptions {
+        &self.options
+    }
+
+    pub fn set_options(&mut self, options: CompileOptions) {
+        self.options = options;
+    }
+
+    /// Adds a shader, replacing any shader already registered under `name`.
+    pub fn insert(&mut self, name: impl Into<String>, source: ShaderSource) -> Option<ShaderSource> {
+        self.shaders.insert(name.into(), source)
+    }
+
+    /// Reads a shader file and registers it under its file name, which is returned.
+    pub fn load_file(&mut self, path: impl AsRef<Path>) -> Result<String> {
+        let path = path.as_ref();
+        let name = path.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| Error::ShaderLoad(format!("{} has no file name", path.display())))?;
+        self.insert(name.clone(), ShaderSource::from_path(path)?);
+        Ok(name)
+    }
+
+    /// Registers every shader file in a directory under its file name, such as `sqrt.comp`.
+    /// Files that aren't shaders and subdirectories are skipped. Returns how many were loaded.
+    pub fn load_dir(&mut self, dir: impl AsRef<Path>) -> Result<usize> {
+        let dir = dir.as_ref();
+        let io_error = |error| Error::Io { path: dir.to_path_buf(), error };
+        let mut paths = std::fs::read_dir(dir).map_err(io_error)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<PathBuf>>>()
+            .map_err(io_error)?;
+        paths.sort();
+
+        let mut loaded = 0;
+        for path in paths.iter().filter(|path| path.is_file() && is_shader_file(path)) {
+            self.load_file(path)?;
+            loaded += 1;
+        }
+        tracing::debug!(dir = %dir.display(), loaded, "loaded shader directory");
+        Ok(loaded)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ShaderSource> {
+        self.shaders.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.shaders.keys().map(String::as_str)
+    }
+
+    /// The SPIR-V of a registered shader, compiled for `entry_point` with the registry's options
+    /// if it is source code.
+    pub fn spirv(&self, name: &str, entry_point: &str) -> Result<Vec<u32>> {
+        self.get(name)
+            .ok_or_else(|| Error::ShaderLoad(format!("no shader named {} is registered", name)))?
+            .to_spirv_with(entry_point, &self.options)
+    }
+}
+
+fn is_shader_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("spv") || ShaderLanguage::from_extension(extension).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A header for SPIR-V 1.0 with an ID bound of 8, followed by `OpCapability Shader`.
+    const MODULE: [u32; 7] = [SPIRV_MAGIC, 0x0001_0000, 0, 8, 0, 0x0002_0011, 1];
+
+    fn invalid_spirv(result: Result<Vec<u32>>) -> String {
+        match result {
+            Err(Error::InvalidSpirv(reason)) => reason,
+            other => panic!("expected invalid SPIR-V, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn little_endian_bytes() {
+        let bytes = MODULE.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>();
+        assert_eq!(spirv_from_bytes(&bytes).unwrap(), MODULE);
+    }
+
+    #[test]
+    fn big_endian_bytes_are_swapped() {
+        let bytes = MODULE.iter().flat_map(|word| word.to_be_bytes()).collect::<Vec<u8>>();
+        assert_eq!(spirv_from_bytes(&bytes).unwrap(), MODULE);
+    }
+
+    #[test]
+    fn partial_words() {
+        let mut bytes = MODULE.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>();
+        bytes.pop();
+        assert_eq!(invalid_spirv(spirv_from_bytes(&bytes)), "27 bytes is not a whole number of 4-byte words");
+    }
+
+    #[test]
+    fn wrong_magic_number() {
+        let bytes = [0x0bad_f00du32, 0x0001_0000, 0, 8, 0].iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>();
+        assert_eq!(invalid_spirv(spirv_from_bytes(&bytes)), "expected the magic number 0x07230203, found 0x0badf00d");
+        assert!(matches!(ShaderSource::spirv(vec![0x0bad_f00d, 0, 0, 0, 0]), Err(Error::InvalidSpirv(_))));
+    }
+
+    #[test]
+    fn short_header() {
+        assert_eq!(invalid_spirv(spirv_from_bytes(&[])), "the module is empty");
+        let bytes = MODULE[..3].iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>();
+        assert_eq!(invalid_spirv(spirv_from_bytes(&bytes)), "3 words is too short for the 5-word header");
+        assert!(check_spirv_header(&MODULE[..SPIRV_HEADER_WORDS]).is_ok());
+    }
+
+    #[test]
+    fn languages_by_extension() {
+        assert_eq!(ShaderLanguage::from_extension("comp"), Some(ShaderLanguage::Glsl));
+        assert_eq!(ShaderLanguage::from_extension("glsl"), Some(ShaderLanguage::Glsl));
+        assert_eq!(ShaderLanguage::from_extension("HLSL"), Some(ShaderLanguage::Hlsl));
+        assert_eq!(ShaderLanguage::from_extension("wgsl"), Some(ShaderLanguage::Wgsl));
+        // SPIR-V isn't source code, so `from_path` and `is_shader_file` check for it first
+        assert_eq!(ShaderLanguage::from_extension("spv"), None);
+        assert_eq!(ShaderLanguage::from_extension("txt"), None);
+        assert_eq!(ShaderLanguage::from_extension(""), None);
+        assert!(is_shader_file(Path::new("shaders/kernel.SPV")));
+        assert!(!is_shader_file(Path::new("shaders/README")));
+    }
+}
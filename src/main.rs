@@ -1,53 +1,120 @@
 // Program to compute the multiplication of two matrices using a vulkan compute shader
 
-#![allow(unused)]
+use std::error::Error;
+use std::process::ExitCode;
 
-pub mod context;
-pub mod demo;
-pub mod shader;
-pub mod data;
+use rscompute::demo::matrix_multiplication_kernel::MatrixMultiplicationKernel;
+use rscompute::demo::matrix_nx_m::MatrixNxM;
+use rscompute::demo::multiply_nx_m_shader::MatrixNxMShader;
+use rscompute::{CompileOptions, ComputeShader, ShaderLayout, ShaderSource, VkCtx};
 
-use crate::context::VkCtx;
-use crate::shader::ComputeShader;
+pub fn main() -> ExitCode {
+    // Display rather than Debug, so compile errors show their source excerpts
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-use std::error::Error;
-use ash::util::*;
-use ash::vk;
-use crate::demo::matrix_nx_m::MatrixNxM;
-use crate::demo::multiply_nx_m_shader::MatrixNxMShader;
+/// Compiles a shader file without running anything, printing the compiler's warnings.
+fn check_shader(path: &str, entry_point: &str) -> Result<(), Box<dyn Error>> {
+    let compiled = ShaderSource::from_path(path)?.compile(entry_point, &CompileOptions::new())?;
+    for warning in &compiled.warnings {
+        eprintln!("{}", warning);
+    }
+    println!("Compiled {} to {} words with {} warnings", path, compiled.spirv.len(), compiled.warnings.len());
+    Ok(())
+}
+
+/// Builds the shader's pipeline and buffers, writes its inputs, runs it and reads the results back.
+fn dispatch<TPushConstants: ShaderLayout>(ctx: &VkCtx, shader: &mut impl ComputeShader<TPushConstants>) -> Result<(), Box<dyn Error>> {
+    println!("Building shader context");
+    let mut shader_ctx = shader.build_shader_context(ctx)?;
+    println!("Writing inputs to shader");
+    shader.write_inputs(ctx, &mut shader_ctx.write_buffers)?;
+    println!("Running shader");
+    shader.run_shader(ctx, &shader_ctx)?;
+    println!("Reading results from shader");
+    shader.read_result(ctx, &mut shader_ctx.read_buffers)?;
+    Ok(())
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let args = std::env::args().collect::<Vec<String>>();
+    if let Some(position) = args.iter().position(|arg| arg == "--compile") {
+        let path = args.get(position + 1).ok_or("--compile needs a shader file")?;
+        let entry_point = match args.iter().position(|arg| arg == "--entry-point") {
+            Some(position) => args.get(position + 1).ok_or("--entry-point needs a name")?,
+            None => "main",
+        };
+        return check_shader(path, entry_point);
+    }
 
-pub fn main() -> Result<(), Box<dyn Error>> {
-    let matrix_a = MatrixNxM::new(3, vec![
-        1.0, 1.0,
-        2.0, 2.0,
-        3.0, 3.0,
+    // Not square, so a kernel that mixes up the result's rows and columns gives the wrong answer
+    let matrix_a = MatrixNxM::new(2, vec![
+        1.0, 2.0, 3.0,
+        4.0, 5.0, 6.0,
     ]);
 
-    let matrix_b = MatrixNxM::new(2, vec![
-        1.0, 1.0, 1.0,
-        2.0, 2.0, 2.0,
+    let matrix_b = MatrixNxM::new(3, vec![
+        1.0, 1.0, 0.0, 2.0,
+        2.0, 0.0, 1.0, 1.0,
+        3.0, 1.0, 1.0, 0.0,
     ]);
 
-    let entry = unsafe { ash::Entry::load().expect("Failed to load ash Entry") };
+    let entry = unsafe { ash::Entry::load()? };
+
+    if args.iter().any(|arg| arg == "--list-devices") {
+        for device in VkCtx::builder().entry(entry).enumerate_devices()? {
+            println!("{}", device);
+        }
+        return Ok(());
+    }
 
     println!("Creating Vulkan context");
-    let ctx = unsafe {
-        VkCtx::create_compute_ctx(&entry).expect("Failed to create Vulkan context")
-    };
+    let mut builder = VkCtx::builder()
+        .entry(entry)
+        .collect_validation_messages(true);
+    if let Some(position) = args.iter().position(|arg| arg == "--cache-dir") {
+        builder = builder.shader_cache(args.get(position + 1).ok_or("--cache-dir needs a directory")?);
+    }
+    let ctx = builder.build()?;
+    println!("Using {}", ctx.device_info());
 
     println!("Creating shader");
-    let mut shader = MatrixNxMShader::new(matrix_a, matrix_b);
-    {
-        println!("Building shader context");
-        let shader_ctx = shader.build_shader_context(&ctx).expect("Failed to build shader context");
-        println!("Writing inputs to shader");
-        shader.write_inputs(&ctx, &shader_ctx.write_buffers);
-        println!("Running shader");
-        shader.run_shader(&ctx, &shader_ctx);
-        println!("Reading results from shader");
-        shader.read_result(&ctx, &shader_ctx.read_buffers);
-        println!("Destroying shader context");
-        shader_ctx.destroy(&ctx.device, &ctx.descriptor_pool);
+    let mut shader = if args.iter().any(|arg| arg == "--hlsl") {
+        MatrixNxMShader::new_hlsl(matrix_a, matrix_b)
+    } else if args.iter().any(|arg| arg == "--wgsl") {
+        MatrixNxMShader::new_wgsl(matrix_a, matrix_b)
+    } else {
+        MatrixNxMShader::new(matrix_a, matrix_b)
+    };
+    if args.iter().any(|arg| arg == "--kernel") {
+        // The same matrices and sizes, run by the kernel written in Rust
+        let sizes = shader.get_push_constants().ok_or("the matrix shader has no sizes")?;
+        let mut kernel = MatrixMultiplicationKernel {
+            a: shader.a.data.clone(),
+            b: shader.b.data.clone(),
+            c: shader.result.data.clone(),
+            acbr: sizes.acbr,
+            ar: sizes.ar,
+            bc: sizes.bc,
+            invocations: [sizes.ar, sizes.bc, 1],
+        };
+        dispatch(&ctx, &mut kernel)?;
+        shader.result.data = kernel.c;
+    } else {
+        dispatch(&ctx, &mut shader)?;
+    }
+
+    if let Some(messages) = ctx.validation_messages() {
+        let errors = messages.errors();
+        if !errors.is_empty() {
+            return Err(format!("{} validation errors, first: {}", errors.len(), errors[0].message).into());
+        }
     }
 
     println!("Multiplied matrices: ");
@@ -57,8 +124,11 @@ pub fn main() -> Result<(), Box<dyn Error>> {
     println!("to get");
     shader.result.print();
 
-    ctx.destroy();
-
+    let expected = shader.a.multiply(&shader.b);
+    if shader.result.data != expected.data {
+        println!("but expected");
+        expected.print();
+        return Err("the shader's result is wrong".into());
+    }
     Ok(())
 }
-
@@ -0,0 +1,312 @@
+use std::ffi::CString;
+use std::sync::Arc;
+use ash::vk;
+use crate::context::VkCtx;
+use crate::device::ComputeLimits;
+use crate::error::{Error, Result};
+use crate::compile::CompileOptions;
+use crate::handle::{Owned, OwnedDescriptorSet};
+use crate::loader::{ShaderLanguage, ShaderSource};
+use crate::reflect::ShaderReflection;
+use crate::specialization::SpecializationConstants;
+
+/// A shader module and what it declares. Pipelines for any of its compute entry points are
+/// created from it, so a module with several kernels is only compiled and loaded once.
+#[derive(Debug)]
+pub struct ComputeModule {
+    pub shader_module: Arc<Owned<vk::ShaderModule>>,
+    pub reflection: ShaderReflection,
+    /// The language of the source the module was compiled from, `None` when it was created
+    /// from SPIR-V. WGSL modules have their overrides compiled in, so take no specialization constants.
+    pub language: Option<ShaderLanguage>,
+}
+
+/// A pipeline for one entry point of a [`ComputeModule`]. Fields are dropped in declaration
+/// order, so the pipeline is destroyed before the layouts it was created with.
+#[derive(Debug)]
+pub struct ComputePipeline {
+    pub pipeline: Owned<vk::Pipeline>,
+    pub pipeline_layout: Owned<vk::PipelineLayout>,
+    /// One per set the entry point uses, indexed by set number
+    pub descriptor_set_layouts: Vec<Owned<vk::DescriptorSetLayout>>,
+    pub shader_module: Arc<Owned<vk::ShaderModule>>,
+    pub entry_point: String,
+    /// What the entry point declares, see [`ShaderReflection::for_entry_point`]
+    pub reflection: ShaderReflection,
+    /// The workgroup size after specialization
+    pub local_size: [u32; 3],
+}
+
+impl ComputeModule {
+    pub fn new(ctx: &VkCtx, spirv: Vec<u32>) -> Result<ComputeModule> {
+        let reflection = ShaderReflection::reflect(&spirv)?;
+        let shader_module = Arc::new(ctx.create_shader_module(spirv)?);
+        Ok(ComputeModule { shader_module, reflection, language: None })
+    }
+
+    /// Compiles `source` for `entry_point` with the context's shader cache, and keeps its language.
+    pub fn from_source(ctx: &VkCtx, source: &ShaderSource, entry_point: &str, options: &CompileOptions) -> Result<ComputeModule> {
+        let mut module = ComputeModule::new(ctx, ctx.compile_shader(source, entry_point, options)?)?;
+        module.language = source.language();
+        Ok(module)
+    }
+
+    /// The names of the module's compute entry points.
+    pub fn entry_points(&self) -> impl Iterator<Item = &str> {
+        self.reflection.entry_points.iter()
+            .filter(|entry_point| entry_point.stage == vk::ShaderStageFlags::COMPUTE)
+            .map(|entry_point| entry_point.name.as_str())
+    }
+
+    /// What `entry_point` declares, failing with [`Error::Layout`] if the module has no compute
+    /// entry point of that name.
+    pub fn entry_point_reflection(&self, entry_point: &str) -> Result<ShaderReflection> {
+        self.reflection.for_entry_point(entry_point)
+            .filter(|reflection| reflection.entry_points[0].stage == vk::ShaderStageFlags::COMPUTE)
+            .ok_or_else(|| Error::Layout(format!("the shader has no compute entry point named {}, it has {}",
+                entry_point, self.entry_points().collect::<Vec<&str>>().join(", "))))
+    }
+
+    /// Creates a pipeline for `entry_point`, with descriptor set and pipeline layouts built
+    /// from what it declares.
+    pub fn create_pipeline(&self, ctx: &VkCtx, entry_point: &str, specialization: &SpecializationConstants) -> Result<ComputePipeline> {
+        let _span = tracing::debug_span!("create_pipeline", entry_point).entered();
+        let reflection = self.entry_point_reflection(entry_point)?;
+        let local_size = check_specialization_constants(ctx, &reflection, specialization)?;
+
+        // Sets the shader skips still need a layout, an empty one
+        let descriptor_set_layouts = (0..reflection.set_count().max(1))
+            .map(|set| {
+                let bindings = reflection.set_bindings(set)
+                    .map(|binding| vk::DescriptorSetLayoutBinding {
+                        binding: binding.binding,
+                        descriptor_type: binding.descriptor_type,
+                        descriptor_count: binding.count,
+                        stage_flags: vk::ShaderStageFlags::COMPUTE,
+                        ..Default::default()
+                    })
+                    .collect::<Vec<vk::DescriptorSetLayoutBinding>>();
+                Ok(Owned::new(&ctx.device, unsafe {
+                    ctx.device.create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo {
+                        binding_count: bindings.len() as u32,
+                        p_bindings: bindings.as_ptr(),
+                        ..Default::default()
+                    }, None)
+                }.map_err(|r| Error::from_vk(r, Error::Pipeline))?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let set_layout_handles = descriptor_set_layouts.iter()
+            .map(|layout| layout.handle())
+            .collect::<Vec<vk::DescriptorSetLayout>>();
+
+        // The range covers the whole block the shader declares
+        let push_constant_ranges = reflection.push_constants.iter()
+            .filter(|block| block.size > 0)
+            .map(|block| vk::PushConstantRange {
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                offset: 0,
+                size: block.size as u32,
+            })
+            .collect::<Vec<vk::PushConstantRange>>();
+
+        let pipeline_layout = Owned::new(&ctx.device, unsafe {
+            ctx.device.create_pipeline_layout(&vk::PipelineLayoutCreateInfo {
+                set_layout_count: set_layout_handles.len() as u32,
+                p_set_layouts: set_layout_handles.as_ptr(),
+                push_constant_range_count: push_constant_ranges.len() as u32,
+                p_push_constant_ranges: push_constant_ranges.as_ptr(),
+                ..Default::default()
+            }, None)
+        }.map_err(|r| Error::from_vk(r, Error::Pipeline))?);
+
+        let name = CString::new(entry_point)
+            .map_err(|_| Error::Layout(format!("the entry point name {:?} contains a nul byte", entry_point)))?;
+        let (map_entries, data) = specialization.map_entries_and_data();
+        let specialization_info = vk::SpecializationInfo::default()
+            .map_entries(&map_entries)
+            .data(&data);
+        let compute_pipeline_info = vk::ComputePipelineCreateInfo {
+            stage: vk::PipelineShaderStageCreateInfo {
+                module: **self.shader_module,
+                p_name: name.as_ptr(),
+                stage: vk::ShaderStageFlags::COMPUTE,
+                p_specialization_info: if specialization.is_empty() { std::ptr::null() } else { &specialization_info },
+                ..Default::default()
+            },
+            layout: *pipeline_layout,
+            ..Default::default()
+        };
+        let pipeline = unsafe {
+            ctx.device.create_compute_pipelines(*ctx.pipeline_cache, &[compute_pipeline_info], None)
+        }.map_err(|(_, r)| Error::from_vk(r, Error::Pipeline))?[0];
+        tracing::debug!(?pipeline, ?local_size, "created compute pipeline");
+
+        Ok(ComputePipeline {
+            pipeline: Owned::new(&ctx.device, pipeline),
+            pipeline_layout,
+            descriptor_set_layouts,
+            shader_module: self.shader_module.clone(),
+            entry_point: entry_point.to_string(),
+            reflection,
+            local_size,
+        })
+    }
+
+    /// Creates a pipeline for every compute entry point, in the order the module declares them.
+    ///
+    /// Every entry point is specialized with the same constants. Specialization constants belong
+    /// to the module, so an ID names the same constant in each entry point, and each one's
+    /// specialized local size is checked separately. Entry points that need different values,
+    /// such as different `local_size_x_id` sizes, are created one at a time with
+    /// [`ComputeModule::create_pipeline`].
+    pub fn create_pipelines(&self, ctx: &VkCtx, specialization: &SpecializationConstants) -> Result<Vec<ComputePipeline>> {
+        self.entry_points()
+            .map(|entry_point| self.create_pipeline(ctx, entry_point, specialization))
+            .collect()
+    }
+}
+
+impl ComputePipeline {
+    /// Allocates a descriptor set with the layout of `set`.
+    pub fn allocate_descriptor_set(&self, ctx: &VkCtx, set: u32) -> Result<OwnedDescriptorSet> {
+        let layout = self.descriptor_set_layouts.get(set as usize)
+            .ok_or_else(|| Error::Layout(format!("{} uses no set {}", self.entry_point, set)))?;
+        ctx.descriptor_allocator.allocate(layout.handle(), &self.reflection.pool_sizes(set))
+    }
+}
+
+/// Checks the specialization constants against the ones the shader declares, by ID and type,
+/// and returns the workgroup size they give its entry point, which must fit the device.
+fn check_specialization_constants(ctx: &VkCtx, reflection: &ShaderReflection,
+                                  constants: &SpecializationConstants) -> Result<[u32; 3]> {
+    check_declared_constants(reflection, constants)?;
+    specialized_local_size(reflection, constants, &ctx.device_info().limits)
+}
+
+fn check_declared_constants(reflection: &ShaderReflection, constants: &SpecializationConstants) -> Result<()> {
+    for (id, value) in constants.iter() {
+        let declared = reflection.specialization_constant(id)
+            .ok_or_else(|| Error::Layout(format!("specialization constant {} is set, but the shader declares none with that ID", id)))?;
+        if declared.ty() != value.ty() {
+            // Constants behind `local_size_x_id` have no name
+            let constant = match declared.name.as_str() {
+                "" => id.to_string(),
+                name => format!("{} {}", id, name),
+            };
+            return Err(Error::Layout(format!("specialization constant {} is a {}, but was set to the {} {}",
+                constant, declared.ty(), value.ty(), value)));
+        }
+    }
+    Ok(())
+}
+
+fn specialized_local_size(reflection: &ShaderReflection, constants: &SpecializationConstants,
+                          limits: &ComputeLimits) -> Result<[u32; 3]> {
+    let entry_point = &reflection.entry_points[0];
+    let local_size = entry_point.specialized_local_size(constants)
+        .ok_or_else(|| Error::Layout(format!("the entry point {} declares no valid local size", entry_point.name)))?;
+    let invocations = local_size.iter().map(|&size| size as u64).product::<u64>();
+    if local_size.contains(&0)
+        || local_size.iter().zip(limits.max_compute_work_group_size).any(|(&size, max)| size > max)
+        || invocations > limits.max_compute_work_group_invocations as u64 {
+        return Err(Error::Layout(format!("a local size of {:?} is outside the device's limits of {:?} and {} invocations",
+            local_size, limits.max_compute_work_group_size, limits.max_compute_work_group_invocations)));
+    }
+    Ok(local_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reflect::{EntryPoint, SpecializationConstant};
+    use crate::specialization::SpecializationValue;
+    use super::*;
+
+    /// A kernel with `layout(local_size_x_id = 0) in;` and `layout(constant_id = 1) const float scale`.
+    fn reflection() -> ShaderReflection {
+        ShaderReflection {
+            entry_points: vec![EntryPoint {
+                name: String::from("main"),
+                stage: vk::ShaderStageFlags::COMPUTE,
+                local_size: Some([64, 1, 1]),
+                local_size_ids: [Some(0), None, None],
+                bindings: Vec::new(),
+                uses_push_constants: false,
+            }],
+            specialization_constants: vec![
+                SpecializationConstant { id: 0, name: String::new(), default: SpecializationValue::Uint(64) },
+                SpecializationConstant { id: 1, name: String::from("scale"), default: SpecializationValue::Float(1.0) },
+            ],
+            ..ShaderReflection::default()
+        }
+    }
+
+    fn limits() -> ComputeLimits {
+        ComputeLimits {
+            max_compute_shared_memory_size: 32768,
+            max_compute_work_group_count: [65535; 3],
+            max_compute_work_group_invocations: 1024,
+            max_compute_work_group_size: [1024, 1024, 64],
+            max_push_constants_size: 128,
+            max_storage_buffer_range: u32::MAX,
+            max_uniform_buffer_range: 65536,
+            max_bound_descriptor_sets: 8,
+            max_memory_allocation_count: 4096,
+            max_texel_buffer_elements: 1 << 27,
+            max_image_dimension_2d: 16384,
+            max_image_dimension_3d: 2048,
+            min_storage_buffer_offset_alignment: 16,
+            min_uniform_buffer_offset_alignment: 64,
+            non_coherent_atom_size: 64,
+            buffer_image_granularity: 1024,
+            timestamp_period: 1.0,
+        }
+    }
+
+    fn layout_error(result: Result<impl std::fmt::Debug>) -> String {
+        match result {
+            Err(Error::Layout(message)) => message,
+            other => panic!("expected a layout error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn declared_constants_are_accepted() {
+        let constants = SpecializationConstants::new().set(0, 128u32).set(1, 0.5f32);
+        assert!(check_declared_constants(&reflection(), &constants).is_ok());
+        assert_eq!(specialized_local_size(&reflection(), &constants, &limits()).unwrap(), [128, 1, 1]);
+        assert_eq!(specialized_local_size(&reflection(), &SpecializationConstants::new(), &limits()).unwrap(), [64, 1, 1]);
+    }
+
+    #[test]
+    fn undeclared_constants_are_errors() {
+        let constants = SpecializationConstants::new().set(2, 1u32);
+        assert_eq!(layout_error(check_declared_constants(&reflection(), &constants)),
+            "specialization constant 2 is set, but the shader declares none with that ID");
+    }
+
+    #[test]
+    fn mistyped_constants_are_errors() {
+        let constants = SpecializationConstants::new().set(1, 2i32);
+        assert_eq!(layout_error(check_declared_constants(&reflection(), &constants)),
+            "specialization constant 1 scale is a float, but was set to the int 2");
+        let constants = SpecializationConstants::new().set(0, 128u64);
+        assert_eq!(layout_error(check_declared_constants(&reflection(), &constants)),
+            "specialization constant 0 is a uint, but was set to the uint64_t 128ul");
+    }
+
+    #[test]
+    fn local_sizes_must_fit_the_device() {
+        for size in [0u32, 2048] {
+            let constants = SpecializationConstants::new().set(0, size);
+            assert!(layout_error(specialized_local_size(&reflection(), &constants, &limits()))
+                .starts_with(&format!("a local size of [{}, 1, 1] is outside the device's limits", size)));
+        }
+        let mut reflection = reflection();
+        reflection.entry_points[0].local_size = Some([64, 32, 1]);
+        assert!(matches!(specialized_local_size(&reflection, &SpecializationConstants::new(), &limits()),
+            Err(Error::Layout(_))));
+        reflection.entry_points[0].local_size = None;
+        assert_eq!(layout_error(specialized_local_size(&reflection, &SpecializationConstants::new(), &limits())),
+            "the entry point main declares no valid local size");
+    }
+}
@@ -0,0 +1,1059 @@
+use std::collections::{HashMap, HashSet};
+use ash::vk;
+use rspirv::dr::{self, Instruction, Operand};
+use rspirv::spirv::{BuiltIn, Decoration, Dim, ExecutionMode, ExecutionModel, Op, StorageClass, Word};
+use crate::error::{Error, Result};
+use crate::layout::{DeclaredBlock, DeclaredMember};
+use crate::specialization::{SpecializationConstants, SpecializationType, SpecializationValue};
+
+/// How a shader uses a binding, from its `readonly` and `writeonly` qualifiers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindingAccess {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+}
+
+impl BindingAccess {
+    pub fn reads(self) -> bool {
+        self != BindingAccess::WriteOnly
+    }
+
+    pub fn writes(self) -> bool {
+        self != BindingAccess::ReadOnly
+    }
+}
+
+/// A descriptor binding declared by a shader.
+#[derive(Clone, Debug)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    /// The variable's name, or its block's name when the variable is anonymous
+    pub name: String,
+    pub descriptor_type: vk::DescriptorType,
+    /// Number of descriptors, 0 for a runtime-sized array of them
+    pub count: u32,
+    pub access: BindingAccess,
+    /// The members of a uniform or storage buffer
+    pub block: Option<DeclaredBlock>,
+}
+
+/// An entry point of a shader module.
+#[derive(Clone, Debug)]
+pub struct EntryPoint {
+    pub name: String,
+    pub stage: vk::ShaderStageFlags,
+    /// The `local_size` of a compute entry point. Sizes set by specialization constants
+    /// hold the constants' default values.
+    pub local_size: Option<[u32; 3]>,
+    /// The IDs of the specialization constants that set each dimension of `local_size`,
+    /// from `local_size_x_id` and friends
+    pub local_size_ids: [Option<u32>; 3],
+    /// The set and binding of every binding the entry point or a function it calls uses, sorted
+    pub bindings: Vec<(u32, u32)>,
+    pub uses_push_constants: bool,
+}
+
+impl EntryPoint {
+    /// The `local_size` once the specialization constants in `constants` are applied.
+    pub fn specialized_local_size(&self, constants: &SpecializationConstants) -> Option<[u32; 3]> {
+        let mut local_size = self.local_size?;
+        for (size, id) in local_size.iter_mut().zip(self.local_size_ids) {
+            if let Some(value) = id.and_then(|id| constants.get(id)) {
+                *size = value.as_u32()?;
+            }
+        }
+        Some(local_size)
+    }
+}
+
+/// A specialization constant declared with a `SpecId`, such as `layout(constant_id = 3) const uint N = 64;`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpecializationConstant {
+    pub id: u32,
+    /// Empty for constants without a name, such as those behind `local_size_x_id`
+    pub name: String,
+    pub default: SpecializationValue,
+}
+
+impl SpecializationConstant {
+    pub fn ty(&self) -> SpecializationType {
+        self.default.ty()
+    }
+}
+
+/// What a SPIR-V module declares: its entry points, descriptor bindings and push constants.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderReflection {
+    pub entry_points: Vec<EntryPoint>,
+    /// Sorted by set, then binding
+    pub bindings: Vec<ReflectedBinding>,
+    pub push_constants: Option<DeclaredBlock>,
+    /// Sorted by ID
+    pub specialization_constants: Vec<SpecializationConstant>,
+}
+
+impl ShaderReflection {
+    pub fn reflect(spirv: &[u32]) -> Result<ShaderReflection> {
+        let module = dr::load_words(spirv)
+            .map_err(|e| Error::Reflection(format!("failed to parse SPIR-V: {}", e)))?;
+        // The parser doesn't keep the header's generator word, so it is read from the words
+        let generator = spirv.get(2).copied().unwrap_or(0);
+        let reflection = Reflector::new(&module, generator).reflect()?;
+        tracing::debug!(
+            entry_points = reflection.entry_points.len(),
+            bindings = reflection.bindings.len(),
+            push_constant_size = reflection.push_constants.as_ref().map(|block| block.size),
+            specialization_constants = reflection.specialization_constants.len(),
+            "reflected shader module");
+        Ok(reflection)
+    }
+
+    pub fn entry_point(&self, name: &str) -> Option<&EntryPoint> {
+        self.entry_points.iter().find(|entry_point| entry_point.name == name)
+    }
+
+    /// The reflection of a module as one entry point sees it, `None` if it has no entry point
+    /// `name`. When the module has several entry points only the bindings and push constants
+    /// `name` uses are kept, so each gets a pipeline layout of its own. A module with one
+    /// entry point keeps everything it declares, so bindings it ignores can still be bound.
+    pub fn for_entry_point(&self, name: &str) -> Option<ShaderReflection> {
+        let entry_point = self.entry_point(name)?;
+        if self.entry_points.len() == 1 {
+            return Some(self.clone());
+        }
+        Some(ShaderReflection {
+            entry_points: vec![entry_point.clone()],
+            bindings: self.bindings.iter()
+                .filter(|binding| entry_point.bindings.contains(&(binding.set, binding.binding)))
+                .cloned()
+                .collect(),
+            push_constants: self.push_constants.clone().filter(|_| entry_point.uses_push_constants),
+            specialization_constants: self.specialization_constants.clone(),
+        })
+    }
+
+    pub fn specialization_constant(&self, id: u32) -> Option<&SpecializationConstant> {
+        self.specialization_constants.iter().find(|constant| constant.id == id)
+    }
+
+    pub fn specialization_constant_named(&self, name: &str) -> Option<&SpecializationConstant> {
+        self.specialization_constants.iter().find(|constant| constant.name == name)
+    }
+
+    pub fn binding(&self, set: u32, binding: u32) -> Option<&ReflectedBinding> {
+        self.bindings.iter().find(|reflected| reflected.set == set && reflected.binding == binding)
+    }
+
+    /// The number of descriptor set layouts the pipeline layout needs, including empty
+    /// ones for sets the shader skips.
+    pub fn set_count(&self) -> u32 {
+        self.bindings.iter().map(|binding| binding.set + 1).max().unwrap_or(0)
+    }
+
+    /// The descriptors a set needs of each type, to size the pool it is allocated from.
+    pub fn pool_sizes(&self, set: u32) -> Vec<vk::DescriptorPoolSize> {
+        self.set_bindings(set)
+            .map(|binding| vk::DescriptorPoolSize {
+                ty: binding.descriptor_type,
+                descriptor_count: binding.count,
+            })
+            .collect()
+    }
+
+    /// The bindings of one descriptor set.
+    pub fn set_bindings(&self, set: u32) -> impl Iterator<Item = &ReflectedBinding> {
+        self.bindings.iter().filter(move |binding| binding.set == set)
+    }
+}
+
+/// The decorations on one target, each with its extra operands.
+type Decorations<'a> = Vec<(Decoration, &'a [Operand])>;
+
+/// Indexes the parts of a module that reflection looks things up in.
+struct Reflector<'a> {
+    module: &'a dr::Module,
+    names: HashMap<Word, &'a str>,
+    member_names: HashMap<(Word, u32), &'a str>,
+    decorations: HashMap<Word, Decorations<'a>>,
+    member_decorations: HashMap<(Word, u32), Decorations<'a>>,
+    definitions: HashMap<Word, &'a Instruction>,
+    functions: HashMap<Word, &'a dr::Function>,
+    /// Whether naga wrote the module, which wraps variables of struct type in a block
+    wraps_struct_variables: bool,
+}
+
+/// The generator ID naga writes in the module header. It writes the bare ID rather than
+/// shifting it into the high 16 bits as the SPIR-V registry describes, so both are accepted.
+const NAGA_GENERATOR: Word = 28;
+
+fn literal(operand: Option<&Operand>) -> Option<u32> {
+    match operand? {
+        Operand::LiteralInt32(value) => Some(*value),
+        _ => None,
+    }
+}
+
+fn id(operand: Option<&Operand>) -> Option<Word> {
+    operand?.id_ref_any()
+}
+
+impl<'a> Reflector<'a> {
+    fn new(module: &'a dr::Module, generator: Word) -> Reflector<'a> {
+        let mut reflector = Reflector {
+            module,
+            names: HashMap::new(),
+            member_names: HashMap::new(),
+            decorations: HashMap::new(),
+            member_decorations: HashMap::new(),
+            definitions: HashMap::new(),
+            functions: module.functions.iter()
+                .filter_map(|function| Some((function.def_id()?, function)))
+                .collect(),
+            wraps_struct_variables: generator == NAGA_GENERATOR || generator >> 16 == NAGA_GENERATOR,
+        };
+        for instruction in &module.debug_names {
+            match (instruction.class.opcode, instruction.operands.as_slice()) {
+                (Op::Name, [Operand::IdRef(target), Operand::LiteralString(name)]) => {
+                    reflector.names.insert(*target, name);
+                }
+                (Op::MemberName, [Operand::IdRef(target), Operand::LiteralInt32(member), Operand::LiteralString(name)]) => {
+                    reflector.member_names.insert((*target, *member), name);
+                }
+                _ => {}
+            }
+        }
+        for instruction in &module.annotations {
+            match (instruction.class.opcode, instruction.operands.as_slice()) {
+                (Op::Decorate, [Operand::IdRef(target), Operand::Decoration(decoration), rest @ ..]) => {
+                    reflector.decorations.entry(*target).or_default().push((*decoration, rest));
+                }
+                (Op::MemberDecorate, [Operand::IdRef(target), Operand::LiteralInt32(member), Operand::Decoration(decoration), rest @ ..]) => {
+                    reflector.member_decorations.entry((*target, *member)).or_default().push((*decoration, rest));
+                }
+                _ => {}
+            }
+        }
+        for instruction in &module.types_global_values {
+            if let Some(result_id) = instruction.result_id {
+                reflector.definitions.insert(result_id, instruction);
+            }
+        }
+        reflector
+    }
+
+    fn reflect(&self) -> Result<ShaderReflection> {
+        let mut reflection = ShaderReflection {
+            specialization_constants: self.specialization_constants()?,
+            ..Default::default()
+        };
+        let mut binding_variables = Vec::new();
+        let mut push_constant_variable = None;
+
+        for variable in self.module.types_global_values.iter().filter(|instruction| instruction.class.opcode == Op::Variable) {
+            let Some(variable_id) = variable.result_id else { continue };
+            let Some(storage_class) = variable.operands.first().and_then(|operand| match operand {
+                Operand::StorageClass(storage_class) => Some(*storage_class),
+                _ => None,
+            }) else { continue };
+            let pointee = variable.result_type
+                .and_then(|pointer| self.definitions.get(&pointer))
+                .and_then(|pointer| id(pointer.operands.get(1)))
+                .ok_or_else(|| Error::Reflection(format!("variable %{} has no pointer type", variable_id)))?;
+
+            match storage_class {
+                StorageClass::PushConstant => {
+                    reflection.push_constants = Some(self.variable_block(pointee)?);
+                    push_constant_variable = Some(variable_id);
+                }
+                StorageClass::Uniform | StorageClass::StorageBuffer | StorageClass::UniformConstant => {
+                    if let Some(binding) = self.binding(variable_id, storage_class, pointee)? {
+                        binding_variables.push((variable_id, (binding.set, binding.binding)));
+                        reflection.bindings.push(binding);
+                    }
+                }
+                _ => {}
+            }
+        }
+        reflection.bindings.sort_by_key(|binding| (binding.set, binding.binding));
+        reflection.entry_points = self.entry_points(&binding_variables, push_constant_variable);
+        Ok(reflection)
+    }
+
+    /// The module's entry points, given the variables of its bindings and push constants.
+    fn entry_points(&self, binding_variables: &[(Word, (u32, u32))], push_constant_variable: Option<Word>) -> Vec<EntryPoint> {
+        let workgroup_size = self.workgroup_size_builtin();
+        self.module.entry_points.iter()
+            .filter_map(|instruction| {
+                let model = match instruction.operands.first()? {
+                    Operand::ExecutionModel(model) => *model,
+                    _ => return None,
+                };
+                let function = id(instruction.operands.get(1))?;
+                let name = match instruction.operands.get(2)? {
+                    Operand::LiteralString(name) => name.clone(),
+                    _ => return None,
+                };
+                let stage = match model {
+                    ExecutionModel::GLCompute => vk::ShaderStageFlags::COMPUTE,
+                    ExecutionModel::Vertex => vk::ShaderStageFlags::VERTEX,
+                    ExecutionModel::Fragment => vk::ShaderStageFlags::FRAGMENT,
+                    _ => vk::ShaderStageFlags::ALL,
+                };
+                let (local_size, local_size_ids) = match model {
+                    ExecutionModel::GLCompute => match workgroup_size {
+                        Some(constants) => self.local_size_from_constants(constants),
+                        None => self.local_size(function),
+                    }.map_or((None, [None; 3]), |(size, ids)| (Some(size), ids)),
+                    _ => (None, [None; 3]),
+                };
+                let used = self.used_ids(function, instruction.operands.get(3..).unwrap_or_default());
+                let mut bindings = binding_variables.iter()
+                    .filter(|(variable, _)| used.contains(variable))
+                    .map(|(_, binding)| *binding)
+                    .collect::<Vec<(u32, u32)>>();
+                bindings.sort();
+                let uses_push_constants = push_constant_variable.is_some_and(|variable| used.contains(&variable));
+                Some(EntryPoint { name, stage, local_size, local_size_ids, bindings, uses_push_constants })
+            })
+            .collect()
+    }
+
+    /// Every ID an entry point's interface, its function and the functions it calls refer to,
+    /// which includes every global variable it uses.
+    fn used_ids(&self, function: Word, interface: &[Operand]) -> HashSet<Word> {
+        let mut used = interface.iter().filter_map(Operand::id_ref_any).collect::<HashSet<Word>>();
+        let mut visited = HashSet::new();
+        let mut pending = vec![function];
+        while let Some(function) = pending.pop() {
+            if !visited.insert(function) {
+                continue;
+            }
+            let Some(function) = self.functions.get(&function) else { continue };
+            for instruction in function.all_inst_iter() {
+                used.extend(instruction.operands.iter().filter_map(Operand::id_ref_any));
+                if instruction.class.opcode == Op::FunctionCall {
+                    pending.extend(id(instruction.operands.first()));
+                }
+            }
+        }
+        used
+    }
+
+    /// The `LocalSize` or `LocalSizeId` execution mode of an entry point, with the
+    /// specialization constant IDs of its sizes.
+    fn local_size(&self, function: Word) -> Option<([u32; 3], [Option<u32>; 3])> {
+        self.module.execution_modes.iter()
+            .filter(|instruction| id(instruction.operands.first()) == Some(function))
+            .find_map(|instruction| match instruction.operands.get(1)? {
+                Operand::ExecutionMode(ExecutionMode::LocalSize) => Some(([
+                    literal(instruction.operands.get(2))?,
+                    literal(instruction.operands.get(3))?,
+                    literal(instruction.operands.get(4))?,
+                ], [None; 3])),
+                Operand::ExecutionMode(ExecutionMode::LocalSizeId) => self.local_size_from_constants([
+                    id(instruction.operands.get(2))?,
+                    id(instruction.operands.get(3))?,
+                    id(instruction.operands.get(4))?,
+                ]),
+                _ => None,
+            })
+    }
+
+    /// A local size given as three constants, which may be specialization constants.
+    fn local_size_from_constants(&self, constants: [Word; 3]) -> Option<([u32; 3], [Option<u32>; 3])> {
+        Some((
+            [self.constant(constants[0])?, self.constant(constants[1])?, self.constant(constants[2])?],
+            constants.map(|constant| self.spec_id(constant)),
+        ))
+    }
+
+    /// The components of the constant decorated `BuiltIn WorkgroupSize`, which overrides every
+    /// entry point's local size. `local_size_x_id` declares one.
+    fn workgroup_size_builtin(&self) -> Option<[Word; 3]> {
+        let (target, _) = self.decorations.iter()
+            .find(|(_, decorations)| decorations.iter().any(|(decoration, operands)| {
+                *decoration == Decoration::BuiltIn
+                    && matches!(operands.first(), Some(Operand::BuiltIn(BuiltIn::WorkgroupSize)))
+            }))?;
+        let composite = self.definitions.get(target)?;
+        Some([
+            id(composite.operands.first())?,
+            id(composite.operands.get(1))?,
+            id(composite.operands.get(2))?,
+        ])
+    }
+
+    fn spec_id(&self, constant: Word) -> Option<u32> {
+        self.decoration(constant, Decoration::SpecId).and_then(|operands| literal(operands.first()))
+    }
+
+    /// Every scalar constant with a `SpecId`, with its type and default value.
+    fn specialization_constants(&self) -> Result<Vec<SpecializationConstant>> {
+        let mut constants = Vec::new();
+        for instruction in &self.module.types_global_values {
+            let Some(constant) = instruction.result_id else { continue };
+            let Some(spec_id) = self.spec_id(constant) else { continue };
+            let default = match instruction.class.opcode {
+                Op::SpecConstantTrue => SpecializationValue::Bool(true),
+                Op::SpecConstantFalse => SpecializationValue::Bool(false),
+                Op::SpecConstant => {
+                    let ty = instruction.result_type.map(|ty| self.definition(ty)).transpose()?;
+                    match (ty.map(|ty| (ty.class.opcode, ty.operands.as_slice())), instruction.operands.first()) {
+                        (Some((Op::TypeInt, [Operand::LiteralInt32(32), Operand::LiteralInt32(1)])), Some(Operand::LiteralInt32(value))) =>
+                            SpecializationValue::Int(*value as i32),
+                        (Some((Op::TypeInt, [Operand::LiteralInt32(32), _])), Some(Operand::LiteralInt32(value))) =>
+                            SpecializationValue::Uint(*value),
+                        (Some((Op::TypeInt, [Operand::LiteralInt32(64), Operand::LiteralInt32(1)])), Some(Operand::LiteralInt64(value))) =>
+                            SpecializationValue::Int64(*value as i64),
+                        (Some((Op::TypeInt, [Operand::LiteralInt32(64), _])), Some(Operand::LiteralInt64(value))) =>
+                            SpecializationValue::Uint64(*value),
+                        (Some((Op::TypeFloat, [Operand::LiteralInt32(32), ..])), Some(Operand::LiteralFloat32(value))) =>
+                            SpecializationValue::Float(*value),
+                        (Some((Op::TypeFloat, [Operand::LiteralInt32(64), ..])), Some(Operand::LiteralFloat64(value))) =>
+                            SpecializationValue::Double(*value),
+                        _ => {
+                            tracing::debug!(spec_id, "skipping specialization constant of an unsupported type");
+                            continue;
+                        }
+                    }
+                }
+                // Composites and operations on specialization constants have no ID of their own
+                _ => continue,
+            };
+            constants.push(SpecializationConstant {
+                id: spec_id,
+                name: self.name(constant).unwrap_or_default().to_string(),
+                default,
+            });
+        }
+        constants.sort_by_key(|constant| constant.id);
+        Ok(constants)
+    }
+
+    /// The value of a 32-bit integer constant, or the default value of a specialization constant.
+    fn constant(&self, constant: Word) -> Option<u32> {
+        let instruction = self.definitions.get(&constant)?;
+        match instruction.class.opcode {
+            Op::Constant | Op::SpecConstant => literal(instruction.operands.first()),
+            _ => None,
+        }
+    }
+
+    fn decoration(&self, target: Word, decoration: Decoration) -> Option<&'a [Operand]> {
+        self.decorations.get(&target)?.iter()
+            .find(|(found, _)| *found == decoration)
+            .map(|(_, operands)| *operands)
+    }
+
+    fn member_decoration(&self, target: Word, member: u32, decoration: Decoration) -> Option<&'a [Operand]> {
+        self.member_decorations.get(&(target, member))?.iter()
+            .find(|(found, _)| *found == decoration)
+            .map(|(_, operands)| *operands)
+    }
+
+    fn name(&self, target: Word) -> Option<&'a str> {
+        self.names.get(&target).copied().filter(|name| !name.is_empty())
+    }
+
+    fn binding(&self, variable: Word, storage_class: StorageClass, pointee: Word) -> Result<Option<ReflectedBinding>> {
+        let (Some(set), Some(binding)) = (
+            self.decoration(variable, Decoration::DescriptorSet).and_then(|operands| literal(operands.first())),
+            self.decoration(variable, Decoration::Binding).and_then(|operands| literal(operands.first())),
+        ) else {
+            return Ok(None);
+        };
+
+        // Arrays of descriptors are bound as one binding with a count
+        let mut element = pointee;
+        let mut count = 1;
+        let definition = self.definition(element)?;
+        match definition.class.opcode {
+            Op::TypeArray => {
+                element = id(definition.operands.first()).unwrap_or(element);
+                count = id(definition.operands.get(1)).and_then(|length| self.constant(length)).unwrap_or(1);
+            }
+            Op::TypeRuntimeArray => {
+                element = id(definition.operands.first()).unwrap_or(element);
+                count = 0;
+            }
+            _ => {}
+        }
+
+        let definition = self.definition(element)?;
+        let (descriptor_type, block) = match (storage_class, definition.class.opcode) {
+            (StorageClass::StorageBuffer, Op::TypeStruct) =>
+                (vk::DescriptorType::STORAGE_BUFFER, Some(self.variable_block(element)?)),
+            (StorageClass::Uniform, Op::TypeStruct) if self.decoration(element, Decoration::BufferBlock).is_some() =>
+                (vk::DescriptorType::STORAGE_BUFFER, Some(self.variable_block(element)?)),
+            (StorageClass::Uniform, Op::TypeStruct) =>
+                (vk::DescriptorType::UNIFORM_BUFFER, Some(self.variable_block(element)?)),
+            (StorageClass::UniformConstant, Op::TypeImage) => (self.image_descriptor_type(definition), None),
+            (StorageClass::UniformConstant, Op::TypeSampler) => (vk::DescriptorType::SAMPLER, None),
+            (StorageClass::UniformConstant, Op::TypeSampledImage) => {
+                let image = id(definition.operands.first()).map(|image| self.definition(image)).transpose()?;
+                match image.map(|image| self.image_descriptor_type(image)) {
+                    Some(vk::DescriptorType::UNIFORM_TEXEL_BUFFER) => (vk::DescriptorType::UNIFORM_TEXEL_BUFFER, None),
+                    _ => (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, None),
+                }
+            }
+            (_, opcode) => {
+                tracing::debug!(variable, set, binding, ?opcode, "skipping binding of an unsupported type");
+                return Ok(None);
+            }
+        };
+
+        let access = match descriptor_type {
+            vk::DescriptorType::STORAGE_BUFFER => self.buffer_access(variable, element),
+            vk::DescriptorType::STORAGE_IMAGE | vk::DescriptorType::STORAGE_TEXEL_BUFFER => self.variable_access(variable),
+            _ => BindingAccess::ReadOnly,
+        };
+        let name = self.name(variable)
+            .or_else(|| self.name(element))
+            .map_or_else(|| format!("%{}", variable), str::to_string);
+
+        Ok(Some(ReflectedBinding { set, binding, name, descriptor_type, count, access, block }))
+    }
+
+    fn definition(&self, target: Word) -> Result<&'a Instruction> {
+        self.definitions.get(&target).copied()
+            .ok_or_else(|| Error::Reflection(format!("%{} is used but never defined", target)))
+    }
+
+    fn image_descriptor_type(&self, image: &Instruction) -> vk::DescriptorType {
+        let buffer = matches!(image.operands.get(1), Some(Operand::Dim(Dim::DimBuffer)));
+        // Sampled is 1 for images used with a sampler and 2 for storage images
+        let storage = literal(image.operands.get(5)) == Some(2);
+        match (buffer, storage) {
+            (true, true) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            (true, false) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            (false, true) => vk::DescriptorType::STORAGE_IMAGE,
+            (false, false) => vk::DescriptorType::SAMPLED_IMAGE,
+        }
+    }
+
+    fn variable_access(&self, variable: Word) -> BindingAccess {
+        match (self.decoration(variable, Decoration::NonWritable), self.decoration(variable, Decoration::NonReadable)) {
+            (Some(_), _) => BindingAccess::ReadOnly,
+            (None, Some(_)) => BindingAccess::WriteOnly,
+            (None, None) => BindingAccess::ReadWrite,
+        }
+    }
+
+    /// Buffer qualifiers are either on the variable, or on every member of its block.
+    fn buffer_access(&self, variable: Word, block: Word) -> BindingAccess {
+        let access = self.variable_access(variable);
+        if access != BindingAccess::ReadWrite {
+            return access;
+        }
+        let members = self.definitions.get(&block).map_or(0, |definition| definition.operands.len() as u32);
+        let every_member = |decoration| members > 0
+            && (0..members).all(|member| self.member_decoration(block, member, decoration).is_some());
+        if every_member(Decoration::NonWritable) {
+            BindingAccess::ReadOnly
+        } else if every_member(Decoration::NonReadable) {
+            BindingAccess::WriteOnly
+        } else {
+            BindingAccess::ReadWrite
+        }
+    }
+
+    /// The block of a buffer or push constant variable. naga wraps a WGSL variable of struct
+    /// type in a block whose only member is the struct at offset 0, so in modules it wrote
+    /// that block is described by the struct it wraps. Blocks from other compilers are taken
+    /// as declared, since there `uniform P { Params p; };` is a block with one member.
+    fn variable_block(&self, block: Word) -> Result<DeclaredBlock> {
+        if self.wraps_struct_variables {
+            let definition = self.definition(block)?;
+            if let (Op::TypeStruct, [operand]) = (definition.class.opcode, definition.operands.as_slice()) {
+                let wrapped = id(Some(operand)).filter(|&member_type| {
+                    self.member_decoration(block, 0, Decoration::Offset).and_then(|operands| literal(operands.first())) == Some(0)
+                        && self.definitions.get(&member_type).is_some_and(|member| member.class.opcode == Op::TypeStruct)
+                });
+                if let Some(wrapped) = wrapped {
+                    return self.declared_block(wrapped);
+                }
+            }
+        }
+        self.declared_block(block)
+    }
+
+    fn declared_block(&self, block: Word) -> Result<DeclaredBlock> {
+        let definition = self.definition(block)?;
+        if definition.class.opcode != Op::TypeStruct {
+            return Err(Error::Reflection(format!("block %{} is not a struct", block)));
+        }
+        let members = definition.operands.iter()
+            .enumerate()
+            .map(|(index, operand)| {
+                let index = index as u32;
+                let member_type = id(Some(operand))
+                    .ok_or_else(|| Error::Reflection(format!("member {} of %{} has no type", index, block)))?;
+                let offset = self.member_decoration(block, index, Decoration::Offset)
+                    .and_then(|operands| literal(operands.first()))
+                    .ok_or_else(|| Error::Reflection(format!("member {} of %{} has no offset", index, block)))?;
+                Ok(DeclaredMember {
+                    name: self.member_names.get(&(block, index))
+                        .map_or_else(|| format!("_{}", index), |name| name.to_string()),
+                    offset: offset as u64,
+                    size: self.member_size(block, index, member_type)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let size = members.iter().map(|member| member.offset + member.size).max().unwrap_or(0);
+        Ok(DeclaredBlock {
+            name: self.name(block).map_or_else(|| format!("%{}", block), str::to_string),
+            size,
+            members,
+        })
+    }
+
+    /// The size of a struct member. Matrix strides are member decorations, so matrices
+    /// (and arrays of them) need to know which member they are.
+    fn member_size(&self, block: Word, member: u32, member_type: Word) -> Result<u64> {
+        let definition = self.definition(member_type)?;
+        if definition.class.opcode == Op::TypeMatrix {
+            if let Some(stride) = self.member_decoration(block, member, Decoration::MatrixStride)
+                .and_then(|operands| literal(operands.first())) {
+                let row_major = self.member_decoration(block, member, Decoration::RowMajor).is_some();
+                let vectors = if row_major {
+                    let column = self.definition(id(definition.operands.first()).unwrap_or(member_type))?;
+                    literal(column.operands.get(1)).unwrap_or(0)
+                } else {
+                    literal(definition.operands.get(1)).unwrap_or(0)
+                };
+                return Ok(stride as u64 * vectors as u64);
+            }
+        }
+        self.type_size(member_type)
+    }
+
+    /// The size of a type inside a block, 0 for runtime-sized arrays.
+    fn type_size(&self, ty: Word) -> Result<u64> {
+        let definition = self.definition(ty)?;
+        let operand_type = |index| id(definition.operands.get(index))
+            .ok_or_else(|| Error::Reflection(format!("type %{} is missing an operand", ty)));
+        Ok(match definition.class.opcode {
+            Op::TypeBool => 4,
+            Op::TypeInt | Op::TypeFloat => literal(definition.operands.first()).unwrap_or(32) as u64 / 8,
+            Op::TypeVector | Op::TypeMatrix =>
+                self.type_size(operand_type(0)?)? * literal(definition.operands.get(1)).unwrap_or(0) as u64,
+            Op::TypeArray => {
+                let length = self.constant(operand_type(1)?).unwrap_or(0) as u64;
+                let stride = match self.decoration(ty, Decoration::ArrayStride).and_then(|operands| literal(operands.first())) {
+                    Some(stride) => stride as u64,
+                    None => self.type_size(operand_type(0)?)?,
+                };
+                stride * length
+            }
+            Op::TypeRuntimeArray => 0,
+            Op::TypeStruct => self.declared_block(ty)?.size,
+            opcode => return Err(Error::Reflection(format!("{:?} cannot be used in a block", opcode))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rspirv::binary::Assemble;
+    use rspirv::dr::{Builder, Operand};
+    use rspirv::spirv::{AddressingModel, Capability, Decoration, FunctionControl, ImageFormat, MemoryModel, StorageClass};
+    use super::*;
+
+    /// The generator word glslang writes, tool 8 version 11
+    const GLSLANG_GENERATOR: Word = 0x0008_000b;
+
+    fn builder() -> Builder {
+        let mut builder = Builder::new();
+        builder.capability(Capability::Shader);
+        builder.memory_model(AddressingModel::Logical, MemoryModel::GLSL450);
+        builder
+    }
+
+    fn assemble(builder: Builder) -> Vec<u32> {
+        let mut module = builder.module();
+        if let Some(header) = module.header.as_mut() {
+            header.generator = GLSLANG_GENERATOR;
+        }
+        module.assemble()
+    }
+
+    fn reflect(builder: Builder) -> ShaderReflection {
+        ShaderReflection::reflect(&assemble(builder)).unwrap()
+    }
+
+    /// A struct with an ID of its own, since the builder reuses the ID of an identical one.
+    fn block(builder: &mut Builder, name: &str, members: &[(&str, Word, u32)]) -> Word {
+        let id = builder.id();
+        let block = builder.type_struct_id(Some(id), members.iter().map(|(_, ty, _)| *ty));
+        builder.name(block, name);
+        for (index, (name, _, offset)) in members.iter().enumerate() {
+            builder.member_name(block, index as u32, *name);
+            builder.member_decorate(block, index as u32, Decoration::Offset, [Operand::LiteralInt32(*offset)]);
+        }
+        block
+    }
+
+    /// A variable of type `pointee` bound to `set` and `binding`.
+    fn bind(builder: &mut Builder, name: &str, pointee: Word, storage_class: StorageClass, set: u32, binding: u32) -> Word {
+        let pointer = builder.type_pointer(None, storage_class, pointee);
+        let variable = builder.variable(pointer, None, storage_class, None);
+        builder.name(variable, name);
+        builder.decorate(variable, Decoration::DescriptorSet, [Operand::LiteralInt32(set)]);
+        builder.decorate(variable, Decoration::Binding, [Operand::LiteralInt32(binding)]);
+        variable
+    }
+
+    /// A `void` function that loads each `(type, variable)` and calls each function in `calls`.
+    fn function(builder: &mut Builder, loads: &[(Word, Word)], calls: &[Word]) -> Word {
+        let void = builder.type_void();
+        let function_type = builder.type_function(void, []);
+        let function = builder.begin_function(void, None, FunctionControl::NONE, function_type).unwrap();
+        builder.begin_block(None).unwrap();
+        for &(ty, variable) in loads {
+            builder.load(ty, None, variable, None, []).unwrap();
+        }
+        for &called in calls {
+            builder.function_call(void, None, called, []).unwrap();
+        }
+        builder.ret().unwrap();
+        builder.end_function().unwrap();
+        function
+    }
+
+    /// A compute entry point `main` with no body and the given local size.
+    fn compute_main(builder: &mut Builder, local_size: [u32; 3]) -> Word {
+        let main = function(builder, &[], &[]);
+        builder.entry_point(ExecutionModel::GLCompute, main, "main", []);
+        builder.execution_mode(main, ExecutionMode::LocalSize, local_size);
+        main
+    }
+
+    /// A storage buffer `buffer Name { float values[]; }` with the given decorations on its
+    /// variable and on its member.
+    fn storage_buffer(builder: &mut Builder, binding: u32, variable_decoration: Option<Decoration>,
+                      member_decoration: Option<Decoration>) -> Word {
+        let float = builder.type_float(32);
+        let array = builder.type_runtime_array(float);
+        let name = format!("Buffer{}", binding);
+        let buffer = block(builder, &name, &[("values", array, 0)]);
+        builder.decorate(buffer, Decoration::Block, []);
+        if let Some(decoration) = member_decoration {
+            builder.member_decorate(buffer, 0, decoration, []);
+        }
+        let variable = bind(builder, &name.to_lowercase(), buffer, StorageClass::StorageBuffer, 0, binding);
+        if let Some(decoration) = variable_decoration {
+            builder.decorate(variable, decoration, []);
+        }
+        variable
+    }
+
+    fn image(builder: &mut Builder, dim: Dim, sampled: u32) -> Word {
+        let float = builder.type_float(32);
+        let format = if sampled == 2 { ImageFormat::Rgba32f } else { ImageFormat::Unknown };
+        builder.type_image(float, dim, 0, 0, 0, sampled, format, None)
+    }
+
+    #[test]
+    fn descriptor_types() {
+        let mut builder = builder();
+        compute_main(&mut builder, [1, 1, 1]);
+        storage_buffer(&mut builder, 0, None, None);
+        let float = builder.type_float(32);
+        let uniform = block(&mut builder, "Uniforms", &[("scale", float, 0)]);
+        builder.decorate(uniform, Decoration::Block, []);
+        bind(&mut builder, "uniforms", uniform, StorageClass::Uniform, 0, 1);
+        // GLSL before SPIR-V 1.3 declares storage buffers as uniform BufferBlocks
+        let legacy = block(&mut builder, "Legacy", &[("value", float, 0)]);
+        builder.decorate(legacy, Decoration::BufferBlock, []);
+        bind(&mut builder, "legacy", legacy, StorageClass::Uniform, 0, 2);
+        let storage_image = image(&mut builder, Dim::Dim2D, 2);
+        bind(&mut builder, "storage_image", storage_image, StorageClass::UniformConstant, 0, 3);
+        let sampled_image = image(&mut builder, Dim::Dim2D, 1);
+        bind(&mut builder, "sampled_image", sampled_image, StorageClass::UniformConstant, 0, 4);
+        let sampler = builder.type_sampler();
+        bind(&mut builder, "sampler", sampler, StorageClass::UniformConstant, 0, 5);
+        let combined = builder.type_sampled_image(sampled_image);
+        bind(&mut builder, "combined", combined, StorageClass::UniformConstant, 0, 6);
+        let storage_texel = image(&mut builder, Dim::DimBuffer, 2);
+        bind(&mut builder, "storage_texels", storage_texel, StorageClass::UniformConstant, 0, 7);
+        // glslang declares a samplerBuffer as a sampled buffer image
+        let buffer_image = image(&mut builder, Dim::DimBuffer, 1);
+        let uniform_texel = builder.type_sampled_image(buffer_image);
+        bind(&mut builder, "uniform_texels", uniform_texel, StorageClass::UniformConstant, 0, 8);
+        let uint = builder.type_int(32, 0);
+        let four = builder.constant_u32(uint, 4);
+        let combined_array = builder.type_array(combined, four);
+        bind(&mut builder, "textures", combined_array, StorageClass::UniformConstant, 1, 0);
+        let unsized_array = builder.type_runtime_array(storage_image);
+        bind(&mut builder, "images", unsized_array, StorageClass::UniformConstant, 1, 1);
+
+        let reflection = reflect(builder);
+        let bindings = reflection.bindings.iter()
+            .map(|binding| (binding.set, binding.binding, binding.name.as_str(), binding.descriptor_type, binding.count))
+            .collect::<Vec<_>>();
+        assert_eq!(bindings, [
+            (0, 0, "buffer0", vk::DescriptorType::STORAGE_BUFFER, 1),
+            (0, 1, "uniforms", vk::DescriptorType::UNIFORM_BUFFER, 1),
+            (0, 2, "legacy", vk::DescriptorType::STORAGE_BUFFER, 1),
+            (0, 3, "storage_image", vk::DescriptorType::STORAGE_IMAGE, 1),
+            (0, 4, "sampled_image", vk::DescriptorType::SAMPLED_IMAGE, 1),
+            (0, 5, "sampler", vk::DescriptorType::SAMPLER, 1),
+            (0, 6, "combined", vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1),
+            (0, 7, "storage_texels", vk::DescriptorType::STORAGE_TEXEL_BUFFER, 1),
+            (0, 8, "uniform_texels", vk::DescriptorType::UNIFORM_TEXEL_BUFFER, 1),
+            (1, 0, "textures", vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4),
+            (1, 1, "images", vk::DescriptorType::STORAGE_IMAGE, 0),
+        ]);
+        assert_eq!(reflection.set_count(), 2);
+        let pool_sizes = reflection.pool_sizes(1).iter()
+            .map(|size| (size.ty, size.descriptor_count))
+            .collect::<Vec<_>>();
+        assert_eq!(pool_sizes, [(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4), (vk::DescriptorType::STORAGE_IMAGE, 0)]);
+    }
+
+    #[test]
+    fn access_from_variable_and_member_decorations() {
+        let mut builder = builder();
+        compute_main(&mut builder, [1, 1, 1]);
+        storage_buffer(&mut builder, 0, None, None);
+        storage_buffer(&mut builder, 1, Some(Decoration::NonWritable), None);
+        storage_buffer(&mut builder, 2, Some(Decoration::NonReadable), None);
+        storage_buffer(&mut builder, 3, None, Some(Decoration::NonWritable));
+        storage_buffer(&mut builder, 4, None, Some(Decoration::NonReadable));
+        // A qualifier on only some members leaves the buffer read-write
+        let float = builder.type_float(32);
+        let partly = block(&mut builder, "Partly", &[("input", float, 0), ("output", float, 4)]);
+        builder.decorate(partly, Decoration::Block, []);
+        builder.member_decorate(partly, 0, Decoration::NonWritable, []);
+        bind(&mut builder, "partly", partly, StorageClass::StorageBuffer, 0, 5);
+        let storage_image = image(&mut builder, Dim::Dim2D, 2);
+        let image_variable = bind(&mut builder, "written_image", storage_image, StorageClass::UniformConstant, 0, 6);
+        builder.decorate(image_variable, Decoration::NonReadable, []);
+        bind(&mut builder, "image", storage_image, StorageClass::UniformConstant, 0, 7);
+        // Uniform buffers are read-only whatever they are decorated with
+        let uniform = block(&mut builder, "Uniforms", &[("scale", float, 0)]);
+        builder.decorate(uniform, Decoration::Block, []);
+        bind(&mut builder, "uniforms", uniform, StorageClass::Uniform, 0, 8);
+
+        let reflection = reflect(builder);
+        let access = reflection.bindings.iter().map(|binding| binding.access).collect::<Vec<_>>();
+        assert_eq!(access, [
+            BindingAccess::ReadWrite,
+            BindingAccess::ReadOnly,
+            BindingAccess::WriteOnly,
+            BindingAccess::ReadOnly,
+            BindingAccess::WriteOnly,
+            BindingAccess::ReadWrite,
+            BindingAccess::WriteOnly,
+            BindingAccess::ReadWrite,
+            BindingAccess::ReadOnly,
+        ]);
+    }
+
+    #[test]
+    fn block_members_and_sizes() {
+        let mut builder = builder();
+        compute_main(&mut builder, [1, 1, 1]);
+        let uint = builder.type_int(32, 0);
+        let float = builder.type_float(32);
+        let vec3 = builder.type_vector(float, 3);
+        let vec4 = builder.type_vector(float, 4);
+        let mat4 = builder.type_matrix(vec4, 4);
+        let four = builder.constant_u32(uint, 4);
+        let array = builder.type_array(float, four);
+        builder.decorate(array, Decoration::ArrayStride, [Operand::LiteralInt32(16)]);
+
+        let push_constants = block(&mut builder, "PushConstants", &[("n", uint, 0), ("scale", float, 4), ("direction", vec3, 16)]);
+        builder.decorate(push_constants, Decoration::Block, []);
+        let pointer = builder.type_pointer(None, StorageClass::PushConstant, push_constants);
+        builder.variable(pointer, None, StorageClass::PushConstant, None);
+
+        let uniforms = block(&mut builder, "Uniforms", &[("weights", array, 0), ("transform", mat4, 64)]);
+        builder.decorate(uniforms, Decoration::Block, []);
+        builder.member_decorate(uniforms, 1, Decoration::MatrixStride, [Operand::LiteralInt32(16)]);
+        builder.member_decorate(uniforms, 1, Decoration::ColMajor, []);
+        bind(&mut builder, "uniforms", uniforms, StorageClass::Uniform, 0, 0);
+
+        let reflection = reflect(builder);
+        let members = |block: &DeclaredBlock| block.members.iter()
+            .map(|member| (member.name.clone(), member.offset, member.size))
+            .collect::<Vec<_>>();
+        let push_constants = reflection.push_constants.as_ref().unwrap();
+        assert_eq!(push_constants.name, "PushConstants");
+        // The size ends at the last member, without padding after it
+        assert_eq!(push_constants.size, 28);
+        assert_eq!(members(push_constants), [("n".to_string(), 0, 4), ("scale".to_string(), 4, 4), ("direction".to_string(), 16, 12)]);
+        let uniforms = reflection.binding(0, 0).and_then(|binding| binding.block.as_ref()).unwrap();
+        assert_eq!(uniforms.size, 128);
+        assert_eq!(members(uniforms), [("weights".to_string(), 0, 64), ("transform".to_string(), 64, 64)]);
+    }
+
+    #[test]
+    fn literal_local_size() {
+        let mut builder = builder();
+        compute_main(&mut builder, [8, 4, 1]);
+        let reflection = reflect(builder);
+        let main = reflection.entry_point("main").unwrap();
+        assert_eq!(main.stage, vk::ShaderStageFlags::COMPUTE);
+        assert_eq!(main.local_size, Some([8, 4, 1]));
+        assert_eq!(main.local_size_ids, [None; 3]);
+        assert!(reflection.specialization_constants.is_empty());
+    }
+
+    #[test]
+    fn local_size_from_specialization_constants() {
+        // What glslang writes for `layout(local_size_x_id = 0, local_size_y_id = 1) in;`
+        // and `layout(constant_id = 3) const float scale = 1.5;`
+        let mut builder = builder();
+        compute_main(&mut builder, [1, 1, 1]);
+        let uint = builder.type_int(32, 0);
+        let uvec3 = builder.type_vector(uint, 3);
+        let x = builder.spec_constant_u32(uint, 64);
+        builder.decorate(x, Decoration::SpecId, [Operand::LiteralInt32(0)]);
+        let y = builder.spec_constant_u32(uint, 2);
+        builder.decorate(y, Decoration::SpecId, [Operand::LiteralInt32(1)]);
+        let z = builder.constant_u32(uint, 1);
+        let size = builder.spec_constant_composite(uvec3, [x, y, z]);
+        builder.decorate(size, Decoration::BuiltIn, [Operand::BuiltIn(BuiltIn::WorkgroupSize)]);
+        let float = builder.type_float(32);
+        let scale = builder.spec_constant_f32(float, 1.5);
+        builder.name(scale, "scale");
+        builder.decorate(scale, Decoration::SpecId, [Operand::LiteralInt32(3)]);
+
+        let reflection = reflect(builder);
+        let main = reflection.entry_point("main").unwrap();
+        // The built-in wins over the LocalSize execution mode
+        assert_eq!(main.local_size, Some([64, 2, 1]));
+        assert_eq!(main.local_size_ids, [Some(0), Some(1), None]);
+        let constants = SpecializationConstants::new().set(0, 16u32);
+        assert_eq!(main.specialized_local_size(&constants), Some([16, 2, 1]));
+        assert_eq!(main.specialized_local_size(&SpecializationConstants::new().set(1, 1.0f32)), None);
+
+        let constants = reflection.specialization_constants.iter()
+            .map(|constant| (constant.id, constant.name.as_str(), constant.default))
+            .collect::<Vec<_>>();
+        assert_eq!(constants, [
+            (0, "", SpecializationValue::Uint(64)),
+            (1, "", SpecializationValue::Uint(2)),
+            (3, "scale", SpecializationValue::Float(1.5)),
+        ]);
+        assert_eq!(reflection.specialization_constant_named("scale").map(|constant| constant.id), Some(3));
+    }
+
+    #[test]
+    fn bindings_are_filtered_per_entry_point() {
+        let mut builder = builder();
+        let first_buffer = storage_buffer(&mut builder, 0, None, None);
+        let second_buffer = storage_buffer(&mut builder, 1, None, None);
+        storage_buffer(&mut builder, 2, None, None);
+        let float = builder.type_float(32);
+        let push_constants = block(&mut builder, "PushConstants", &[("scale", float, 0)]);
+        builder.decorate(push_constants, Decoration::Block, []);
+        let pointer = builder.type_pointer(None, StorageClass::PushConstant, push_constants);
+        let push_constant_variable = builder.variable(pointer, None, StorageClass::PushConstant, None);
+        let first_block = reflect_type(&builder, first_buffer);
+        let second_block = reflect_type(&builder, second_buffer);
+
+        let first = function(&mut builder, &[(first_block, first_buffer)], &[]);
+        builder.entry_point(ExecutionModel::GLCompute, first, "first", []);
+        builder.execution_mode(first, ExecutionMode::LocalSize, [64, 1, 1]);
+        // The second entry point only uses its resources through a function it calls
+        let helper = function(&mut builder, &[(second_block, second_buffer), (push_constants, push_constant_variable)], &[]);
+        let second = function(&mut builder, &[], &[helper]);
+        builder.entry_point(ExecutionModel::GLCompute, second, "second", []);
+        builder.execution_mode(second, ExecutionMode::LocalSize, [32, 1, 1]);
+
+        let reflection = reflect(builder);
+        assert_eq!(reflection.bindings.len(), 3);
+        let first = reflection.for_entry_point("first").unwrap();
+        assert_eq!(first.entry_points.len(), 1);
+        assert_eq!(first.entry_points[0].local_size, Some([64, 1, 1]));
+        assert_eq!(first.bindings.iter().map(|binding| binding.binding).collect::<Vec<_>>(), [0]);
+        assert!(first.push_constants.is_none());
+        let second = reflection.for_entry_point("second").unwrap();
+        assert_eq!(second.entry_points[0].bindings, [(0, 1)]);
+        assert_eq!(second.bindings.iter().map(|binding| binding.binding).collect::<Vec<_>>(), [1]);
+        assert!(second.entry_points[0].uses_push_constants);
+        assert!(second.push_constants.is_some());
+        assert!(reflection.for_entry_point("third").is_none());
+    }
+
+    #[test]
+    fn single_entry_point_keeps_unused_bindings() {
+        let mut builder = builder();
+        compute_main(&mut builder, [1, 1, 1]);
+        storage_buffer(&mut builder, 0, None, None);
+        let reflection = reflect(builder);
+        assert!(reflection.entry_points[0].bindings.is_empty());
+        assert_eq!(reflection.for_entry_point("main").unwrap().bindings.len(), 1);
+    }
+
+    /// The type a variable points to.
+    fn reflect_type(builder: &Builder, variable: Word) -> Word {
+        let module = builder.module_ref();
+        let pointer = module.types_global_values.iter()
+            .find(|instruction| instruction.result_id == Some(variable))
+            .and_then(|instruction| instruction.result_type)
+            .unwrap();
+        module.types_global_values.iter()
+            .find(|instruction| instruction.result_id == Some(pointer))
+            .and_then(|instruction| instruction.operands[1].id_ref_any())
+            .unwrap()
+    }
+
+    /// A module declaring `uniform P { Params p; } params;` at set 0 binding 0, where
+    /// `Params` is `struct { float a; float b; }`, with the given generator in its header.
+    fn single_struct_block(generator: Word) -> Vec<u32> {
+        let mut builder = Builder::new();
+        builder.capability(Capability::Shader);
+        builder.memory_model(AddressingModel::Logical, MemoryModel::GLSL450);
+        let float = builder.type_float(32);
+        let params = builder.type_struct([float, float]);
+        builder.name(params, "Params");
+        builder.member_name(params, 0, "a");
+        builder.member_name(params, 1, "b");
+        builder.member_decorate(params, 0, Decoration::Offset, [Operand::LiteralInt32(0)]);
+        builder.member_decorate(params, 1, Decoration::Offset, [Operand::LiteralInt32(4)]);
+        let block = builder.type_struct([params]);
+        builder.name(block, "P");
+        builder.member_name(block, 0, "p");
+        builder.member_decorate(block, 0, Decoration::Offset, [Operand::LiteralInt32(0)]);
+        builder.decorate(block, Decoration::Block, []);
+        let pointer = builder.type_pointer(None, StorageClass::Uniform, block);
+        let variable = builder.variable(pointer, None, StorageClass::Uniform, None);
+        builder.name(variable, "params");
+        builder.decorate(variable, Decoration::DescriptorSet, [Operand::LiteralInt32(0)]);
+        builder.decorate(variable, Decoration::Binding, [Operand::LiteralInt32(0)]);
+        let mut module = builder.module();
+        if let Some(header) = module.header.as_mut() {
+            header.generator = generator;
+        }
+        module.assemble()
+    }
+
+    #[test]
+    fn single_struct_block_is_kept_outside_naga() {
+        let reflection = ShaderReflection::reflect(&single_struct_block(GLSLANG_GENERATOR)).unwrap();
+        let block = reflection.binding(0, 0).and_then(|binding| binding.block.as_ref()).unwrap();
+        assert_eq!(block.name, "P");
+        assert_eq!(block.size, 8);
+        assert_eq!(block.members.len(), 1);
+        assert_eq!(block.members[0].name, "p");
+        assert_eq!((block.members[0].offset, block.members[0].size), (0, 8));
+    }
+
+    #[test]
+    fn naga_wrapper_block_is_unwrapped() {
+        for generator in [NAGA_GENERATOR, NAGA_GENERATOR << 16] {
+            let reflection = ShaderReflection::reflect(&single_struct_block(generator)).unwrap();
+            let block = reflection.binding(0, 0).and_then(|binding| binding.block.as_ref()).unwrap();
+            assert_eq!(block.name, "Params");
+            let members = block.members.iter()
+                .map(|member| (member.name.as_str(), member.offset, member.size))
+                .collect::<Vec<_>>();
+            assert_eq!(members, [("a", 0, 4), ("b", 4, 4)]);
+        }
+    }
+}
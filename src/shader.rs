@@ -1,98 +1,171 @@
-use std::ops::Deref;
-use std::thread;
-use std::time::Duration;
+use std::sync::Arc;
 use ash::vk;
-use ash::vk::{CommandBufferBeginInfo, ComputePipelineCreateInfo, DescriptorBufferInfo, DescriptorSetLayoutBinding, DescriptorSetLayoutCreateInfo, DescriptorType, FenceCreateInfo, PipelineBindPoint, PipelineCache, PipelineLayout, PipelineLayoutCreateInfo, PipelineShaderStageCreateInfo, ShaderStageFlags, SubmitInfo, WriteDescriptorSet};
-use vk::{DescriptorSet, DescriptorSetLayout, Pipeline, ShaderModule};
+use ash::vk::{AccessFlags, BufferView, DependencyFlags, DescriptorBufferInfo, DescriptorImageInfo, DescriptorType, MemoryBarrier, PipelineBindPoint, PipelineLayout, PipelineStageFlags, ShaderStageFlags, WriteDescriptorSet};
+use vk::{DescriptorSetLayout, Pipeline, ShaderModule};
+use crate::compile::{self, CompileOptions};
 use crate::context::VkCtx;
-use crate::data::{LinkedMemory, GpuMappedMemory};
-use crate::demo::multiply_nx_m_shader::MatrixNxMShader;
-
-#[derive(Clone, Debug)]
+use crate::data::{self, LinkedMemory, GpuMappedMemory, MemoryPlacement};
+use crate::error::{Error, Result};
+use crate::handle::{Owned, OwnedDescriptorSet};
+use crate::image::{ImageDescription, LinkedImage};
+use crate::layout::{self, BlockLayout, ShaderLayout};
+use crate::loader::{ShaderLanguage, ShaderSource};
+use crate::pipeline::{ComputeModule, ComputePipeline};
+use crate::reflect::ShaderReflection;
+use crate::specialization::SpecializationConstants;
+
+/// Everything needed to dispatch a shader. Fields are dropped in declaration order,
+/// so the buffers and pipeline are destroyed before the layouts and module they use.
+#[derive(Debug)]
 pub struct ShaderExecutionContext {
-    pub shader_module : ShaderModule,
-    pub descriptor_set_layouts: Vec<DescriptorSetLayout>,
-    pub descriptor_set: DescriptorSet,
-    pub pipeline_layout: PipelineLayout,
-    pub pipeline : Pipeline,
     pub write_buffers: Vec<LinkedMemory>,
-    pub read_buffers: Vec<LinkedMemory>
-}
-
-impl ShaderExecutionContext {
-    pub fn destroy(&self, device: &ash::Device, descriptor_set_pool: &vk::DescriptorPool) {
-        unsafe {
-            println!("Destroying buffers");
-            for buffer in self.write_buffers.iter() {
-                buffer.destroy(device);
-            }
-            for buffer in self.read_buffers.iter() {
-                buffer.destroy(device);
-            }
-            println!("Destroying pipeline");
-            device.destroy_pipeline(self.pipeline, None);
-            println!("Destroying pipeline layout");
-            device.destroy_pipeline_layout(self.pipeline_layout, None);
-            println!("Freeing descriptor sets");
-            device.free_descriptor_sets(*descriptor_set_pool, &[self.descriptor_set]);
-            println!("Destroying descriptor set layouts");
-            for layout in self.descriptor_set_layouts.iter() {
-                device.destroy_descriptor_set_layout(*layout, None);
-            }
-            println!("Destroying shader module");
-            device.destroy_shader_module(self.shader_module, None);
-        }
-    }
+    pub read_buffers: Vec<LinkedMemory>,
+    pub pipeline : Owned<Pipeline>,
+    pub pipeline_layout: Owned<PipelineLayout>,
+    /// One per set the shader declares, indexed by set number
+    pub descriptor_sets: Vec<OwnedDescriptorSet>,
+    pub descriptor_set_layouts: Vec<Owned<DescriptorSetLayout>>,
+    /// Shared with the [`ComputeModule`] and any other pipelines created from it
+    pub shader_module: Arc<Owned<ShaderModule>>,
+    /// What the shader declares, which the layouts above were built from
+    pub reflection: ShaderReflection,
+    /// The workgroup size of the pipeline, after specialization
+    pub local_size: [u32; 3],
 }
 
 pub struct LayoutDescription {
+    pub set: u32,
     pub binding: u32,
     pub buffer_size: u64,
-    pub index: LayoutDescriptorIndex
+    pub index: LayoutDescriptorIndex,
+    pub placement: MemoryPlacement,
+    pub resource: BindingResource,
+}
+
+/// What is bound to a binding. The exact descriptor type, such as uniform or storage
+/// buffer, comes from the shader.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BindingResource {
+    /// A uniform or storage buffer
+    #[default]
+    Buffer,
+    /// A uniform or storage texel buffer read as texels of a format
+    TexelBuffer(vk::Format),
+    /// A storage image, sampled image or combined image sampler. `buffer_size` must be the size of
+    /// its texels packed tightly, which is how they are written and read on the host.
+    Image(ImageDescription),
 }
 
 impl LayoutDescription {
 
-    pub fn create_linked_memory(&self, ctx: &VkCtx) -> Result<LinkedMemory, vk::Result> {
-        let buffer = unsafe {
-            let buffer_info = vk::BufferCreateInfo {
-                size: self.buffer_size,
-                usage: vk::BufferUsageFlags::STORAGE_BUFFER,
-                ..Default::default()
-            };
+    pub fn create_linked_memory(&self, ctx: &VkCtx, descriptor_type: DescriptorType) -> Result<LinkedMemory> {
+        let readback = matches!(self.index, LayoutDescriptorIndex::ReadIndex(_));
+        match &self.resource {
+            BindingResource::Buffer => LinkedMemory::with_usage(ctx, self.binding, self.buffer_size,
+                data::buffer_usage(descriptor_type), self.placement, readback),
+            BindingResource::TexelBuffer(format) => LinkedMemory::texel_buffer(ctx, self.binding, self.buffer_size,
+                *format, descriptor_type, self.placement, readback),
+            BindingResource::Image(description) => LinkedMemory::image(ctx, self.binding, description, descriptor_type, readback),
+        }
+    }
 
-            ctx.device.create_buffer(&buffer_info, None)
-        }?;
-        println!("Created buffer {:?} for binding {}", buffer, self.binding);
+    /// Whether the shader's descriptor type for the binding can hold this resource.
+    fn accepts(&self, descriptor_type: DescriptorType) -> bool {
+        match self.resource {
+            BindingResource::Buffer =>
+                matches!(descriptor_type, DescriptorType::STORAGE_BUFFER | DescriptorType::UNIFORM_BUFFER),
+            BindingResource::TexelBuffer(_) =>
+                matches!(descriptor_type, DescriptorType::UNIFORM_TEXEL_BUFFER | DescriptorType::STORAGE_TEXEL_BUFFER),
+            BindingResource::Image(_) => matches!(descriptor_type,
+                DescriptorType::STORAGE_IMAGE | DescriptorType::SAMPLED_IMAGE | DescriptorType::COMBINED_IMAGE_SAMPLER),
+        }
+    }
+}
 
-        let memory = unsafe {
-            let mem_requirements = unsafe {
-                ctx.device.get_buffer_memory_requirements(buffer)
-            };
+/// The shader's compile options for `source`, with its specialization constants as the WGSL overrides.
+fn source_options<TPushConstants: ShaderLayout>(shader: &(impl ComputeShader<TPushConstants> + ?Sized),
+                                                source: &ShaderSource) -> CompileOptions {
+    let mut options = shader.compile_options();
+    if source.language() == Some(ShaderLanguage::Wgsl) {
+        options.wgsl_overrides = shader.get_specialization_constants();
+    }
+    options
+}
 
-            let memory_type_index = unsafe {
-                ctx.find_memory_type(
-                    mem_requirements.memory_type_bits,
-                    vk::MemoryPropertyFlags::HOST_VISIBLE
-                        | vk::MemoryPropertyFlags::HOST_COHERENT)
-                    .expect("Failed to find suitable memory type")
-            };
+/// The descriptor type the shader declares for a binding, which the layout descriptors
+/// have already been checked against.
+fn descriptor_type(reflection: &ShaderReflection, set: u32, binding: u32) -> DescriptorType {
+    reflection.binding(set, binding)
+        .map_or(DescriptorType::STORAGE_BUFFER, |binding| binding.descriptor_type)
+}
 
-            let memory_info = vk::MemoryAllocateInfo {
-                allocation_size: mem_requirements.size,
-                memory_type_index,
+/// Points bindings of descriptor sets at linked memory, each written as the descriptor
+/// type the shader declares for it.
+fn write_descriptors(device: &ash::Device, reflection: &ShaderReflection, writes: &[(vk::DescriptorSet, u32, &LinkedMemory)]) {
+    // Only the info matching each binding's descriptor type is read
+    let buffer_info = writes.iter()
+        .map(|(_, _, memory)| {
+            DescriptorBufferInfo {
+                buffer: *memory.buffer,
+                offset: 0,
+                range: memory.size
+            }
+        })
+        .collect::<Vec<DescriptorBufferInfo>>();
+    let image_info = writes.iter()
+        .map(|(_, _, memory)| memory.image.as_ref().map_or_else(Default::default, LinkedImage::descriptor_info))
+        .collect::<Vec<DescriptorImageInfo>>();
+    let texel_views = writes.iter()
+        .map(|(_, _, memory)| memory.texel_view.as_ref().map_or(BufferView::null(), |view| **view))
+        .collect::<Vec<BufferView>>();
+
+    let write_descriptor_sets = writes.iter()
+        .enumerate()
+        .map(|(i, (descriptor_set, set, memory))| {
+            WriteDescriptorSet {
+                dst_set: *descriptor_set,
+                dst_binding: memory.binding,
+                descriptor_count: 1,
+                descriptor_type: descriptor_type(reflection, *set, memory.binding),
+                p_buffer_info: &buffer_info[i],
+                p_image_info: &image_info[i],
+                p_texel_buffer_view: &texel_views[i],
                 ..Default::default()
-            };
+            }
+        })
+        .collect::<Vec<WriteDescriptorSet>>();
+
+    unsafe {
+        device.update_descriptor_sets(&write_descriptor_sets, &[]);
+    }
+}
 
-            ctx.device.allocate_memory(&memory_info, None)
-        }?;
-        println!("Allocated memory {:?} for buffer {:?}", memory, buffer);
-        println!("Binding buffer {:?} to memory {:?}", buffer, memory);
-        unsafe {
-            ctx.device.bind_buffer_memory(buffer, memory, 0)
-        }?;
+impl ShaderExecutionContext {
+    /// Allocates another descriptor set with the layout of `set`, for bindings that change
+    /// between dispatches. Write it with [`ShaderExecutionContext::write_descriptor`] and put it
+    /// in `descriptor_sets[set]` to dispatch with it.
+    pub fn allocate_descriptor_set(&self, ctx: &VkCtx, set: u32) -> Result<OwnedDescriptorSet> {
+        let layout = self.descriptor_set_layouts.get(set as usize)
+            .ok_or_else(|| Error::Layout(format!("the shader declares no set {}", set)))?;
+        ctx.descriptor_allocator.allocate(layout.handle(), &self.reflection.pool_sizes(set))
+    }
 
-        Ok(LinkedMemory { binding: self.binding, buffer, memory })
+    /// Points `memory`'s binding in a descriptor set with the layout of `set` at `memory`.
+    pub fn write_descriptor(&self, ctx: &VkCtx, descriptor_set: &OwnedDescriptorSet, set: u32, memory: &LinkedMemory) -> Result<()> {
+        let binding = self.reflection.binding(set, memory.binding)
+            .ok_or_else(|| Error::Layout(format!("set {} binding {} is not declared by the shader", set, memory.binding)))?;
+        let holds = match binding.descriptor_type {
+            DescriptorType::STORAGE_IMAGE | DescriptorType::SAMPLED_IMAGE | DescriptorType::COMBINED_IMAGE_SAMPLER =>
+                memory.image.is_some(),
+            DescriptorType::UNIFORM_TEXEL_BUFFER | DescriptorType::STORAGE_TEXEL_BUFFER => memory.texel_view.is_some(),
+            _ => true,
+        };
+        if !holds {
+            return Err(Error::Layout(format!("set {} binding {} ({}) is a {:?}, which the memory cannot be bound as",
+                set, binding.binding, binding.name, binding.descriptor_type)));
+        }
+        write_descriptors(&ctx.device, &self.reflection, &[(**descriptor_set, set, memory)]);
+        Ok(())
     }
 }
 
@@ -111,37 +184,51 @@ impl LayoutDescription {
 }
 
 
-pub trait ComputeShader<TPushConstants : Sized> {
+pub trait ComputeShader<TPushConstants : ShaderLayout> {
+    /**
+     * The shader's source code or SPIR-V, which was built in, read from a file or looked
+     * up in a [`crate::loader::ShaderRegistry`]
+     */
+    fn get_source(&self) -> Result<ShaderSource>;
+
+    /**
+     * The options the source is compiled with, such as the defines and include paths it needs
+     */
+    fn compile_options(&self) -> CompileOptions {
+        CompileOptions::default()
+    }
+
+    /**
+     * Compiles the source to SPIR-V, or reads it from the context's shader cache. WGSL is
+     * translated with the specialization constants as its overrides.
+     */
+    fn get_spirv(&self, ctx: &VkCtx) -> Result<Vec<u32>> {
+        let source = self.get_source()?;
+        ctx.compile_shader(&source, self.get_entry_point(), &source_options(self, &source))
+    }
+
     /**
-     * Reads or creates the SPIR-V binary for the shader
+     * The entry point the pipeline runs, which is also the one source code is compiled for
      */
-    fn get_spirv() -> Result<Vec<u32>, vk::Result>;
-
-    fn compile_to_spirv(source: &str, file_name: &str, entry_point: &str) -> Result<Vec<u32>, vk::Result> {
-        let mut compiler = shaderc::Compiler::new().expect("Failed to create shader compiler");
-        let mut options = shaderc::CompileOptions::new().expect("Failed to create shader compile options");
-        let binary_result = compiler.compile_into_spirv(
-            source,
-            shaderc::ShaderKind::Compute,
-            file_name,
-            entry_point,
-            Some(&options)).expect("Failed to compile shader");
-        Ok(binary_result.as_binary().to_vec())
+    fn get_entry_point(&self) -> &str {
+        "main"
     }
 
-    fn get_write_buffers(&self, ctx: &VkCtx) -> Vec<LinkedMemory> {
+    fn compile_to_spirv(source: &str, file_name: &str, entry_point: &str) -> Result<Vec<u32>> {
+        compile::compile(ShaderLanguage::Glsl, source, file_name, entry_point, &CompileOptions::default())
+    }
+
+    fn get_write_buffers(&self, ctx: &VkCtx, reflection: &ShaderReflection) -> Result<Vec<LinkedMemory>> {
         self.get_layout_descriptors().iter()
             .filter(|descriptor| matches!(descriptor.index, LayoutDescriptorIndex::WriteIndex(_)))
-            .map(|writable| {
-                writable.create_linked_memory(ctx).expect("Failed to create linked memory for writable buffer")
-            }).collect()
+            .map(|writable| writable.create_linked_memory(ctx, descriptor_type(reflection, writable.set, writable.binding)))
+            .collect()
     }
-    fn get_read_buffers(&self, ctx: &VkCtx) -> Vec<LinkedMemory> {
+    fn get_read_buffers(&self, ctx: &VkCtx, reflection: &ShaderReflection) -> Result<Vec<LinkedMemory>> {
         self.get_layout_descriptors().iter()
             .filter(|descriptor| matches!(descriptor.index, LayoutDescriptorIndex::ReadIndex(_)))
-            .map(|readable| {
-                readable.create_linked_memory(ctx).expect("Failed to create linked memory for readable buffer")
-            }).collect()
+            .map(|readable| readable.create_linked_memory(ctx, descriptor_type(reflection, readable.set, readable.binding)))
+            .collect()
     }
 
     fn get_layout_descriptors(&self) -> Vec<LayoutDescription>;
@@ -154,6 +241,15 @@ pub trait ComputeShader<TPushConstants : Sized> {
         None
     }
 
+    /**
+     * Values for the shader's specialization constants, such as a workgroup size declared
+     * with `local_size_x_id`, set when the pipeline is built. For WGSL they are the values
+     * of the `override`s with those `@id`s, which are set when the source is translated.
+     */
+    fn get_specialization_constants(&self) -> SpecializationConstants {
+        SpecializationConstants::default()
+    }
+
     fn get_group_vec(&self) -> (u32, u32, u32) {
         (1, 1, 1)
     }
@@ -161,224 +257,225 @@ pub trait ComputeShader<TPushConstants : Sized> {
     /**
      * Writes the contents of the shaders inputs to the GPU
      */
-    fn write_inputs(&self, ctx: &VkCtx, write_buffers: &Vec<LinkedMemory>) -> vk::Result {
+    fn write_inputs(&self, ctx: &VkCtx, write_buffers: &mut [LinkedMemory]) -> Result<()> {
+        let _span = tracing::debug_span!("write_inputs", buffers = write_buffers.len()).entered();
         let write_descriptors = self.get_layout_descriptors();
         let writables = self.get_writables();
 
         for descriptor in write_descriptors.iter()
             .filter(|descriptor| matches!(descriptor.index, LayoutDescriptorIndex::WriteIndex(_))) {
-            let result = writables[descriptor.get_index()].write(ctx, &write_buffers[descriptor.get_index()]);
-            if result != vk::Result::SUCCESS {
-                return result;
-            }
+            writables[descriptor.get_index()].write(ctx, &mut write_buffers[descriptor.get_index()])?;
         }
-        vk::Result::SUCCESS
+        if write_buffers.iter().any(|buffer| buffer.is_staged()) {
+            tracing::trace!("uploading staged inputs");
+            ctx.execute_commands(|device, command_buffer| {
+                for buffer in write_buffers.iter() {
+                    buffer.record_upload(device, command_buffer);
+                }
+            })?;
+        }
+        Ok(())
     }
 
     /**
      * Reads the results of the compute shader from the GPU
      */
-    fn read_result(&mut self, ctx: &VkCtx, read_buffers: &Vec<LinkedMemory>) -> vk::Result {
+    fn read_result(&mut self, ctx: &VkCtx, read_buffers: &mut [LinkedMemory]) -> Result<()> {
+        let _span = tracing::debug_span!("read_result", buffers = read_buffers.len()).entered();
+        if read_buffers.iter().any(|buffer| buffer.is_staged()) {
+            tracing::trace!("downloading staged results");
+            ctx.execute_commands(|device, command_buffer| unsafe {
+                for buffer in read_buffers.iter() {
+                    buffer.record_download(device, command_buffer);
+                }
+                device.cmd_pipeline_barrier(command_buffer,
+                    PipelineStageFlags::TRANSFER, PipelineStageFlags::HOST, DependencyFlags::empty(),
+                    &[MemoryBarrier::default()
+                        .src_access_mask(AccessFlags::TRANSFER_WRITE)
+                        .dst_access_mask(AccessFlags::HOST_READ)],
+                    &[], &[]);
+            })?;
+        }
+
         let read_descriptors = self.get_layout_descriptors();
         let mut readables = self.get_readables();
 
         for descriptor in read_descriptors.iter()
             .filter(|descriptor| matches!(descriptor.index, LayoutDescriptorIndex::ReadIndex(_))) {
-            let result = readables[descriptor.get_index()].read(ctx, &read_buffers[descriptor.get_index()]);
-            if result != vk::Result::SUCCESS {
-                return result;
-            }
+            readables[descriptor.get_index()].read(ctx, &mut read_buffers[descriptor.get_index()])?;
         }
-        vk::Result::SUCCESS
+        Ok(())
     }
 
     /**
      * Dispatch the compute shader to execute the compute operation
      */
-    fn run_shader(&self, ctx: &VkCtx, module: &ShaderExecutionContext) -> vk::Result {
-        let pipeline = module.pipeline;
-        let command_buffer = ctx.command_buffer;
-        let descriptor_set = module.descriptor_set;
-        let pipeline_layout = module.pipeline_layout;
+    fn run_shader(&self, ctx: &VkCtx, module: &ShaderExecutionContext) -> Result<()> {
+        let pipeline = *module.pipeline;
+        let descriptor_sets = module.descriptor_sets.iter()
+            .map(|descriptor_set| **descriptor_set)
+            .collect::<Vec<vk::DescriptorSet>>();
+        let pipeline_layout = *module.pipeline_layout;
 
         let (group_count_x, group_count_y, group_count_z) = self.get_group_vec();
+        let _span = tracing::debug_span!("dispatch",
+            pipeline = ?pipeline, group_count_x, group_count_y, group_count_z).entered();
 
-        unsafe {
-            let fence =
-                ctx.device.create_fence(&FenceCreateInfo {
-                    ..Default::default()
-                }, None)
-                .expect("Failed to create fence");
-            ctx.device.begin_command_buffer(command_buffer, &CommandBufferBeginInfo::default());
-            ctx.device.cmd_bind_pipeline(command_buffer, PipelineBindPoint::COMPUTE, pipeline);
-            ctx.device.cmd_bind_descriptor_sets(command_buffer, PipelineBindPoint::COMPUTE, pipeline_layout, 0, &[descriptor_set], &[]);
-
-            let push_constants = self.get_push_constants();
-            if let Some(push_constants) = push_constants {
-                let push_constants_as_u8 =
-                    std::slice::from_raw_parts(
-                        &push_constants as *const TPushConstants as *const u8,
-                        std::mem::size_of_val(&push_constants));
-
-                ctx.device.cmd_push_constants(
+        let push_constants = self.get_push_constants()
+            .map(|push_constants| layout::to_bytes(&push_constants, BlockLayout::Std430));
+        ctx.execute_commands(|device, command_buffer| unsafe {
+            device.cmd_bind_pipeline(command_buffer, PipelineBindPoint::COMPUTE, pipeline);
+            device.cmd_bind_descriptor_sets(command_buffer, PipelineBindPoint::COMPUTE, pipeline_layout, 0, &descriptor_sets, &[]);
+
+            if let Some(push_constants) = &push_constants {
+                device.cmd_push_constants(
                     command_buffer,
                     pipeline_layout,
                     ShaderStageFlags::COMPUTE,
                     0,
-                    &push_constants_as_u8);
+                    push_constants);
             }
 
-            ctx.device.cmd_dispatch(command_buffer, group_count_x, group_count_y, group_count_z);
-            ctx.device.end_command_buffer(command_buffer);
-            ctx.device.queue_submit(ctx.queue, &[SubmitInfo {
-                command_buffer_count: 1,
-                p_command_buffers: &command_buffer,
-                ..Default::default()
-            }], fence);
-            ctx.device.wait_for_fences(&[fence], true, u64::MAX);
-            ctx.device.destroy_fence(fence, None);
-        }
-
-        //thread::sleep(Duration::from_secs(1));
-
-        vk::Result::SUCCESS
+            device.cmd_dispatch(command_buffer, group_count_x, group_count_y, group_count_z);
+            // Make the results visible to mapped reads and to the copies out of staged buffers
+            device.cmd_pipeline_barrier(command_buffer,
+                PipelineStageFlags::COMPUTE_SHADER, PipelineStageFlags::HOST | PipelineStageFlags::TRANSFER,
+                DependencyFlags::empty(),
+                &[MemoryBarrier::default()
+                    .src_access_mask(AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(AccessFlags::HOST_READ | AccessFlags::TRANSFER_READ)],
+                &[], &[]);
+        })
     }
 
-    fn create_compute_pipeline(layouts: &Vec<DescriptorSetLayout>, pipeline_layout: &PipelineLayout,
-                               ctx: &VkCtx, shader_module: &ShaderModule) -> Result<Pipeline, vk::Result> {
-        let compute_pipeline_info = ComputePipelineCreateInfo {
-            stage: PipelineShaderStageCreateInfo {
-                module: shader_module.clone(),
-                p_name: b"main\0".as_ptr() as *const std::ffi::c_char,
-                stage: ShaderStageFlags::COMPUTE,
-                ..Default::default()
-            },
-            layout: pipeline_layout.clone(),
-            ..Default::default()
-        };
-
-        let compute_infos = [compute_pipeline_info];
-
-        let pipelines = unsafe {
-            ctx.device.create_compute_pipelines(PipelineCache::null(), &compute_infos, None)
-        }.expect("Failed to create compute pipeline");
-
-        Ok(pipelines[0])
+    /**
+     * Checks the layout descriptors against the bindings the shader declares: every
+     * binding must be described, with a buffer large enough for its block, and data only
+     * flows in through bindings the shader reads and out through bindings it writes.
+     */
+    fn check_layout_descriptors(reflection: &ShaderReflection, layout_descriptors: &[LayoutDescription]) -> Result<()> {
+        for descriptor in layout_descriptors {
+            let Some(binding) = reflection.binding(descriptor.set, descriptor.binding) else {
+                return Err(Error::Layout(format!("set {} binding {} is not declared by the shader",
+                    descriptor.set, descriptor.binding)));
+            };
+            if !descriptor.accepts(binding.descriptor_type) {
+                return Err(Error::Layout(format!("binding {} ({}) is a {:?}, which cannot hold a {:?}",
+                    binding.binding, binding.name, binding.descriptor_type, descriptor.resource)));
+            }
+            if let BindingResource::Image(image) = &descriptor.resource {
+                if image.size().is_some_and(|size| size != descriptor.buffer_size) {
+                    return Err(Error::Layout(format!("binding {} ({}) is {} bytes, but its {:?} texels are {}",
+                        binding.binding, binding.name, descriptor.buffer_size, image.format, image.size().unwrap_or(0))));
+                }
+            }
+            match descriptor.index {
+                LayoutDescriptorIndex::WriteIndex(_) if !binding.access.reads() =>
+                    return Err(Error::Layout(format!("binding {} ({}) is an input, but the shader declares it writeonly",
+                        binding.binding, binding.name))),
+                LayoutDescriptorIndex::ReadIndex(_) if !binding.access.writes() =>
+                    return Err(Error::Layout(format!("binding {} ({}) is an output, but the shader declares it readonly",
+                        binding.binding, binding.name))),
+                _ => {}
+            }
+            if let Some(block) = binding.block.as_ref().filter(|block| block.size > descriptor.buffer_size) {
+                return Err(Error::Layout(format!("binding {} ({}) is {} bytes, but its block {} needs at least {}",
+                    binding.binding, binding.name, descriptor.buffer_size, block.name, block.size)));
+            }
+        }
+        if let Some(binding) = reflection.bindings.iter()
+            .find(|binding| !layout_descriptors.iter().any(|descriptor| descriptor.set == binding.set && descriptor.binding == binding.binding)) {
+            return Err(Error::Layout(format!("set {} binding {} ({}) has no layout descriptor",
+                binding.set, binding.binding, binding.name)));
+        }
+        Ok(())
     }
 
     /**
      * Builds the shader context, which can be used multiple times to
      * write to the shader inputs, dispatch the shader, and read the results.
+     *
+     * The descriptor set and pipeline layouts come from reflecting the shader, and the
+     * layout descriptors and push constants are checked against what it declares.
      */
-    fn build_shader_context(&self, ctx: &VkCtx) -> Result<ShaderExecutionContext, vk::Result> {
-        let shader_module = ctx.create_shader_module(
-            Self::get_spirv().expect("Failed to get SPIR-V binary for shader"))
-            .expect("Failed to create shader module");
+    fn build_shader_context(&self, ctx: &VkCtx) -> Result<ShaderExecutionContext> {
+        let _span = tracing::info_span!("build_shader_context", shader = std::any::type_name::<Self>()).entered();
+        let source = self.get_source()?;
+        let module = ComputeModule::from_source(ctx, &source, self.get_entry_point(), &source_options(self, &source))?;
+        self.build_shader_context_with(ctx, &module)
+    }
 
-        let layout_descriptors = self.get_layout_descriptors();
-        let mut descriptors = layout_descriptors.iter()
-            .map(|descriptor| {
-                DescriptorSetLayoutBinding {
-                    binding: descriptor.binding,
-                    descriptor_type: DescriptorType::STORAGE_BUFFER,
-                    descriptor_count: 1,
-                    stage_flags: ShaderStageFlags::COMPUTE,
-                    ..Default::default()
-                }
-            })
-            .collect::<Vec<DescriptorSetLayoutBinding>>();
-
-        let descriptor_set_layouts = vec![
-            unsafe {
-                ctx.device.create_descriptor_set_layout(&DescriptorSetLayoutCreateInfo {
-                    binding_count: descriptors.len() as u32,
-                    p_bindings: descriptors.as_ptr(),
-                    ..Default::default()
-                }, None)
-            }.expect("Failed to create descriptor set layout")
-        ];
-
-        let descriptor_set = unsafe {
-            ctx.device.allocate_descriptor_sets(&vk::DescriptorSetAllocateInfo {
-                descriptor_pool: ctx.descriptor_pool,
-                descriptor_set_count: 1,
-                p_set_layouts: descriptor_set_layouts.as_ptr(),
-                ..Default::default()
-            }).expect("Failed to allocate descriptor sets")[0]
-        };
+    /**
+     * Builds the shader context from a module that was already loaded, such as one with an
+     * entry point for each of several shaders, using the entry point of [`ComputeShader::get_entry_point`].
+     * A WGSL module must have been created with [`ComputeModule::from_source`], with the shader's
+     * specialization constants as its [`CompileOptions::wgsl_overrides`] as [`ComputeShader::get_spirv`]
+     * sets them, since its language decides whether the constants were compiled in.
+     */
+    fn build_shader_context_with(&self, ctx: &VkCtx, module: &ComputeModule) -> Result<ShaderExecutionContext> {
+        let entry_point = self.get_entry_point();
+        let reflection = module.entry_point_reflection(entry_point)?;
 
-        let push_constant_range = match self.get_push_constants() {
-            Some(_) => vk::PushConstantRange {
-                stage_flags: ShaderStageFlags::COMPUTE,
-                offset: 0,
-                size: std::mem::size_of::<TPushConstants>() as u32
-            },
-            None => vk::PushConstantRange {
-                stage_flags: ShaderStageFlags::empty(),
-                offset: 0,
-                size: 0
-            }
-        };
+        let layout_descriptors = self.get_layout_descriptors();
+        Self::check_layout_descriptors(&reflection, &layout_descriptors)?;
+
+        let push_constant_size = TPushConstants::shader_type().size(BlockLayout::Std430);
+        match &reflection.push_constants {
+            Some(declared) => declared.check::<TPushConstants>(BlockLayout::Std430)?,
+            None if push_constant_size > 0 =>
+                return Err(Error::Layout(format!("{} is pushed, but the shader declares no push constants",
+                    std::any::type_name::<TPushConstants>()))),
+            None => {}
+        }
+        let max_push_constants_size = ctx.device_info().limits.max_push_constants_size as u64;
+        if push_constant_size > max_push_constants_size {
+            return Err(Error::Layout(format!("{} push constant bytes exceed the device's limit of {}",
+                push_constant_size, max_push_constants_size)));
+        }
 
-        let pipeline_layout_info = PipelineLayoutCreateInfo {
-            set_layout_count: descriptor_set_layouts.len() as u32,
-            p_set_layouts: descriptor_set_layouts.as_ptr(),
-            push_constant_range_count: 1,
-            p_push_constant_ranges: &push_constant_range,
-            ..Default::default()
+        // WGSL overrides were compiled in when the source was translated
+        let specialization = match module.language {
+            Some(ShaderLanguage::Wgsl) => SpecializationConstants::default(),
+            _ => self.get_specialization_constants(),
         };
+        let ComputePipeline {
+            pipeline,
+            pipeline_layout,
+            descriptor_set_layouts,
+            shader_module,
+            reflection,
+            local_size,
+            ..
+        } = module.create_pipeline(ctx, entry_point, &specialization)?;
 
-        let pipeline_layout = unsafe {
-            ctx.device.create_pipeline_layout(&pipeline_layout_info, None)
-        }?;
-
-        let pipeline = Self::create_compute_pipeline(&descriptor_set_layouts, &pipeline_layout, &ctx, &shader_module)
-            .expect("Failed to create compute pipeline");
+        let descriptor_sets = descriptor_set_layouts.iter()
+            .zip(0..)
+            .map(|(layout, set)| ctx.descriptor_allocator.allocate(layout.handle(), &reflection.pool_sizes(set)))
+            .collect::<Result<Vec<OwnedDescriptorSet>>>()?;
 
         let module = ShaderExecutionContext {
-            shader_module,
-            descriptor_set_layouts,
-            descriptor_set,
-            pipeline_layout,
+            write_buffers: self.get_write_buffers(ctx, &reflection)?,
+            read_buffers: self.get_read_buffers(ctx, &reflection)?,
             pipeline,
-            write_buffers: self.get_write_buffers(ctx),
-            read_buffers: self.get_read_buffers(ctx)
+            pipeline_layout,
+            descriptor_sets,
+            descriptor_set_layouts,
+            shader_module,
+            reflection,
+            local_size,
         };
 
-        let descriptor_set_layout = module.descriptor_set_layouts[0];
-        let descriptor_set = module.descriptor_set;
-
-        let buffer_info = layout_descriptors.iter()
+        let writes = layout_descriptors.iter()
             .map(|descriptor| {
-                DescriptorBufferInfo {
-                    buffer: match descriptor.index {
-                        LayoutDescriptorIndex::WriteIndex(i) => module.write_buffers[i].buffer,
-                        LayoutDescriptorIndex::ReadIndex(i) => module.read_buffers[i].buffer
-                    },
-                    offset: 0,
-                    range: descriptor.buffer_size
-                }
-            })
-            .collect::<Vec<DescriptorBufferInfo>>();
-
-        let write_descriptor_sets = buffer_info.iter()
-            .enumerate()
-            .map(|(i, buffer_info)| {
-                WriteDescriptorSet {
-                    dst_set: descriptor_set,
-                    dst_binding: layout_descriptors[i].binding,
-                    descriptor_count: 1,
-                    descriptor_type: DescriptorType::STORAGE_BUFFER,
-                    p_buffer_info: buffer_info,
-                    ..Default::default()
-                }
+                let memory = match descriptor.index {
+                    LayoutDescriptorIndex::WriteIndex(i) => &module.write_buffers[i],
+                    LayoutDescriptorIndex::ReadIndex(i) => &module.read_buffers[i]
+                };
+                (*module.descriptor_sets[descriptor.set as usize], descriptor.set, memory)
             })
-            .collect::<Vec<WriteDescriptorSet>>();
-
-        unsafe {
-            ctx.device.update_descriptor_sets(&write_descriptor_sets, &[]);
-        }
+            .collect::<Vec<_>>();
+        write_descriptors(&ctx.device, &module.reflection, &writes);
 
         Ok(module)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,28 @@
+// the HLSL version of matrix_multiplication.comp, with the same bindings and push constants
+// a and b are t0 and t1, the result is u0, which the host shifts to binding 2
+StructuredBuffer<float> a : register(t0);
+StructuredBuffer<float> b : register(t1);
+RWStructuredBuffer<float> c : register(u0);
+
+struct PushConstants {
+    uint acbr;
+    uint ar;
+    uint bc;
+};
+[[vk::push_constant]] PushConstants pc;
+
+// numthreads must be a literal, so unlike the GLSL kernel the host can't specialize it
+[numthreads(8, 8, 1)]
+void CSMain(uint3 id : SV_DispatchThreadID) {
+    uint i = id.x;
+    uint j = id.y;
+    // the last workgroups in each dimension can run past the edge of the result
+    if (i >= pc.ar || j >= pc.bc) {
+        return;
+    }
+    float sum = 0.0;
+    for (uint k = 0; k < pc.acbr; k++) {
+        sum += a[(i * pc.acbr) + k] * b[(k * pc.bc) + j];
+    }
+    c[(i * pc.bc) + j] = sum;
+}
@@ -0,0 +1,31 @@
+// the WGSL version of matrix_multiplication.comp, with the same bindings and push constants
+@group(0) @binding(0) var<storage, read> a: array<f32>;
+@group(0) @binding(1) var<storage, read> b: array<f32>;
+// output to buffer c
+@group(0) @binding(2) var<storage, read_write> c: array<f32>;
+
+struct PushConstants {
+    acbr: u32,
+    ar: u32,
+    bc: u32,
+}
+var<immediate> pc: PushConstants;
+
+// the host picks the workgroup size with overrides 0 and 1, which are compiled in when naga translates the kernel
+@id(0) override local_size_x: u32 = 8;
+@id(1) override local_size_y: u32 = 8;
+
+@compute @workgroup_size(local_size_x, local_size_y)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    let j = id.y;
+    // the last workgroups in each dimension can run past the edge of the result
+    if (i >= pc.ar || j >= pc.bc) {
+        return;
+    }
+    var sum = 0.0;
+    for (var k = 0u; k < pc.acbr; k++) {
+        sum += a[(i * pc.acbr) + k] * b[(k * pc.bc) + j];
+    }
+    c[(i * pc.bc) + j] = sum;
+}
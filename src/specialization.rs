@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use ash::vk;
+
+/// The type of a specialization constant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SpecializationType {
+    Bool,
+    Int,
+    Uint,
+    Float,
+    Int64,
+    Uint64,
+    Double,
+}
+
+impl SpecializationType {
+    /// Bytes the value takes in the specialization data, where a bool is a `VkBool32`.
+    pub fn size(self) -> usize {
+        match self {
+            SpecializationType::Bool | SpecializationType::Int | SpecializationType::Uint | SpecializationType::Float => 4,
+            SpecializationType::Int64 | SpecializationType::Uint64 | SpecializationType::Double => 8,
+        }
+    }
+}
+
+impl fmt::Display for SpecializationType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SpecializationType::Bool => "bool",
+            SpecializationType::Int => "int",
+            SpecializationType::Uint => "uint",
+            SpecializationType::Float => "float",
+            SpecializationType::Int64 => "int64_t",
+            SpecializationType::Uint64 => "uint64_t",
+            SpecializationType::Double => "double",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A specialization constant's value, typed so it can be checked against the shader's declaration.
+///
+/// Values are equal when their types and bits are, so a NaN equals itself and `0.0` differs
+/// from `-0.0`, which lets them be part of the [`crate::compile::CompileOptions`] cache key.
+#[derive(Clone, Copy, Debug)]
+pub enum SpecializationValue {
+    Bool(bool),
+    Int(i32),
+    Uint(u32),
+    Float(f32),
+    Int64(i64),
+    Uint64(u64),
+    Double(f64),
+}
+
+impl SpecializationValue {
+    pub fn ty(self) -> SpecializationType {
+        match self {
+            SpecializationValue::Bool(_) => SpecializationType::Bool,
+            SpecializationValue::Int(_) => SpecializationType::Int,
+            SpecializationValue::Uint(_) => SpecializationType::Uint,
+            SpecializationValue::Float(_) => SpecializationType::Float,
+            SpecializationValue::Int64(_) => SpecializationType::Int64,
+            SpecializationValue::Uint64(_) => SpecializationType::Uint64,
+            SpecializationValue::Double(_) => SpecializationType::Double,
+        }
+    }
+
+    /// The value as a workgroup size, for the integer types that can hold one.
+    pub fn as_u32(self) -> Option<u32> {
+        match self {
+            SpecializationValue::Int(value) => u32::try_from(value).ok(),
+            SpecializationValue::Uint(value) => Some(value),
+            SpecializationValue::Int64(value) => u32::try_from(value).ok(),
+            SpecializationValue::Uint64(value) => u32::try_from(value).ok(),
+            _ => None,
+        }
+    }
+
+    /// The value as naga takes a WGSL pipeline constant, which it converts to the override's type.
+    #[cfg(feature = "wgsl")]
+    pub(crate) fn as_f64(self) -> f64 {
+        match self {
+            SpecializationValue::Bool(value) => f64::from(u8::from(value)),
+            SpecializationValue::Int(value) => f64::from(value),
+            SpecializationValue::Uint(value) => f64::from(value),
+            SpecializationValue::Float(value) => f64::from(value),
+            SpecializationValue::Int64(value) => value as f64,
+            SpecializationValue::Uint64(value) => value as f64,
+            SpecializationValue::Double(value) => value,
+        }
+    }
+
+    fn bits(self) -> u64 {
+        match self {
+            SpecializationValue::Bool(value) => u64::from(value),
+            SpecializationValue::Int(value) => value as u32 as u64,
+            SpecializationValue::Uint(value) => u64::from(value),
+            SpecializationValue::Float(value) => u64::from(value.to_bits()),
+            SpecializationValue::Int64(value) => value as u64,
+            SpecializationValue::Uint64(value) => value,
+            SpecializationValue::Double(value) => value.to_bits(),
+        }
+    }
+
+    fn write_bytes(self, data: &mut Vec<u8>) {
+        match self {
+            SpecializationValue::Bool(value) => data.extend_from_slice(&vk::Bool32::from(value).to_ne_bytes()),
+            SpecializationValue::Int(value) => data.extend_from_slice(&value.to_ne_bytes()),
+            SpecializationValue::Uint(value) => data.extend_from_slice(&value.to_ne_bytes()),
+            SpecializationValue::Float(value) => data.extend_from_slice(&value.to_ne_bytes()),
+            SpecializationValue::Int64(value) => data.extend_from_slice(&value.to_ne_bytes()),
+            SpecializationValue::Uint64(value) => data.extend_from_slice(&value.to_ne_bytes()),
+            SpecializationValue::Double(value) => data.extend_from_slice(&value.to_ne_bytes()),
+        }
+    }
+}
+
+impl PartialEq for SpecializationValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.ty() == other.ty() && self.bits() == other.bits()
+    }
+}
+
+impl Eq for SpecializationValue {}
+
+impl Hash for SpecializationValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.ty(), self.bits()).hash(state);
+    }
+}
+
+impl fmt::Display for SpecializationValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpecializationValue::Bool(value) => write!(f, "{}", value),
+            SpecializationValue::Int(value) => write!(f, "{}", value),
+            SpecializationValue::Uint(value) => write!(f, "{}u", value),
+            SpecializationValue::Float(value) => write!(f, "{:?}", value),
+            SpecializationValue::Int64(value) => write!(f, "{}l", value),
+            SpecializationValue::Uint64(value) => write!(f, "{}ul", value),
+            SpecializationValue::Double(value) => write!(f, "{:?}lf", value),
+        }
+    }
+}
+
+macro_rules! impl_from_value {
+    ($($host:ty => $variant:ident),*) => {
+        $(impl From<$host> for SpecializationValue {
+            fn from(value: $host) -> Self {
+                SpecializationValue::$variant(value)
+            }
+        })*
+    };
+}
+
+impl_from_value!(bool => Bool, i32 => Int, u32 => Uint, f32 => Float, i64 => Int64, u64 => Uint64, f64 => Double);
+
+/// Values the host sets for a pipeline's specialization constants, by the ID a shader gives them
+/// with `constant_id` or `local_size_x_id`. Constants that aren't set keep the shader's default.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SpecializationConstants {
+    values: BTreeMap<u32, SpecializationValue>,
+}
+
+impl SpecializationConstants {
+    pub fn new() -> SpecializationConstants {
+        SpecializationConstants::default()
+    }
+
+    pub fn set(mut self, id: u32, value: impl Into<SpecializationValue>) -> Self {
+        self.insert(id, value);
+        self
+    }
+
+    pub fn insert(&mut self, id: u32, value: impl Into<SpecializationValue>) -> Option<SpecializationValue> {
+        self.values.insert(id, value.into())
+    }
+
+    pub fn get(&self, id: u32) -> Option<SpecializationValue> {
+        self.values.get(&id).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The constants in ID order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, SpecializationValue)> + '_ {
+        self.values.iter().map(|(id, value)| (*id, *value))
+    }
+
+    /// The map entries and data of a `VkSpecializationInfo`, with the values packed in ID order.
+    pub(crate) fn map_entries_and_data(&self) -> (Vec<vk::SpecializationMapEntry>, Vec<u8>) {
+        let mut data = Vec::new();
+        let map_entries = self.iter()
+            .map(|(constant_id, value)| {
+                let offset = data.len() as u32;
+                value.write_bytes(&mut data);
+                vk::SpecializationMapEntry { constant_id, offset, size: value.ty().size() }
+            })
+            .collect();
+        (map_entries, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_packed_in_id_order() {
+        let constants = SpecializationConstants::new()
+            .set(7, 2.5f32)
+            .set(0, 64u32)
+            .set(3, -1i32);
+        let (map_entries, data) = constants.map_entries_and_data();
+        let entries = map_entries.iter()
+            .map(|entry| (entry.constant_id, entry.offset, entry.size))
+            .collect::<Vec<_>>();
+        assert_eq!(entries, [(0, 0, 4), (3, 4, 4), (7, 8, 4)]);
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&64u32.to_ne_bytes());
+        expected.extend_from_slice(&(-1i32).to_ne_bytes());
+        expected.extend_from_slice(&2.5f32.to_ne_bytes());
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn bools_are_vk_bool32() {
+        let constants = SpecializationConstants::new().set(0, true).set(1, false);
+        let (map_entries, data) = constants.map_entries_and_data();
+        assert_eq!(map_entries.iter().map(|entry| (entry.offset, entry.size)).collect::<Vec<_>>(), [(0, 4), (4, 4)]);
+        assert_eq!(data, [vk::TRUE.to_ne_bytes(), vk::FALSE.to_ne_bytes()].concat());
+    }
+
+    #[test]
+    fn wide_values_take_eight_bytes() {
+        let constants = SpecializationConstants::new()
+            .set(0, 1u32)
+            .set(1, u64::MAX - 1)
+            .set(2, -2i64)
+            .set(3, 0.25f64);
+        let (map_entries, data) = constants.map_entries_and_data();
+        let entries = map_entries.iter()
+            .map(|entry| (entry.constant_id, entry.offset, entry.size))
+            .collect::<Vec<_>>();
+        // Values are packed without padding, which specialization data doesn't need
+        assert_eq!(entries, [(0, 0, 4), (1, 4, 8), (2, 12, 8), (3, 20, 8)]);
+        assert_eq!(data.len(), 28);
+        assert_eq!(data[4..12], (u64::MAX - 1).to_ne_bytes());
+        assert_eq!(data[12..20], (-2i64).to_ne_bytes());
+        assert_eq!(data[20..28], 0.25f64.to_ne_bytes());
+    }
+
+    #[test]
+    fn no_constants_pack_to_nothing() {
+        let (map_entries, data) = SpecializationConstants::new().map_entries_and_data();
+        assert!(map_entries.is_empty());
+        assert!(data.is_empty());
+    }
+}
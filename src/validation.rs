@@ -0,0 +1,201 @@
+use std::ffi::{c_void, CStr};
+use std::sync::{Arc, Mutex};
+use ash::vk;
+use crate::error::{Error, Result};
+
+/// Environment variable that turns validation on (`1`, `true`) or off (`0`, `false`),
+/// overriding both the `validation` cargo feature and [`crate::context::VkCtxBuilder::validation`].
+pub const VALIDATION_ENV_VAR: &str = "RSCOMPUTE_VALIDATION";
+
+pub const VALIDATION_LAYER_NAME: &CStr = c"VK_LAYER_KHRONOS_validation";
+
+/// Whether to enable validation when neither the builder nor the environment says otherwise.
+pub(crate) const VALIDATION_DEFAULT: bool = cfg!(feature = "validation");
+
+/// Parses [`VALIDATION_ENV_VAR`], returning `None` when it is unset or not a boolean.
+pub(crate) fn validation_from_env() -> Option<bool> {
+    parse_validation(std::env::var(VALIDATION_ENV_VAR).ok().as_deref())
+}
+
+fn parse_validation(value: Option<&str>) -> Option<bool> {
+    match value?.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "on" | "yes" => Some(true),
+        "0" | "false" | "off" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+pub(crate) fn layer_available(entry: &ash::Entry, layer_name: &CStr) -> Result<bool> {
+    let layer_properties = unsafe { entry.enumerate_instance_layer_properties() }
+        .map_err(Error::Instance)?;
+    Ok(layer_properties.iter()
+        .any(|layer_property| layer_property.layer_name_as_c_str() == Ok(layer_name)))
+}
+
+/// A message reported by the validation layer through `VK_EXT_debug_utils`.
+#[derive(Clone, Debug)]
+pub struct ValidationMessage {
+    pub severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    pub message_id_name: Option<String>,
+    pub message: String,
+}
+
+impl ValidationMessage {
+    pub fn is_error(&self) -> bool {
+        self.severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+    }
+}
+
+/// Validation messages collected since the context was created, so tests can check
+/// that a dispatch did not produce any validation errors.
+#[derive(Debug)]
+pub struct ValidationMessages {
+    collect: bool,
+    messages: Mutex<Vec<ValidationMessage>>,
+}
+
+impl ValidationMessages {
+    pub(crate) fn new(collect: bool) -> ValidationMessages {
+        ValidationMessages {
+            collect,
+            messages: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Removes and returns every collected message.
+    pub fn take(&self) -> Vec<ValidationMessage> {
+        self.messages.lock()
+            .map(|mut messages| std::mem::take(&mut *messages))
+            .unwrap_or_default()
+    }
+
+    /// Returns the collected error messages, leaving them collected.
+    pub fn errors(&self) -> Vec<ValidationMessage> {
+        self.messages.lock()
+            .map(|messages| messages.iter().filter(|message| message.is_error()).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.messages.lock()
+            .map(|messages| messages.iter().any(|message| message.is_error()))
+            .unwrap_or(false)
+    }
+
+    fn record(&self, message: ValidationMessage) {
+        if !self.collect {
+            return;
+        }
+        if let Ok(mut messages) = self.messages.lock() {
+            messages.push(message);
+        }
+    }
+}
+
+/// The debug-utils messenger, which forwards validation messages to `tracing`.
+pub struct DebugMessenger {
+    loader: ash::ext::debug_utils::Instance,
+    messenger: vk::DebugUtilsMessengerEXT,
+    messages: Arc<ValidationMessages>,
+}
+
+impl DebugMessenger {
+    /// Builds the messenger create info, which is also chained into the instance create info
+    /// so messages from `vkCreateInstance` and `vkDestroyInstance` are reported too.
+    pub(crate) fn create_info(messages: &Arc<ValidationMessages>) -> vk::DebugUtilsMessengerCreateInfoEXT<'static> {
+        vk::DebugUtilsMessengerCreateInfoEXT::default()
+            .message_severity(
+                vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
+                    | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE)
+            .message_type(
+                vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                    | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                    | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE)
+            .pfn_user_callback(Some(debug_callback))
+            .user_data(Arc::as_ptr(messages) as *mut c_void)
+    }
+
+    pub(crate) fn new(entry: &ash::Entry, instance: &ash::Instance, messages: Arc<ValidationMessages>) -> Result<DebugMessenger> {
+        let loader = ash::ext::debug_utils::Instance::new(entry, instance);
+        let messenger = unsafe {
+            loader.create_debug_utils_messenger(&DebugMessenger::create_info(&messages), None)
+        }.map_err(Error::Instance)?;
+        Ok(DebugMessenger { loader, messenger, messages })
+    }
+
+    pub fn messages(&self) -> &Arc<ValidationMessages> {
+        &self.messages
+    }
+}
+
+impl Drop for DebugMessenger {
+    fn drop(&mut self) {
+        unsafe { self.loader.destroy_debug_utils_messenger(self.messenger, None) };
+    }
+}
+
+unsafe extern "system" fn debug_callback(
+    severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+    user_data: *mut c_void,
+) -> vk::Bool32 {
+    if callback_data.is_null() {
+        return vk::FALSE;
+    }
+    let callback_data = &*callback_data;
+    let message_id_name = callback_data.message_id_name_as_c_str()
+        .map(|name| name.to_string_lossy().into_owned());
+    let message = callback_data.message_as_c_str()
+        .map(|message| message.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let id = message_id_name.as_deref().unwrap_or("");
+
+    if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        tracing::error!(?message_type, id, "{}", message);
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+        tracing::warn!(?message_type, id, "{}", message);
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+        tracing::debug!(?message_type, id, "{}", message);
+    } else {
+        tracing::trace!(?message_type, id, "{}", message);
+    }
+
+    if !user_data.is_null() {
+        let messages = &*(user_data as *const ValidationMessages);
+        messages.record(ValidationMessage {
+            severity,
+            message_type,
+            message_id_name,
+            message,
+        });
+    }
+
+    // Returning false lets the call that triggered the message continue
+    vk::FALSE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn on_and_off_spellings() {
+        for value in ["1", "true", "on", "yes", "TRUE", " Yes\n"] {
+            assert_eq!(parse_validation(Some(value)), Some(true), "{:?}", value);
+        }
+        for value in ["0", "false", "off", "no", "False", "\tOFF "] {
+            assert_eq!(parse_validation(Some(value)), Some(false), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn unset_or_invalid_values_defer() {
+        for value in [None, Some(""), Some("  "), Some("2"), Some("enabled"), Some("truee"), Some("o n")] {
+            assert_eq!(parse_validation(value), None, "{:?}", value);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use naga::back::spv;
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use naga::{Scalar, ShaderStage, SourceLocation, TypeInner};
+use crate::compile::{CompileOptions, CompiledShader, Diagnostic, Severity, SpirvVersion, TargetEnv};
+use crate::error::{Error, Result};
+use crate::specialization::SpecializationType;
+
+/// Translates a WGSL compute shader to SPIR-V with naga.
+///
+/// `@group(n) @binding(m)` becomes descriptor set `n` binding `m`, and `var<immediate>` the
+/// push constant block. Of the compile options only the target environment and SPIR-V
+/// version apply; WGSL has no preprocessor. naga resolves `override` declarations while
+/// translating rather than emitting specialization constants, so those set in
+/// [`CompileOptions::wgsl_overrides`] are compiled in and the others keep their defaults.
+/// Parse and validation errors are returned as [`Error::ShaderCompile`] with the span naga
+/// reports.
+pub(crate) fn translate(code: &str, file_name: &str, entry_point: &str, options: &CompileOptions) -> Result<CompiledShader> {
+    let compile_error = |location: Option<SourceLocation>, message: String| Error::ShaderCompile {
+        file_name: file_name.to_string(),
+        error_count: 1,
+        diagnostics: vec![diagnostic(code, file_name, location, message)],
+    };
+
+    let module = naga::front::wgsl::parse_str(code).map_err(|error| {
+        let message = error.notes().fold(error.message().to_string(), |message, note| format!("{}; note: {}", message, note));
+        compile_error(error.location(code), message)
+    })?;
+    if !module.entry_points.iter().any(|entry| entry.stage == ShaderStage::Compute && entry.name == entry_point) {
+        let compute_entry_points = module.entry_points.iter()
+            .filter(|entry| entry.stage == ShaderStage::Compute)
+            .map(|entry| entry.name.as_str())
+            .collect::<Vec<&str>>();
+        return Err(compile_error(None, format!("there is no @compute entry point named {}, there is {}",
+            entry_point, if compute_entry_points.is_empty() { "none".to_string() } else { compute_entry_points.join(", ") })));
+    }
+
+    let info = Validator::new(ValidationFlags::all(), Capabilities::IMMEDIATES | Capabilities::FLOAT64)
+        .validate(&module)
+        .map_err(|error| compile_error(error.location(code), error_chain(&error)))?;
+    let mut constants = naga::back::PipelineConstants::default();
+    for (id, value) in options.wgsl_overrides.iter() {
+        let declared = module.overrides.iter()
+            .map(|(_, declared)| declared)
+            .find(|declared| declared.id.map(u32::from) == Some(id))
+            .ok_or_else(|| compile_error(None, format!("override {} is set, but the shader declares none with @id({})", id, id)))?;
+        let name = declared.name.as_deref().unwrap_or_default();
+        let TypeInner::Scalar(scalar) = module.types[declared.ty].inner else {
+            return Err(compile_error(None, format!("override {} {} isn't a scalar", id, name)));
+        };
+        match override_type(scalar) {
+            Some(ty) if ty == value.ty() => {}
+            Some(ty) => return Err(compile_error(None, format!("override {} {} is a {}, but was set to the {} {}",
+                id, name, ty, value.ty(), value))),
+            None => return Err(compile_error(None, format!("override {} {} has a type the host can't set", id, name))),
+        }
+        constants.insert(id.to_string(), value.as_f64());
+    }
+    let (module, info) = naga::back::pipeline_constants::process_overrides(
+        &module, &info, Some((ShaderStage::Compute, entry_point)), &constants)
+        .map_err(|error| compile_error(None, error_chain(&error)))?;
+
+    let spv_options = spv::Options {
+        lang_version: lang_version(options),
+        // Names are always kept, as shaderc keeps them, so layout errors can name bindings.
+        // The source isn't: an OpSource for WGSL is newer than the grammar reflection parses.
+        flags: spv::WriterFlags::DEBUG,
+        // Resources without an entry in the (empty) binding map keep their group and binding
+        fake_missing_bindings: true,
+        ..Default::default()
+    };
+    let pipeline_options = spv::PipelineOptions {
+        shader_stage: ShaderStage::Compute,
+        entry_point: entry_point.to_string(),
+    };
+    let spirv = spv::write_vec(&module, &info, &spv_options, Some(&pipeline_options))
+        .map_err(|error| compile_error(None, error_chain(&error)))?;
+    tracing::debug!(words = spirv.len(), "translated WGSL shader");
+    Ok(CompiledShader { spirv, warnings: Vec::new(), includes: Vec::new() })
+}
+
+/// The specialization type of an override's scalar type, `None` for the ones without one such as `f16`.
+fn override_type(scalar: Scalar) -> Option<SpecializationType> {
+    match scalar {
+        Scalar::BOOL => Some(SpecializationType::Bool),
+        Scalar::I32 => Some(SpecializationType::Int),
+        Scalar::U32 => Some(SpecializationType::Uint),
+        Scalar::F32 => Some(SpecializationType::Float),
+        Scalar::I64 => Some(SpecializationType::Int64),
+        Scalar::U64 => Some(SpecializationType::Uint64),
+        Scalar::F64 => Some(SpecializationType::Double),
+        _ => None,
+    }
+}
+
+/// The SPIR-V version to write, the one `spirv_version` sets or else the one `target_env` implies.
+fn lang_version(options: &CompileOptions) -> (u8, u8) {
+    let version = options.spirv_version.unwrap_or(match options.target_env {
+        TargetEnv::Vulkan1_0 => SpirvVersion::V1_0,
+        TargetEnv::Vulkan1_1 => SpirvVersion::V1_3,
+        TargetEnv::Vulkan1_2 => SpirvVersion::V1_5,
+        TargetEnv::Vulkan1_3 => SpirvVersion::V1_6,
+    });
+    match version {
+        SpirvVersion::V1_0 => (1, 0),
+        SpirvVersion::V1_1 => (1, 1),
+        SpirvVersion::V1_2 => (1, 2),
+        SpirvVersion::V1_3 => (1, 3),
+        SpirvVersion::V1_4 => (1, 4),
+        SpirvVersion::V1_5 => (1, 5),
+        SpirvVersion::V1_6 => (1, 6),
+    }
+}
+
+/// The error's message followed by those of its sources, which is where naga says what is wrong.
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(next) = source {
+        message = format!("{}: {}", message, next);
+        source = next.source();
+    }
+    message
+}
+
+/// An error at `location`, whose byte offsets are turned into the character columns
+/// [`Diagnostic`] uses.
+fn diagnostic(code: &str, file_name: &str, location: Option<SourceLocation>, message: String) -> Diagnostic {
+    let mut diagnostic = Diagnostic::new(Severity::Error, file_name, message);
+    let Some(location) = location else {
+        return diagnostic;
+    };
+    diagnostic.line = Some(location.line_number);
+    let Some(source_line) = code.lines().nth(location.line_number as usize - 1) else {
+        return diagnostic;
+    };
+    // A span running past the end of the line is cut at it
+    let start = (location.line_position as usize - 1).min(source_line.len());
+    let end = (start + location.length as usize).min(source_line.len());
+    if source_line.is_char_boundary(start) && source_line.is_char_boundary(end) {
+        diagnostic.span = Some((source_line[..start].chars().count() + 1, source_line[start..end].chars().count().max(1)));
+    }
+    diagnostic.source_line = Some(source_line.to_string());
+    diagnostic
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reflect::ShaderReflection;
+    use super::*;
+
+    const KERNEL: &str = "
+        @id(0) override size: u32 = 4;
+        @id(1) override scale: f32 = 1.0;
+        @group(0) @binding(0) var<storage, read_write> data: array<f32>;
+
+        @compute @workgroup_size(size)
+        fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+            data[id.x] *= scale;
+        }
+    ";
+
+    fn local_size(options: &CompileOptions) -> Result<Option<[u32; 3]>> {
+        let compiled = translate(KERNEL, "kernel.wgsl", "main", options)?;
+        Ok(ShaderReflection::reflect(&compiled.spirv)?.entry_point("main").unwrap().local_size)
+    }
+
+    fn error_message(error: Error) -> String {
+        match error {
+            Error::ShaderCompile { diagnostics, .. } => diagnostics[0].message.clone(),
+            error => panic!("expected a compile error, got {}", error),
+        }
+    }
+
+    #[test]
+    fn overrides_keep_their_defaults() {
+        assert_eq!(local_size(&CompileOptions::new()).unwrap(), Some([4, 1, 1]));
+    }
+
+    #[test]
+    fn overrides_are_compiled_in() {
+        let options = CompileOptions::new().wgsl_override(0, 64u32).wgsl_override(1, 2.0f32);
+        assert_eq!(local_size(&options).unwrap(), Some([64, 1, 1]));
+    }
+
+    #[test]
+    fn demo_workgroup_size_is_overridden() {
+        let options = CompileOptions::new().wgsl_override(0, 16u32).wgsl_override(1, 4u32);
+        let compiled = translate(include_str!("shaders/matrix_multiplication.wgsl"), "matrix_multiplication.wgsl", "main", &options).unwrap();
+        let reflection = ShaderReflection::reflect(&compiled.spirv).unwrap();
+        assert_eq!(reflection.entry_point("main").unwrap().local_size, Some([16, 4, 1]));
+    }
+
+    #[test]
+    fn undeclared_override_is_an_error() {
+        let error = local_size(&CompileOptions::new().wgsl_override(2, 1u32)).unwrap_err();
+        assert_eq!(error_message(error), "override 2 is set, but the shader declares none with @id(2)");
+    }
+
+    #[test]
+    fn mistyped_override_is_an_error() {
+        let error = local_size(&CompileOptions::new().wgsl_override(0, 64i32)).unwrap_err();
+        assert_eq!(error_message(error), "override 0 size is a uint, but was set to the int 64");
+    }
+}
@@ -0,0 +1,6 @@
+//! Structs `#[derive(GpuMappedMemory)]` must reject, with the error pointing at the field.
+
+#[test]
+fn gpu_mapped_errors() {
+    trybuild::TestCases::new().compile_fail("tests/ui/gpu_mapped/*.rs");
+}
@@ -0,0 +1,6 @@
+//! Kernels `#[compute_kernel]` must reject, with the error pointing at the Rust code.
+
+#[test]
+fn kernel_errors() {
+    trybuild::TestCases::new().compile_fail("tests/ui/kernel/*.rs");
+}
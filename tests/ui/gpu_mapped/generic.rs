@@ -0,0 +1,8 @@
+use rscompute::GpuMappedMemory;
+
+#[derive(GpuMappedMemory)]
+struct Values<T> {
+    values: Vec<T>,
+}
+
+fn main() {}
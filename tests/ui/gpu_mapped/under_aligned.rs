@@ -0,0 +1,9 @@
+use rscompute::GpuMappedMemory;
+
+#[derive(GpuMappedMemory)]
+struct Values {
+    flags: [u16; 2],
+    values: Vec<f32>,
+}
+
+fn main() {}
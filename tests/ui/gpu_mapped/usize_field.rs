@@ -0,0 +1,9 @@
+use rscompute::GpuMappedMemory;
+
+#[derive(GpuMappedMemory)]
+struct Values {
+    count: usize,
+    values: Vec<f32>,
+}
+
+fn main() {}
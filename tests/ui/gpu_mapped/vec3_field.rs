@@ -0,0 +1,8 @@
+use rscompute::GpuMappedMemory;
+
+#[derive(GpuMappedMemory)]
+struct Particles {
+    positions: Vec<[f32; 3]>,
+}
+
+fn main() {}
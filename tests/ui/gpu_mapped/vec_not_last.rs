@@ -0,0 +1,9 @@
+use rscompute::GpuMappedMemory;
+
+#[derive(GpuMappedMemory)]
+struct Values {
+    values: Vec<f32>,
+    count: u32,
+}
+
+fn main() {}
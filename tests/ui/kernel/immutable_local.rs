@@ -0,0 +1,10 @@
+use rscompute::compute_kernel;
+
+#[compute_kernel(workgroup_size = [64])]
+fn kernel(values: &mut [u32]) {
+    let count = 0;
+    count += 1;
+    values[0] = count;
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use rscompute::compute_kernel;
+
+#[compute_kernel(workgroup_size = [64])]
+fn kernel(input: &[f32], output: &mut [f32], scale: u32) {
+    output[global_id.x] = input[global_id.x] * scale;
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use rscompute::compute_kernel;
+
+#[compute_kernel(workgroup_size = [64])]
+fn kernel(values: &mut [f32]) {
+    println!("{}", values[0]);
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use rscompute::compute_kernel;
+
+#[compute_kernel(workgroup_size = [64])]
+fn kernel(values: &mut [u32], n: i32) {
+    for i in 0u32..n {
+        values[i] = i;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use rscompute::compute_kernel;
+
+#[compute_kernel]
+fn kernel(values: &mut [f32]) {
+    values[0] = 1.0;
+}
+
+fn main() {}
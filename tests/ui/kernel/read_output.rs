@@ -0,0 +1,9 @@
+use rscompute::compute_kernel;
+
+#[compute_kernel(workgroup_size = [64])]
+fn accumulate(input: &[f32], output: &mut [f32]) {
+    let i = global_id.x;
+    output[i] += input[i];
+}
+
+fn main() {}
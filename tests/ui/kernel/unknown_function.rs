@@ -0,0 +1,8 @@
+use rscompute::compute_kernel;
+
+#[compute_kernel(workgroup_size = [64])]
+fn kernel(values: &mut [f32]) {
+    values[0] = values[1].atan2(values[2]);
+}
+
+fn main() {}
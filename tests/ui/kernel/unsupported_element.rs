@@ -0,0 +1,8 @@
+use rscompute::compute_kernel;
+
+#[compute_kernel(workgroup_size = [64])]
+fn kernel(values: &mut [f64]) {
+    values[0] = 1.0;
+}
+
+fn main() {}
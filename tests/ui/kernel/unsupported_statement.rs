@@ -0,0 +1,10 @@
+use rscompute::compute_kernel;
+
+#[compute_kernel(workgroup_size = [64])]
+fn kernel(values: &mut [u32]) {
+    loop {
+        values[0] = 1;
+    }
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use rscompute::compute_kernel;
+
+#[compute_kernel(workgroup_size = [64])]
+fn kernel(input: &[f32], output: &mut [f32]) {
+    input[global_id.x] = output[global_id.x];
+}
+
+fn main() {}